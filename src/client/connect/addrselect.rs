@@ -0,0 +1,331 @@
+//! Destination address selection, from section 6 of RFC 6724.
+
+use std::cmp::{self, Ordering};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, UdpSocket};
+
+/// Sort `addrs` by the rules of RFC 6724, with the source addresses and
+/// prefix lengths of this machine.
+pub(super) fn sort(addrs: &mut Vec<SocketAddr>) {
+    let prefixes = interface_prefixes();
+    sort_by(addrs, |dst| {
+        source_for(dst).map(|ip| (ip, prefixes.get(&ip).cloned().unwrap_or(DEFAULT_PREFIX_LEN)))
+    });
+}
+
+// Taken for sources whose prefix length isn't known, as is common for
+// IPv6 subnets.
+const DEFAULT_PREFIX_LEN: u32 = 64;
+
+/// Sort `addrs` by the rules of RFC 6724, using `source` to find the source
+/// address and its prefix length that would be used to reach each one.
+///
+/// The sort is stable. If no source address is found for any of `addrs`,
+/// they are left as they are.
+pub(super) fn sort_by<F>(addrs: &mut Vec<SocketAddr>, mut source: F)
+where
+    F: FnMut(&SocketAddr) -> Option<(IpAddr, u32)>,
+{
+    let mut keyed = addrs
+        .iter()
+        .map(|dst| (*dst, source(dst)))
+        .collect::<Vec<_>>();
+    if keyed.iter().all(|&(_, src)| src.is_none()) {
+        return;
+    }
+    keyed.sort_by(|a, b| compare(a, b));
+    addrs.clear();
+    addrs.extend(keyed.into_iter().map(|(dst, _)| dst));
+}
+
+/// Find the source address the system would use to reach `dst`.
+///
+/// Connecting a UDP socket only looks up the route, without sending
+/// anything.
+pub(super) fn source_for(dst: &SocketAddr) -> Option<IpAddr> {
+    let unspecified: IpAddr = if dst.is_ipv6() {
+        Ipv6Addr::from([0; 16]).into()
+    } else {
+        [0, 0, 0, 0].into()
+    };
+    UdpSocket::bind(SocketAddr::new(unspecified, 0))
+        .and_then(|sock| sock.connect(dst).and_then(|()| sock.local_addr()))
+        .ok()
+        .map(|addr| addr.ip())
+}
+
+/// Find the prefix length of each address of this machine's interfaces.
+#[cfg(unix)]
+pub(super) fn interface_prefixes() -> HashMap<IpAddr, u32> {
+    use std::ptr;
+
+    let mut prefixes = HashMap::new();
+    let mut ifaddrs = ptr::null_mut();
+    if unsafe { ::libc::getifaddrs(&mut ifaddrs) } != 0 {
+        return prefixes;
+    }
+    let mut next = ifaddrs;
+    while let Some(ifa) = unsafe { next.as_ref() } {
+        next = ifa.ifa_next;
+        if ifa.ifa_addr.is_null() || ifa.ifa_netmask.is_null() {
+            continue;
+        }
+        let family = unsafe { (*ifa.ifa_addr).sa_family } as ::libc::c_int;
+        let (addr, len) = match family {
+            ::libc::AF_INET => unsafe {
+                let addr = &*(ifa.ifa_addr as *const ::libc::sockaddr_in);
+                let mask = &*(ifa.ifa_netmask as *const ::libc::sockaddr_in);
+                (IpAddr::from(Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr))), mask.sin_addr.s_addr.count_ones())
+            },
+            ::libc::AF_INET6 => unsafe {
+                let addr = &*(ifa.ifa_addr as *const ::libc::sockaddr_in6);
+                let mask = &*(ifa.ifa_netmask as *const ::libc::sockaddr_in6);
+                (IpAddr::from(addr.sin6_addr.s6_addr), mask.sin6_addr.s6_addr.iter().map(|b| b.count_ones()).sum())
+            },
+            _ => continue,
+        };
+        prefixes.insert(addr, len);
+    }
+    unsafe { ::libc::freeifaddrs(ifaddrs) };
+    prefixes
+}
+
+#[cfg(not(unix))]
+pub(super) fn interface_prefixes() -> HashMap<IpAddr, u32> {
+    HashMap::new()
+}
+
+type Keyed = (SocketAddr, Option<(IpAddr, u32)>);
+
+// Whether `a` should be tried before `b`, with their source addresses.
+fn compare(&(ref da, sa): &Keyed, &(ref db, sb): &Keyed) -> Ordering {
+    let (da, db) = (da.ip(), db.ip());
+
+    // Rule 1: Avoid unusable destinations.
+    let ((sa, prefix_a), (sb, prefix_b)) = match (sa, sb) {
+        (Some(sa), Some(sb)) => (sa, sb),
+        (Some(_), None) => return Ordering::Less,
+        (None, Some(_)) => return Ordering::Greater,
+        (None, None) => return Ordering::Equal,
+    };
+
+    // Rule 2: Prefer matching scope.
+    let (scope_da, scope_db) = (scope(&da), scope(&db));
+    let prefer = (scope_da == scope(&sa), scope_db == scope(&sb));
+    if prefer.0 != prefer.1 {
+        return if prefer.0 { Ordering::Less } else { Ordering::Greater };
+    }
+
+    // Rules 3 and 4 need address flags and mobility, which aren't known.
+
+    // Rule 5: Prefer matching label.
+    let ((prec_da, label_da), (prec_db, label_db)) = (policy(&da), policy(&db));
+    let prefer = (label_da == policy(&sa).1, label_db == policy(&sb).1);
+    if prefer.0 != prefer.1 {
+        return if prefer.0 { Ordering::Less } else { Ordering::Greater };
+    }
+
+    // Rule 6: Prefer higher precedence.
+    if prec_da != prec_db {
+        return prec_db.cmp(&prec_da);
+    }
+
+    // Rule 7 needs to know about tunnels, which isn't known.
+
+    // Rule 8: Prefer smaller scope.
+    if scope_da != scope_db {
+        return scope_da.cmp(&scope_db);
+    }
+
+    // Rule 9: Use longest matching prefix, up to the prefix length of
+    // the source. Like other implementations, only for IPv6, so that
+    // round robin DNS still works for IPv4.
+    if let (IpAddr::V6(da), IpAddr::V6(sa), IpAddr::V6(db), IpAddr::V6(sb)) = (da, sa, db, sb) {
+        let len_a = cmp::min(common_prefix_len(&da, &sa), prefix_a);
+        let len_b = cmp::min(common_prefix_len(&db, &sb), prefix_b);
+        if len_a != len_b {
+            return len_b.cmp(&len_a);
+        }
+    }
+
+    // Rule 10: Otherwise, leave the order unchanged.
+    Ordering::Equal
+}
+
+// The precedence and label of an address, from the default policy table
+// in section 2.1. IPv4 addresses are looked up as IPv4-mapped addresses.
+fn policy(ip: &IpAddr) -> (u8, u8) {
+    // Longest prefixes first, so the first match is the longest.
+    const TABLE: &[([u16; 8], u32, u8, u8)] = &[
+        ([0, 0, 0, 0, 0, 0, 0, 1], 128, 50, 0),
+        ([0, 0, 0, 0, 0, 0xffff, 0, 0], 96, 35, 4),
+        ([0, 0, 0, 0, 0, 0, 0, 0], 96, 1, 3),
+        ([0x2001, 0, 0, 0, 0, 0, 0, 0], 32, 5, 5),
+        ([0x2002, 0, 0, 0, 0, 0, 0, 0], 16, 30, 2),
+        ([0x3ffe, 0, 0, 0, 0, 0, 0, 0], 16, 1, 12),
+        ([0xfec0, 0, 0, 0, 0, 0, 0, 0], 10, 1, 11),
+        ([0xfc00, 0, 0, 0, 0, 0, 0, 0], 7, 3, 13),
+        ([0, 0, 0, 0, 0, 0, 0, 0], 0, 40, 1),
+    ];
+
+    let ip = match *ip {
+        IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+        IpAddr::V6(ip) => ip,
+    };
+    for &(prefix, len, precedence, label) in TABLE {
+        if common_prefix_len(&ip, &Ipv6Addr::from(prefix)) >= len {
+            return (precedence, label);
+        }
+    }
+    unreachable!("::/0 matches every address")
+}
+
+// The scope of an address, as in section 3.1, where link-local is 0x2,
+// site-local is 0x5 and global is 0xe.
+fn scope(ip: &IpAddr) -> u8 {
+    match *ip {
+        IpAddr::V4(ip) => {
+            if ip.is_loopback() || ip.is_link_local() {
+                0x2
+            } else {
+                0xe
+            }
+        },
+        IpAddr::V6(ip) => {
+            let first = ip.segments()[0];
+            if first & 0xff00 == 0xff00 {
+                (first & 0x000f) as u8
+            } else if ip.is_loopback() || first & 0xffc0 == 0xfe80 {
+                0x2
+            } else if first & 0xffc0 == 0xfec0 {
+                0x5
+            } else {
+                0xe
+            }
+        },
+    }
+}
+
+// How many leading bits `a` and `b` share.
+fn common_prefix_len(a: &Ipv6Addr, b: &Ipv6Addr) -> u32 {
+    let mut len = 0;
+    for (a, b) in a.octets().iter().zip(b.octets().iter()) {
+        let same = (a ^ b).leading_zeros();
+        len += same;
+        if same < 8 {
+            break;
+        }
+    }
+    len
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::net::{IpAddr, SocketAddr};
+
+    use super::{common_prefix_len, interface_prefixes, sort_by, source_for};
+
+    fn addr(s: &str) -> SocketAddr {
+        SocketAddr::new(s.parse().unwrap(), 80)
+    }
+
+    // Sort `dsts` with the sources given for each destination, which have
+    // a prefix length of 64 unless one follows a `/`.
+    fn sorted(dsts: &[&str], sources: &[(&str, &str)]) -> Vec<SocketAddr> {
+        let sources = sources
+            .iter()
+            .map(|&(dst, src)| {
+                let mut src = src.split('/');
+                let ip = src.next().unwrap().parse::<IpAddr>().unwrap();
+                let len = src.next().map(|len| len.parse().unwrap()).unwrap_or(64);
+                (addr(dst), (ip, len))
+            })
+            .collect::<HashMap<_, _>>();
+        let mut addrs = dsts.iter().map(|s| addr(s)).collect::<Vec<_>>();
+        sort_by(&mut addrs, |dst| sources.get(dst).cloned());
+        addrs
+    }
+
+    #[test]
+    fn test_prefers_matching_label() {
+        // With only a unique local source, the unique local address wins.
+        let addrs = sorted(&["2001:db8::1", "fd00::1"], &[
+            ("2001:db8::1", "fd00::2"),
+            ("fd00::1", "fd00::2"),
+        ]);
+        assert_eq!(addrs, vec![addr("fd00::1"), addr("2001:db8::1")]);
+
+        // With a global source, the global address wins.
+        let addrs = sorted(&["fd00::1", "2001:db8::1"], &[
+            ("2001:db8::1", "2001:db8::2"),
+            ("fd00::1", "2001:db8::2"),
+        ]);
+        assert_eq!(addrs, vec![addr("2001:db8::1"), addr("fd00::1")]);
+    }
+
+    #[test]
+    fn test_prefers_usable_and_higher_precedence() {
+        // Native IPv6 has a higher precedence than IPv4.
+        let addrs = sorted(&["198.51.100.1", "2001:db8::1"], &[
+            ("198.51.100.1", "192.0.2.2"),
+            ("2001:db8::1", "2001:db8::2"),
+        ]);
+        assert_eq!(addrs, vec![addr("2001:db8::1"), addr("198.51.100.1")]);
+
+        // Without an IPv6 route, IPv4 goes first.
+        let addrs = sorted(&["2001:db8::1", "198.51.100.1"], &[
+            ("198.51.100.1", "192.0.2.2"),
+        ]);
+        assert_eq!(addrs, vec![addr("198.51.100.1"), addr("2001:db8::1")]);
+    }
+
+    #[test]
+    fn test_keeps_order_of_ties_and_without_sources() {
+        let dsts = ["198.51.100.3", "198.51.100.1", "198.51.100.2"];
+        let addrs = sorted(&dsts, &[
+            ("198.51.100.1", "192.0.2.2"),
+            ("198.51.100.2", "192.0.2.2"),
+            ("198.51.100.3", "192.0.2.2"),
+        ]);
+        assert_eq!(addrs, dsts.iter().map(|s| addr(s)).collect::<Vec<_>>());
+
+        let dsts = ["2001:db8::1", "fd00::1", "198.51.100.1"];
+        let addrs = sorted(&dsts, &[]);
+        assert_eq!(addrs, dsts.iter().map(|s| addr(s)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_longest_matching_prefix() {
+        let addrs = sorted(&["2001:db8:1::1", "2001:db8:2::1"], &[
+            ("2001:db8:1::1", "2001:db8:2::2"),
+            ("2001:db8:2::1", "2001:db8:2::2"),
+        ]);
+        assert_eq!(addrs, vec![addr("2001:db8:2::1"), addr("2001:db8:1::1")]);
+        // Past the prefix of the source, matching bits don't count.
+        let dsts = ["2001:db8:1::1", "2001:db8:1:0:8000::1"];
+        let addrs = sorted(&dsts, &[
+            ("2001:db8:1::1", "2001:db8:1::2"),
+            ("2001:db8:1:0:8000::1", "2001:db8:1:0:8000::2"),
+        ]);
+        assert_eq!(addrs, vec![addr(dsts[0]), addr(dsts[1])]);
+        let addrs = sorted(&dsts, &[
+            ("2001:db8:1::1", "2001:db8:1:0:8000::2/80"),
+            ("2001:db8:1:0:8000::1", "2001:db8:1:0:8000::2/80"),
+        ]);
+        assert_eq!(addrs, vec![addr(dsts[1]), addr(dsts[0])]);
+        assert_eq!(common_prefix_len(&"::".parse().unwrap(), &"::".parse().unwrap()), 128);
+        assert_eq!(common_prefix_len(&"8000::".parse().unwrap(), &"::".parse().unwrap()), 0);
+    }
+
+    #[test]
+    fn test_source_for_loopback() {
+        assert_eq!(source_for(&addr("127.0.0.1")), Some([127, 0, 0, 1].into()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_interface_prefixes_loopback() {
+        assert_eq!(interface_prefixes().get(&[127, 0, 0, 1].into()), Some(&8));
+    }
+}
@@ -0,0 +1,2114 @@
+//! The `Resolve` trait, support types, and some basic implementations.
+//!
+//! This module contains:
+//!
+//! - A [`GaiResolver`](GaiResolver) that is the default resolver for the
+//!   `HttpConnector`.
+//! - A [`CachingResolver`](CachingResolver) that remembers the addresses
+//!   resolved by another resolver.
+//! - A [`UdpResolver`](UdpResolver) that queries DNS servers without
+//!   blocking a thread.
+//! - The [`Resolve`](Resolve) trait and related types to build a custom
+//!   resolver for use with the `HttpConnector`.
+use std::collections::{HashMap, HashSet};
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::iter::FromIterator;
+use std::net::{
+    IpAddr, Ipv4Addr, Ipv6Addr,
+    SocketAddr, ToSocketAddrs,
+    SocketAddrV4, SocketAddrV6,
+};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use futures::future::{self, Executor, ExecuteError};
+use futures::sync::oneshot;
+use futures_cpupool::{Builder as CpuPoolBuilder, CpuPool};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio_io::io::{read_exact, write_all};
+use tokio_timer::Delay;
+
+use super::idna;
+
+pub(super) use self::sealed::GaiTask;
+
+/// Resolve a hostname to a set of IP addresses.
+///
+/// # Example
+///
+/// A resolver that resolves every name to localhost, such as for tests:
+///
+/// ```
+/// # extern crate futures;
+/// # extern crate hyper;
+/// # fn main() {
+/// use std::io;
+/// use std::net::SocketAddr;
+///
+/// use futures::future::{self, FutureResult};
+/// use hyper::client::HttpConnector;
+/// use hyper::client::connect::dns::{IpAddrs, Name, Resolve};
+///
+/// struct LocalResolver;
+///
+/// impl Resolve for LocalResolver {
+///     type Future = FutureResult<IpAddrs, io::Error>;
+///
+///     fn resolve(&self, name: Name) -> Self::Future {
+///         let addr = SocketAddr::from(([127, 0, 0, 1], name.port()));
+///         future::ok(Some(addr).into_iter().collect())
+///     }
+/// }
+///
+/// let connector = HttpConnector::new_with_resolver(LocalResolver, None);
+/// # drop(connector);
+/// # }
+/// ```
+pub trait Resolve {
+    /// A Future of the resolved set of addresses.
+    type Future: Future<Item=IpAddrs, Error=io::Error>;
+    /// Resolve a hostname.
+    fn resolve(&self, name: Name) -> Self::Future;
+
+    /// Look up the `SRV` records of a name, such as
+    /// `_http._tcp.example.com`.
+    ///
+    /// This is only used by an `HttpConnector` with
+    /// [`set_srv_service`](super::HttpConnector::set_srv_service). Finding
+    /// no records isn't an error, and by default no records are found.
+    fn resolve_srv(&self, _name: &str) -> SrvFuture {
+        SrvFuture::new(future::ok(Vec::new()))
+    }
+}
+
+/// A domain name to resolve into IP addresses.
+///
+/// An internationalized name may be given in Unicode. The resolvers in this
+/// module look it up in its ASCII form, as with [`to_ascii`](Name::to_ascii).
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Name {
+    host: String,
+    port: u16,
+}
+
+/// A service location from a DNS `SRV` record, as in RFC 2782.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SrvRecord {
+    priority: u16,
+    weight: u16,
+    port: u16,
+    target: String,
+}
+
+/// A future of the `SRV` records of a name.
+#[must_use = "futures do nothing unless polled"]
+pub struct SrvFuture {
+    inner: Box<Future<Item=Vec<SrvRecord>, Error=io::Error> + Send>,
+}
+
+/// The order to try the addresses of a hostname in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddrOrder {
+    /// Keep the order returned by the resolver.
+    Preserve,
+    /// Shuffle the addresses for every connect.
+    Shuffle,
+    /// Start from the next address of the hostname for every connect.
+    RoundRobin,
+}
+
+/// Which address families to connect to, and in what order.
+///
+/// Orderings are stable, so addresses of the same family keep the order
+/// they were resolved in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpFamilyPreference {
+    /// Keep the order returned by the resolver.
+    System,
+    /// Try IPv4 addresses before IPv6 addresses.
+    Ipv4First,
+    /// Try IPv6 addresses before IPv4 addresses.
+    Ipv6First,
+    /// Only connect to IPv4 addresses.
+    Ipv4Only,
+    /// Only connect to IPv6 addresses.
+    Ipv6Only,
+}
+
+/// A resolver using blocking `getaddrinfo` calls in a threadpool.
+///
+/// Clones share the same threadpool, so one `GaiResolver` can be given to
+/// several connectors with `HttpConnector::new_with_resolver`.
+#[derive(Clone)]
+pub struct GaiResolver {
+    executor: GaiExecutor,
+    flags: GaiFlags,
+    owns_pool: bool,
+}
+
+// Hints for `getaddrinfo`, beyond what `ToSocketAddrs` passes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct GaiFlags {
+    addr_config: bool,
+    v4_mapped: bool,
+}
+
+/// A future to resolve a name returned by `GaiResolver`.
+///
+/// Dropping it cancels the lookup, if a thread hasn't started it yet.
+pub struct GaiFuture {
+    rx: oneshot::SpawnHandle<IpAddrs, io::Error>,
+}
+
+/// A resolver that caches the addresses resolved by an inner resolver.
+///
+/// Resolved addresses are kept for a fixed time-to-live, after which the
+/// name is resolved again. Failures can be kept too, for a shorter time.
+/// Clones of a `CachingResolver` share the cache.
+#[derive(Clone, Debug)]
+pub struct CachingResolver<R> {
+    inner: R,
+    cache: Cache,
+    negative: Option<NegativeCache>,
+}
+
+/// A future returned by `CachingResolver`.
+#[must_use = "futures do nothing unless polled"]
+pub struct CachingFuture<F> {
+    state: CachingState<F>,
+}
+
+enum CachingState<F> {
+    Cached(Option<IpAddrs>),
+    Failed(Option<io::Error>),
+    Resolving(F, Name, Cache, Option<NegativeCache>),
+}
+
+/// A resolver that sends DNS queries over UDP from the event loop.
+///
+/// Unlike `GaiResolver`, this doesn't block a thread, and dropping its
+/// future cancels the lookup. It only asks the configured servers for `A`
+/// and `AAAA` records, so it doesn't read `/etc/hosts`. A truncated
+/// answer is asked for again over TCP. Servers are asked in order, moving
+/// to the next one when a server fails or doesn't answer in time.
+#[derive(Clone, Debug)]
+pub struct UdpResolver {
+    servers: Arc<Vec<SocketAddr>>,
+    timeout: Duration,
+}
+
+/// A future returned by `UdpResolver`.
+#[must_use = "futures do nothing unless polled"]
+pub struct UdpFuture {
+    last_error: Option<io::Error>,
+    name: Name,
+    next_server: usize,
+    query: Option<UdpQuery>,
+    ready: Option<io::Result<IpAddrs>>,
+    resolver: UdpResolver,
+}
+
+// The `A` and `AAAA` queries sent to one server.
+struct UdpQuery {
+    answers: [Option<Vec<IpAddr>>; 2],
+    deadline: Delay,
+    packets: [Vec<u8>; 2],
+    sent: usize,
+    server: SocketAddr,
+    sock: UdpSocket,
+    // A query asked again over TCP, after a truncated answer.
+    tcp: [Option<TcpQuery>; 2],
+}
+
+type TcpQuery = Box<Future<Item=Vec<u8>, Error=io::Error> + Send>;
+
+// How a message relates to a query that was sent.
+#[derive(Debug, PartialEq)]
+enum Response {
+    // The addresses answering the query, which may be none.
+    Answer(Vec<IpAddr>),
+    // An answer too long for UDP.
+    Truncated,
+    // Not an answer to the query, such as a spoofed or stale one.
+    Unrelated,
+}
+
+const QTYPE_A: u16 = 1;
+const QTYPE_AAAA: u16 = 28;
+
+impl Name {
+    /// Create a `Name` from a hostname and the port to connect to.
+    pub fn new(host: String, port: u16) -> Name {
+        Name {
+            host,
+            port,
+        }
+    }
+
+    /// View the hostname as a string slice.
+    pub fn as_str(&self) -> &str {
+        &self.host
+    }
+
+    /// Get the port that will be used for the resolved addresses.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Convert the hostname to its ASCII form, with each label holding
+    /// non-ASCII characters in its `xn--` Punycode form, as in IDNA.
+    ///
+    /// Labels are lowercased before they're encoded, so that
+    /// `B\u{fc}cher.example` becomes `xn--bcher-kva.example`, and ASCII labels
+    /// are kept as they are. A label that can't be converted, such as one
+    /// holding a space, is an `io::ErrorKind::InvalidInput` error naming it.
+    pub fn to_ascii(&self) -> io::Result<Name> {
+        Ok(Name::new(ascii_host(&self.host)?, self.port))
+    }
+}
+
+fn ascii_host(host: &str) -> io::Result<String> {
+    idna::to_ascii(host).map_err(|label| io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("hostname label {:?} has no ASCII form", label),
+    ))
+}
+
+impl SrvRecord {
+    /// Create an `SrvRecord` for the service at `port` of `target`.
+    pub fn new(priority: u16, weight: u16, port: u16, target: String) -> SrvRecord {
+        SrvRecord {
+            priority,
+            weight,
+            port,
+            target,
+        }
+    }
+
+    /// Get the priority of the target, where lower values are tried first.
+    pub fn priority(&self) -> u16 {
+        self.priority
+    }
+
+    /// Get the weight of the target, relative to others of the same
+    /// priority.
+    pub fn weight(&self) -> u16 {
+        self.weight
+    }
+
+    /// Get the port of the service on the target.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// View the hostname of the target.
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+}
+
+impl SrvFuture {
+    /// Wrap a future of `SRV` records.
+    pub fn new<F>(future: F) -> SrvFuture
+    where
+        F: Future<Item=Vec<SrvRecord>, Error=io::Error> + Send + 'static,
+    {
+        SrvFuture {
+            inner: Box::new(future),
+        }
+    }
+}
+
+impl Future for SrvFuture {
+    type Item = Vec<SrvRecord>;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.inner.poll()
+    }
+}
+
+impl fmt::Debug for SrvFuture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("SrvFuture")
+    }
+}
+
+// Order `records` to be tried, by priority and then randomly by weight,
+// as in RFC 2782.
+pub(super) fn order_srv(mut records: Vec<SrvRecord>, rng: &mut ShuffleRng) -> Vec<SrvRecord> {
+    // Records of no weight go first, so they're only rarely picked first.
+    records.sort_by_key(|record| (record.priority, record.weight != 0));
+    let mut ordered = Vec::with_capacity(records.len());
+    while !records.is_empty() {
+        let priority = records[0].priority;
+        let end = records.iter().position(|r| r.priority != priority).unwrap_or(records.len());
+        let mut group = records.drain(..end).collect::<Vec<_>>();
+        while !group.is_empty() {
+            let total = group.iter().map(|r| r.weight as usize).sum::<usize>();
+            let pick = rng.next_below(total + 1);
+            let mut sum = 0;
+            let i = group
+                .iter()
+                .position(|r| {
+                    sum += r.weight as usize;
+                    sum >= pick
+                })
+                .expect("pick is at most the total weight");
+            ordered.push(group.remove(i));
+        }
+    }
+    ordered
+}
+
+impl Default for AddrOrder {
+    fn default() -> AddrOrder {
+        AddrOrder::Preserve
+    }
+}
+
+impl Default for IpFamilyPreference {
+    fn default() -> IpFamilyPreference {
+        IpFamilyPreference::System
+    }
+}
+
+impl GaiResolver {
+    /// Construct a new `GaiResolver`.
+    ///
+    /// Takes number of DNS worker threads.
+    pub fn new(threads: usize) -> Self {
+        GaiResolver {
+            owns_pool: true,
+            ..GaiResolver::new_with_executor(dns_pool(threads))
+        }
+    }
+
+    /// Construct a new `GaiResolver` that runs lookups on `pool`.
+    ///
+    /// The pool can be shared with other resolvers, and with anything else.
+    pub fn new_with_pool(pool: CpuPool) -> Self {
+        GaiResolver::new_with_executor(pool)
+    }
+
+    /// Construct a new `GaiResolver` with a shared thread pool executor.
+    ///
+    /// Takes an executor to run blocking `getaddrinfo` tasks on.
+    pub fn new_with_executor<E: 'static>(executor: E) -> Self
+    where
+        E: Executor<GaiTask> + Send + Sync,
+    {
+        GaiResolver {
+            executor: GaiExecutor(Arc::new(executor)),
+            flags: GaiFlags::default(),
+            owns_pool: false,
+        }
+    }
+
+    /// Replace the threadpool with one of `threads` threads.
+    ///
+    /// Lookups already started on the old pool complete normally, and its
+    /// threads exit once no clone of the resolver uses it. Clones made
+    /// before keep using the old pool.
+    ///
+    /// This fails for resolvers constructed with a pool or executor, since
+    /// the resolver doesn't own them, and if `threads` is `0`.
+    pub fn resize(&mut self, threads: usize) -> io::Result<()> {
+        if !self.owns_pool {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "can't resize a threadpool the resolver was given",
+            ));
+        }
+        if threads == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "a threadpool needs at least one thread",
+            ));
+        }
+        self.executor = GaiExecutor(Arc::new(dns_pool(threads)));
+        Ok(())
+    }
+
+    /// Set whether `AI_ADDRCONFIG` is passed to `getaddrinfo`.
+    ///
+    /// If enabled, only addresses of families the host has a non-loopback
+    /// address for are returned, so a host without IPv6 connectivity gets
+    /// no IPv6 addresses. This is only supported on Unix, and ignored
+    /// elsewhere.
+    ///
+    /// Default is `false`.
+    pub fn set_addr_config(&mut self, enabled: bool) {
+        self.flags.addr_config = enabled;
+    }
+
+    /// Set whether only IPv6 addresses are asked for, with `AI_V4MAPPED`.
+    ///
+    /// If enabled, a host with only IPv4 addresses resolves to them as
+    /// IPv4-mapped IPv6 addresses, for connecting over IPv6 sockets only.
+    /// This is only supported on Unix, and ignored elsewhere.
+    ///
+    /// Default is `false`.
+    pub fn set_v4_mapped(&mut self, enabled: bool) {
+        self.flags.v4_mapped = enabled;
+    }
+
+    /// Resolve `host`, giving its addresses with `port`.
+    ///
+    /// This is the same as `resolve`, without needing a `Name`.
+    pub fn lookup(&self, host: &str, port: u16) -> GaiFuture {
+        self.resolve(Name::new(host.to_owned(), port))
+    }
+}
+
+impl Resolve for GaiResolver {
+    type Future = GaiFuture;
+
+    fn resolve(&self, name: Name) -> Self::Future {
+        let blocking = Work::new(name.host, name.port, self.flags);
+        let rx = oneshot::spawn(blocking, &self.executor);
+        GaiFuture {
+            rx,
+        }
+    }
+}
+
+impl fmt::Debug for GaiResolver {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("GaiResolver")
+    }
+}
+
+impl Future for GaiFuture {
+    type Item = IpAddrs;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.rx.poll()
+    }
+}
+
+impl fmt::Debug for GaiFuture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("GaiFuture")
+    }
+}
+
+impl<R: Resolve> CachingResolver<R> {
+    /// Construct a new `CachingResolver` wrapping `inner`.
+    ///
+    /// Addresses are cached for `ttl`, and at most `capacity` names are
+    /// kept at once. When full, expired entries are evicted first, and
+    /// then the least recently used entry.
+    pub fn new(inner: R, ttl: Duration, capacity: usize) -> Self {
+        CachingResolver {
+            inner,
+            cache: Cache::new(ttl, capacity),
+            negative: None,
+        }
+    }
+
+    /// Set how long to remember that resolving a name failed, so it fails
+    /// again at once instead of asking the inner resolver.
+    ///
+    /// A failure that timed out isn't remembered. Names that resolve
+    /// after their failure expired are cached as usual. Clones made
+    /// before this is set don't share the failures.
+    ///
+    /// Default is `None`, resolving again after every failure.
+    pub fn set_dns_negative_ttl(&mut self, ttl: Option<Duration>) {
+        self.negative = ttl.map(NegativeCache::new);
+    }
+}
+
+impl<R: Resolve> Resolve for CachingResolver<R> {
+    type Future = CachingFuture<R::Future>;
+
+    fn resolve(&self, name: Name) -> Self::Future {
+        let failed = self.negative.as_ref().and_then(|negative| negative.get(&name));
+        let state = match (failed, self.cache.get(&name)) {
+            (Some(err), _) => CachingState::Failed(Some(err)),
+            (None, Some(addrs)) => CachingState::Cached(Some(addrs)),
+            (None, None) => CachingState::Resolving(
+                self.inner.resolve(name.clone()),
+                name,
+                self.cache.clone(),
+                self.negative.clone(),
+            ),
+        };
+        CachingFuture {
+            state,
+        }
+    }
+
+    fn resolve_srv(&self, name: &str) -> SrvFuture {
+        self.inner.resolve_srv(name)
+    }
+}
+
+impl<F> Future for CachingFuture<F>
+where
+    F: Future<Item=IpAddrs, Error=io::Error>,
+{
+    type Item = IpAddrs;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.state {
+            CachingState::Cached(ref mut addrs) => {
+                Ok(Async::Ready(addrs.take().expect("polled more than once")))
+            },
+            CachingState::Failed(ref mut err) => Err(err.take().expect("polled more than once")),
+            CachingState::Resolving(ref mut future, ref name, ref cache, ref negative) => {
+                let addrs = match future.poll() {
+                    Ok(Async::Ready(addrs)) => addrs,
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        if let Some(ref negative) = *negative {
+                            if err.kind() != io::ErrorKind::TimedOut {
+                                negative.insert(name.clone(), &err);
+                            }
+                        }
+                        return Err(err);
+                    },
+                };
+                cache.insert(name.clone(), &addrs);
+                Ok(Async::Ready(addrs))
+            },
+        }
+    }
+}
+
+impl<F> fmt::Debug for CachingFuture<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("CachingFuture")
+    }
+}
+
+/// A shared map of names to recently resolved addresses.
+#[derive(Clone)]
+pub(super) struct Cache {
+    capacity: usize,
+    entries: Arc<Mutex<HashMap<Name, CacheEntry>>>,
+    ttl: Duration,
+}
+
+struct CacheEntry {
+    addrs: IpAddrs,
+    expires_at: Instant,
+    last_used: Instant,
+}
+
+impl Cache {
+    pub(super) fn new(ttl: Duration, capacity: usize) -> Cache {
+        Cache {
+            capacity,
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            ttl,
+        }
+    }
+
+    pub(super) fn get(&self, name: &Name) -> Option<IpAddrs> {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        let expired = match entries.get_mut(name) {
+            Some(entry) => if entry.expires_at > now {
+                trace!("dns cache hit for {:?}", name);
+                entry.last_used = now;
+                return Some(entry.addrs.clone());
+            } else {
+                true
+            },
+            None => false,
+        };
+        if expired {
+            trace!("dns cache entry expired for {:?}", name);
+            entries.remove(name);
+        }
+        None
+    }
+
+    pub(super) fn insert(&self, name: Name, addrs: &IpAddrs) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(&name) {
+            entries.retain(|_, entry| entry.expires_at > now);
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(&name) {
+            let oldest = entries
+                .iter()
+                .min_by_key(|&(_, entry)| entry.last_used)
+                .map(|(name, _)| name.clone());
+            if let Some(oldest) = oldest {
+                entries.remove(&oldest);
+            }
+        }
+        entries.insert(name, CacheEntry {
+            addrs: addrs.clone(),
+            expires_at: now + self.ttl,
+            last_used: now,
+        });
+    }
+
+    pub(super) fn remove(&self, name: &Name) {
+        if self.entries.lock().unwrap().remove(name).is_some() {
+            trace!("dns cache entry invalidated for {:?}", name);
+        }
+    }
+}
+
+impl fmt::Debug for Cache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Cache")
+            .field("capacity", &self.capacity)
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+/// A shared map of names to recent errors resolving them.
+#[derive(Clone)]
+pub(super) struct NegativeCache {
+    entries: Arc<Mutex<HashMap<Name, NegativeEntry>>>,
+    ttl: Duration,
+}
+
+// The error isn't `Clone`, so its parts are kept instead.
+struct NegativeEntry {
+    expires_at: Instant,
+    kind: io::ErrorKind,
+    message: String,
+}
+
+impl NegativeCache {
+    pub(super) fn new(ttl: Duration) -> NegativeCache {
+        NegativeCache {
+            entries: Arc::new(Mutex::new(HashMap::new())),
+            ttl,
+        }
+    }
+
+    pub(super) fn get(&self, name: &Name) -> Option<io::Error> {
+        let mut entries = self.entries.lock().unwrap();
+        let expired = match entries.get(name) {
+            Some(entry) => if entry.expires_at > Instant::now() {
+                trace!("negative dns cache hit for {:?}", name);
+                return Some(io::Error::new(
+                    entry.kind,
+                    format!("{} (cached by the negative DNS cache)", entry.message),
+                ));
+            } else {
+                true
+            },
+            None => false,
+        };
+        if expired {
+            entries.remove(name);
+        }
+        None
+    }
+
+    pub(super) fn insert(&self, name: Name, err: &io::Error) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+        // Bogus names could pile up, so only keep unexpired ones.
+        entries.retain(|_, entry| entry.expires_at > now);
+        entries.insert(name, NegativeEntry {
+            expires_at: now + self.ttl,
+            kind: err.kind(),
+            message: err.to_string(),
+        });
+    }
+}
+
+impl fmt::Debug for NegativeCache {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("NegativeCache")
+            .field("ttl", &self.ttl)
+            .finish()
+    }
+}
+
+impl UdpResolver {
+    /// Construct a new `UdpResolver` that asks `servers`, in order.
+    pub fn new(servers: Vec<SocketAddr>) -> UdpResolver {
+        UdpResolver {
+            servers: Arc::new(servers),
+            timeout: Duration::from_secs(2),
+        }
+    }
+
+    /// Set how long to wait for a server to answer, before asking the
+    /// next one.
+    ///
+    /// Default is 2 seconds.
+    #[inline]
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+}
+
+impl Resolve for UdpResolver {
+    type Future = UdpFuture;
+
+    fn resolve(&self, mut name: Name) -> Self::Future {
+        let mut invalid = None;
+        if !idna::is_ascii(&name.host) {
+            match name.to_ascii() {
+                Ok(ascii) => name = ascii,
+                Err(err) => invalid = Some(Err(err)),
+            }
+        }
+        let ready = if invalid.is_some() {
+            invalid
+        } else if let Some(addrs) = IpAddrs::try_parse(&name.host, name.port) {
+            Some(Ok(addrs))
+        } else if self.servers.is_empty() {
+            Some(Err(io::Error::new(io::ErrorKind::InvalidInput, "no DNS servers to ask")))
+        } else if encode_query(0, &name.host, QTYPE_A).is_none() {
+            Some(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} is not a valid DNS name", name.host),
+            )))
+        } else {
+            None
+        };
+        UdpFuture {
+            last_error: None,
+            name,
+            next_server: 0,
+            query: None,
+            ready,
+            resolver: self.clone(),
+        }
+    }
+}
+
+impl Future for UdpFuture {
+    type Item = IpAddrs;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(ready) = self.ready.take() {
+            return ready.map(Async::Ready);
+        }
+        loop {
+            if self.query.is_none() {
+                let server = match self.resolver.servers.get(self.next_server) {
+                    Some(&server) => server,
+                    None => return Err(self.last_error.take().expect("a server was asked")),
+                };
+                self.next_server += 1;
+                let timeout = self.resolver.timeout;
+                match UdpQuery::new(&self.name.host, server, timeout) {
+                    Ok(query) => self.query = Some(query),
+                    // Such as binding `[::]` on a host without IPv6.
+                    Err(err) => {
+                        debug!("DNS query for {} to {} failed: {}", self.name.host, server, err);
+                        self.last_error = Some(err);
+                        continue;
+                    },
+                }
+            }
+
+            let err = match self.query.as_mut().expect("query").poll() {
+                Ok(Async::Ready(ips)) => {
+                    if ips.is_empty() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("no addresses found for {}", self.name.host),
+                        ));
+                    }
+                    let port = self.name.port;
+                    return Ok(Async::Ready(ips.into_iter().map(|ip| SocketAddr::new(ip, port)).collect()));
+                },
+                Ok(Async::NotReady) => return Ok(Async::NotReady),
+                Err(err) => err,
+            };
+            debug!("DNS query for {} failed: {}", self.name.host, err);
+            self.last_error = Some(err);
+            self.query = None;
+        }
+    }
+}
+
+impl fmt::Debug for UdpFuture {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("UdpFuture")
+    }
+}
+
+impl UdpQuery {
+    fn new(host: &str, server: SocketAddr, timeout: Duration) -> io::Result<UdpQuery> {
+        // Unpredictable ids make spoofing an answer harder.
+        let ids = [random_u64() as u16, random_u64() as u16];
+        let packets = match (encode_query(ids[0], host, QTYPE_A), encode_query(ids[1], host, QTYPE_AAAA)) {
+            (Some(a), Some(aaaa)) => [a, aaaa],
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} is not a valid DNS name", host),
+            )),
+        };
+        let unspecified: IpAddr = if server.is_ipv6() {
+            Ipv6Addr::from([0; 16]).into()
+        } else {
+            [0, 0, 0, 0].into()
+        };
+        Ok(UdpQuery {
+            answers: [None, None],
+            deadline: Delay::new(Instant::now() + timeout),
+            packets,
+            sent: 0,
+            server,
+            sock: UdpSocket::bind(&SocketAddr::new(unspecified, 0))?,
+            tcp: [None, None],
+        })
+    }
+
+    // Resolves to the `AAAA` answers followed by the `A` answers, which
+    // may both be empty.
+    fn poll(&mut self) -> Poll<Vec<IpAddr>, io::Error> {
+        while self.sent < self.packets.len() {
+            match self.sock.poll_send_to(&self.packets[self.sent], &self.server)? {
+                Async::Ready(_) => self.sent += 1,
+                Async::NotReady => return self.poll_deadline(),
+            }
+        }
+
+        // Without EDNS, answers over UDP are at most 512 bytes.
+        let mut buf = [0; 512];
+        loop {
+            let (n, from) = match self.sock.poll_recv_from(&mut buf)? {
+                Async::Ready(received) => received,
+                Async::NotReady => break,
+            };
+            if from != self.server {
+                continue;
+            }
+            for i in 0..self.packets.len() {
+                if self.answers[i].is_some() || self.tcp[i].is_some() {
+                    continue;
+                }
+                match parse_response(&buf[..n], &self.packets[i])? {
+                    Response::Answer(ips) => self.answers[i] = Some(ips),
+                    Response::Truncated => {
+                        debug!("DNS answer from {} was truncated, asking over TCP", self.server);
+                        self.tcp[i] = Some(tcp_query(self.server, &self.packets[i]));
+                    },
+                    Response::Unrelated => (),
+                }
+            }
+        }
+
+        // Each query over TCP is polled, so one that never answers can't
+        // keep the other one, or the deadline, from being polled.
+        for i in 0..self.packets.len() {
+            let msg = match self.tcp[i] {
+                Some(ref mut query) => match query.poll()? {
+                    Async::Ready(msg) => msg,
+                    Async::NotReady => continue,
+                },
+                None => continue,
+            };
+            self.tcp[i] = None;
+            match parse_response(&msg, &self.packets[i])? {
+                Response::Answer(ips) => self.answers[i] = Some(ips),
+                _ => return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("DNS server {} didn't answer over TCP", self.server),
+                )),
+            }
+        }
+        if let (&Some(ref v4), &Some(ref v6)) = (&self.answers[0], &self.answers[1]) {
+            return Ok(Async::Ready(v6.iter().chain(v4).cloned().collect()));
+        }
+        self.poll_deadline()
+    }
+
+    // Fails once the server has had its time to answer.
+    fn poll_deadline(&mut self) -> Poll<Vec<IpAddr>, io::Error> {
+        match self.deadline.poll() {
+            Ok(Async::Ready(())) => Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                format!("DNS server {} didn't answer in time", self.server),
+            )),
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(e) => Err(io::Error::new(io::ErrorKind::Other, e)),
+        }
+    }
+}
+
+// Ask `packet` over TCP, resolving to the answer.
+fn tcp_query(server: SocketAddr, packet: &[u8]) -> TcpQuery {
+    // Over TCP, each message is prefixed by its length.
+    let mut framed = Vec::with_capacity(packet.len() + 2);
+    framed.extend_from_slice(&[(packet.len() >> 8) as u8, packet.len() as u8]);
+    framed.extend_from_slice(packet);
+    let fut = TcpStream::connect(&server)
+        .and_then(move |sock| write_all(sock, framed))
+        .and_then(|(sock, _)| read_exact(sock, [0; 2]))
+        .and_then(|(sock, len)| read_exact(sock, vec![0; read_u16(&len, 0) as usize]))
+        .map(|(_, msg)| msg);
+    Box::new(fut)
+}
+
+// Get random bits, for query ids.
+fn random_u64() -> u64 {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    // Each `RandomState` hashes with new keys, from a generator seeded by
+    // the operating system, so even an empty hash can't be guessed.
+    RandomState::new().build_hasher().finish()
+}
+
+// Encode a recursive query for `qtype` records of `host`.
+fn encode_query(id: u16, host: &str, qtype: u16) -> Option<Vec<u8>> {
+    let host = if host.ends_with('.') {
+        &host[..host.len() - 1]
+    } else {
+        host
+    };
+    if host.is_empty() || host.len() > 253 {
+        return None;
+    }
+
+    let mut msg = Vec::with_capacity(host.len() + 18);
+    // Header: the id, recursion desired, and one question.
+    msg.extend_from_slice(&[(id >> 8) as u8, id as u8, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0]);
+    for label in host.split('.') {
+        if label.is_empty() || label.len() > 63 || !idna::is_ascii(label) {
+            return None;
+        }
+        msg.push(label.len() as u8);
+        msg.extend_from_slice(label.as_bytes());
+    }
+    // The root label, the type, and the IN class.
+    msg.extend_from_slice(&[0, (qtype >> 8) as u8, qtype as u8, 0, 1]);
+    Some(msg)
+}
+
+// Parse the addresses answering `query`, as encoded by `encode_query`.
+//
+// An answer only counts if it has the id of the query, and repeats its
+// question. A name that doesn't exist is answered with no addresses.
+fn parse_response(msg: &[u8], query: &[u8]) -> io::Result<Response> {
+    let question = &query[12..];
+    let (name, qtail) = question.split_at(question.len() - 4);
+    let qtype = read_u16(qtail, 0);
+    let echoed = msg.get(12..12 + question.len()).map_or(false, |echo| {
+        // Servers may change the case of the name.
+        eq_ignore_case(&echo[..name.len()], name) && echo[name.len()..] == *qtail
+    });
+    if !echoed || msg[..2] != query[..2] || msg[2] & 0x80 == 0 || read_u16(msg, 4) != 1 {
+        return Ok(Response::Unrelated);
+    }
+    if msg[2] & 0x02 != 0 {
+        return Ok(Response::Truncated);
+    }
+    match msg[3] & 0x0f {
+        0 => (),
+        3 => return Ok(Response::Answer(Vec::new())),
+        rcode => return Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("DNS server failed with response code {}", rcode),
+        )),
+    }
+
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed DNS response");
+    let mut pos = 12 + question.len();
+    let mut ips = Vec::new();
+    for _ in 0..read_u16(msg, 6) {
+        pos = skip_name(msg, pos).ok_or_else(malformed)?;
+        if msg.len() < pos + 10 {
+            return Err(malformed());
+        }
+        let rtype = read_u16(msg, pos);
+        let len = read_u16(msg, pos + 8) as usize;
+        pos += 10;
+        let data = msg.get(pos..pos + len).ok_or_else(malformed)?;
+        pos += len;
+        // Other records, such as a CNAME before the addresses, are skipped.
+        match (rtype, len) {
+            (QTYPE_A, 4) if qtype == QTYPE_A => ips.push(IpAddr::from([data[0], data[1], data[2], data[3]])),
+            (QTYPE_AAAA, 16) if qtype == QTYPE_AAAA => {
+                let mut octets = [0; 16];
+                octets.copy_from_slice(data);
+                ips.push(IpAddr::from(octets));
+            },
+            _ => (),
+        }
+    }
+    Ok(Response::Answer(ips))
+}
+
+// Whether two names are the same, ignoring the case of ASCII letters.
+fn eq_ignore_case(a: &[u8], b: &[u8]) -> bool {
+    let lower = |c: u8| if c >= b'A' && c <= b'Z' { c + (b'a' - b'A') } else { c };
+    a.len() == b.len() && a.iter().zip(b).all(|(&x, &y)| lower(x) == lower(y))
+}
+
+// Get the position after the possibly compressed name at `pos`.
+fn skip_name(msg: &[u8], mut pos: usize) -> Option<usize> {
+    loop {
+        let len = match msg.get(pos) {
+            Some(&len) => len as usize,
+            None => return None,
+        };
+        if len == 0 {
+            return Some(pos + 1);
+        }
+        if len & 0xc0 == 0xc0 {
+            return if pos + 2 <= msg.len() { Some(pos + 2) } else { None };
+        }
+        pos += len + 1;
+    }
+}
+
+fn read_u16(msg: &[u8], pos: usize) -> u16 {
+    (msg[pos] as u16) << 8 | msg[pos + 1] as u16
+}
+
+#[cfg(test)]
+thread_local!(pub(super) static WORK_POLLS: ::std::cell::Cell<usize> = ::std::cell::Cell::new(0));
+
+struct Work {
+    host: String,
+    port: u16,
+    flags: GaiFlags,
+}
+
+impl Work {
+    fn new(host: String, port: u16, flags: GaiFlags) -> Work {
+        Work { host: host, port: port, flags: flags }
+    }
+
+    #[cfg(unix)]
+    fn lookup(&self) -> io::Result<Vec<SocketAddr>> {
+        if self.flags == GaiFlags::default() {
+            return (&*self.host, self.port).to_socket_addrs().map(|addrs| addrs.collect());
+        }
+        let mut flags = 0;
+        if self.flags.addr_config {
+            flags |= ::libc::AI_ADDRCONFIG;
+        }
+        let family = if self.flags.v4_mapped {
+            flags |= ::libc::AI_V4MAPPED;
+            ::libc::AF_INET6
+        } else {
+            ::libc::AF_UNSPEC
+        };
+        getaddrinfo(&self.host, self.port, family, flags)
+    }
+
+    #[cfg(not(unix))]
+    fn lookup(&self) -> io::Result<Vec<SocketAddr>> {
+        (&*self.host, self.port).to_socket_addrs().map(|addrs| addrs.collect())
+    }
+}
+
+// Call `getaddrinfo` with the given hints, for TCP addresses.
+#[cfg(unix)]
+fn getaddrinfo(host: &str, port: u16, family: ::libc::c_int, flags: ::libc::c_int) -> io::Result<Vec<SocketAddr>> {
+    use std::ffi::{CStr, CString};
+    use std::{mem, ptr};
+
+    let c_host = CString::new(host)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "hostname contains a nul byte"))?;
+    let mut hints: ::libc::addrinfo = unsafe { mem::zeroed() };
+    hints.ai_family = family;
+    hints.ai_flags = flags;
+    hints.ai_socktype = ::libc::SOCK_STREAM;
+
+    let mut res = ptr::null_mut();
+    let rc = unsafe { ::libc::getaddrinfo(c_host.as_ptr(), ptr::null(), &hints, &mut res) };
+    if rc == ::libc::EAI_SYSTEM {
+        return Err(io::Error::last_os_error());
+    } else if rc != 0 {
+        let msg = unsafe { CStr::from_ptr(::libc::gai_strerror(rc)) };
+        return Err(io::Error::new(io::ErrorKind::Other, msg.to_string_lossy().into_owned()));
+    }
+
+    let mut addrs = Vec::new();
+    let mut next = res;
+    while let Some(ai) = unsafe { next.as_ref() } {
+        next = ai.ai_next;
+        if ai.ai_addr.is_null() {
+            continue;
+        }
+        match ai.ai_family {
+            ::libc::AF_INET => {
+                let addr = unsafe { &*(ai.ai_addr as *const ::libc::sockaddr_in) };
+                let ip = Ipv4Addr::from(u32::from_be(addr.sin_addr.s_addr));
+                addrs.push(SocketAddr::V4(SocketAddrV4::new(ip, port)));
+            },
+            ::libc::AF_INET6 => {
+                let addr = unsafe { &*(ai.ai_addr as *const ::libc::sockaddr_in6) };
+                let ip = Ipv6Addr::from(addr.sin6_addr.s6_addr);
+                addrs.push(SocketAddr::V6(SocketAddrV6::new(ip, port, addr.sin6_flowinfo, addr.sin6_scope_id)));
+            },
+            _ => (),
+        }
+    }
+    unsafe { ::libc::freeaddrinfo(res) };
+    Ok(addrs)
+}
+
+impl Future for Work {
+    type Item = IpAddrs;
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        // Only polled if the `GaiFuture` wasn't dropped before the task
+        // was started, since `oneshot::Execute` checks for cancellation.
+        #[cfg(test)]
+        WORK_POLLS.with(|polls| polls.set(polls.get() + 1));
+        if !idna::is_ascii(&self.host) {
+            self.host = ascii_host(&self.host)?;
+        }
+        // An IP literal, possibly in brackets, isn't a hostname to look up.
+        if let Some(addrs) = IpAddrs::try_parse(&self.host, self.port) {
+            return Ok(Async::Ready(addrs));
+        }
+        debug!("resolving host={:?}, port={:?}", self.host, self.port);
+        self.lookup()
+            .map(|addrs| Async::Ready(IpAddrs::new(addrs)))
+            .map_err(|err| io::Error::new(err.kind(), ResolveError {
+                host: self.host.clone(),
+                port: self.port,
+                source: err,
+            }))
+    }
+}
+
+// The error of `getaddrinfo`, naming what was resolved.
+#[derive(Debug)]
+struct ResolveError {
+    host: String,
+    port: u16,
+    source: io::Error,
+}
+
+impl fmt::Display for ResolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "failed to resolve {}:{}: {}", self.host, self.port, self.source)
+    }
+}
+
+impl StdError for ResolveError {
+    fn description(&self) -> &str {
+        "failed to resolve"
+    }
+
+    fn cause(&self) -> Option<&StdError> {
+        Some(&self.source)
+    }
+}
+
+/// A set of resolved socket addresses to try to connect to.
+#[derive(Clone, Debug)]
+pub struct IpAddrs {
+    addrs: Vec<SocketAddr>,
+}
+
+impl IpAddrs {
+    /// Create an `IpAddrs` from a list of socket addresses.
+    pub fn new(addrs: Vec<SocketAddr>) -> IpAddrs {
+        IpAddrs { addrs: addrs }
+    }
+
+    /// Create an `IpAddrs` from a list of socket addresses, filtered and
+    /// ordered by an address family preference.
+    pub fn with_preference(addrs: Vec<SocketAddr>, pref: IpFamilyPreference) -> IpAddrs {
+        let addrs = match pref {
+            IpFamilyPreference::System => addrs,
+            IpFamilyPreference::Ipv4First | IpFamilyPreference::Ipv6First => {
+                let prefer_v6 = pref == IpFamilyPreference::Ipv6First;
+                let (mut preferred, fallback) = addrs
+                    .into_iter()
+                    .partition::<Vec<_>, _>(|addr| addr.is_ipv6() == prefer_v6);
+                preferred.extend(fallback);
+                preferred
+            },
+            IpFamilyPreference::Ipv4Only => addrs.into_iter().filter(SocketAddr::is_ipv4).collect(),
+            IpFamilyPreference::Ipv6Only => addrs.into_iter().filter(SocketAddr::is_ipv6).collect(),
+        };
+        IpAddrs::new(addrs)
+    }
+
+    pub(super) fn try_parse(host: &str, port: u16) -> Option<IpAddrs> {
+        if let Ok(addr) = host.parse::<Ipv4Addr>() {
+            let addr = SocketAddrV4::new(addr, port);
+            return Some(IpAddrs::new(vec![SocketAddr::V4(addr)]))
+        }
+        // `Uri::host()` keeps the brackets around an IPv6 literal.
+        let bracketed = host.starts_with('[') && host.ends_with(']');
+        let host = if bracketed {
+            &host[1..host.len() - 1]
+        } else {
+            host
+        };
+        // In a URI, the `%` before a zone is itself encoded as `%25`, as
+        // in RFC 6874. A literal outside of a URI has the zone as is.
+        let (host, scope_id) = match host.find('%') {
+            Some(i) => {
+                let zone = &host[i + 1..];
+                let zone = if bracketed && zone.len() > 2 && zone.starts_with("25") {
+                    &zone[2..]
+                } else {
+                    zone
+                };
+                match scope_id(zone) {
+                    Some(scope_id) => (&host[..i], scope_id),
+                    None => return None,
+                }
+            },
+            None => (host, 0),
+        };
+        if let Ok(addr) = host.parse::<Ipv6Addr>() {
+            let addr = SocketAddrV6::new(addr, port, 0, scope_id);
+            return Some(IpAddrs::new(vec![SocketAddr::V6(addr)]))
+        }
+        None
+    }
+
+    /// Remove repeated addresses, keeping the first of each in order.
+    pub fn deduped(self) -> IpAddrs {
+        let mut seen = HashSet::with_capacity(self.addrs.len());
+        let mut addrs = self.addrs;
+        addrs.retain(|addr| seen.insert(*addr));
+        IpAddrs::new(addrs)
+    }
+
+    /// Alternate between IPv6 and IPv4 addresses, starting with the family
+    /// of the first address, and keeping the order within each family.
+    ///
+    /// Once one family runs out, the rest of the other follow.
+    pub fn interleaved(self) -> IpAddrs {
+        let first_v6 = match self.addrs.first() {
+            Some(addr) => addr.is_ipv6(),
+            None => return self,
+        };
+        let (first, second) = self.split_preferred(first_v6);
+        let mut addrs = Vec::with_capacity(first.len() + second.len());
+        let (mut first, mut second) = (first.addrs.into_iter(), second.addrs.into_iter());
+        loop {
+            match (first.next(), second.next()) {
+                (None, None) => break,
+                (a, b) => addrs.extend(a.into_iter().chain(b)),
+            }
+        }
+        IpAddrs::new(addrs)
+    }
+
+    /// Split into the addresses of the preferred family, and the rest,
+    /// keeping their order.
+    pub fn split_preferred(self, prefer_ipv6: bool) -> (IpAddrs, IpAddrs) {
+        let (preferred, fallback) = self.addrs
+            .into_iter()
+            .partition::<Vec<_>, _>(|addr| addr.is_ipv6() == prefer_ipv6);
+
+        (IpAddrs::new(preferred), IpAddrs::new(fallback))
+    }
+
+    // Split by the family of the first address.
+    pub(super) fn split_by_preference(self) -> (IpAddrs, IpAddrs) {
+        let preferring_v6 = self.addrs
+            .first()
+            .map(SocketAddr::is_ipv6)
+            .unwrap_or(false);
+        self.split_preferred(preferring_v6)
+    }
+
+    // Get the index of the next address from `start` that a local address
+    // of the same family was configured for, or of any address if no
+    // local address was configured.
+    pub(super) fn next_filter(&self, start: usize, local_v4: Option<Ipv4Addr>, local_v6: Option<Ipv6Addr>) -> Option<usize> {
+        self.addrs
+            .iter()
+            .skip(start)
+            .position(|addr| match *addr {
+                _ if local_v4.is_none() && local_v6.is_none() => true,
+                SocketAddr::V4(_) => local_v4.is_some(),
+                SocketAddr::V6(_) => local_v6.is_some(),
+            })
+            .map(|i| start + i)
+    }
+
+    /// View the addresses as a slice.
+    pub fn as_slice(&self) -> &[SocketAddr] {
+        &self.addrs
+    }
+
+    /// Get how many addresses there are.
+    pub fn len(&self) -> usize {
+        self.addrs.len()
+    }
+
+    /// Get whether there are no addresses.
+    pub fn is_empty(&self) -> bool {
+        self.addrs.is_empty()
+    }
+
+    // Sort by RFC 6724 destination address selection, keeping the order
+    // if no source addresses could be found.
+    pub(super) fn sort_rfc6724(self) -> IpAddrs {
+        let mut addrs = self.addrs;
+        super::addrselect::sort(&mut addrs);
+        IpAddrs::new(addrs)
+    }
+
+    // Rotates addresses left by `n` among those of the same family, so
+    // the order of families stays as it was.
+    pub(super) fn rotate_within_families(self, n: usize) -> IpAddrs {
+        let mut addrs = self.addrs;
+        let rotated = addrs.clone();
+        for &v6 in &[false, true] {
+            let slots = (0..addrs.len()).filter(|&i| addrs[i].is_ipv6() == v6).collect::<Vec<_>>();
+            for (i, &slot) in slots.iter().enumerate() {
+                addrs[slot] = rotated[slots[(i + n) % slots.len()]];
+            }
+        }
+        IpAddrs::new(addrs)
+    }
+
+    // Shuffles addresses among those of the same family, so the order
+    // of families stays as it was.
+    pub(super) fn shuffle_within_families(self, rng: &mut ShuffleRng) -> IpAddrs {
+        let mut addrs = self.addrs;
+        for &v6 in &[false, true] {
+            let slots = (0..addrs.len()).filter(|&i| addrs[i].is_ipv6() == v6).collect::<Vec<_>>();
+            // Fisher-Yates over only the slots of this family.
+            for i in (1..slots.len()).rev() {
+                let j = rng.next_below(i + 1);
+                addrs.swap(slots[i], slots[j]);
+            }
+        }
+        IpAddrs::new(addrs)
+    }
+}
+
+/// A small xorshift generator, only used to shuffle addresses.
+#[derive(Debug)]
+pub(super) struct ShuffleRng(u64);
+
+impl ShuffleRng {
+    pub(super) fn new(seed: u64) -> ShuffleRng {
+        // xorshift gets stuck at zero.
+        ShuffleRng(if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed })
+    }
+
+    pub(super) fn from_time() -> ShuffleRng {
+        use std::time::{SystemTime, UNIX_EPOCH};
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() ^ (d.subsec_nanos() as u64) << 32)
+            .unwrap_or(0);
+        ShuffleRng::new(nanos)
+    }
+
+    pub(super) fn next_below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        (self.0 % n as u64) as usize
+    }
+}
+
+impl FromIterator<SocketAddr> for IpAddrs {
+    fn from_iter<I: IntoIterator<Item=SocketAddr>>(iter: I) -> IpAddrs {
+        IpAddrs::new(iter.into_iter().collect())
+    }
+}
+
+// Iterating takes addresses from the front, so a clone only holds the
+// addresses not iterated yet. There are only ever a few.
+impl Iterator for IpAddrs {
+    type Item = SocketAddr;
+    #[inline]
+    fn next(&mut self) -> Option<SocketAddr> {
+        if self.addrs.is_empty() {
+            None
+        } else {
+            Some(self.addrs.remove(0))
+        }
+    }
+
+    #[inline]
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.addrs.len(), Some(self.addrs.len()))
+    }
+}
+
+#[derive(Clone)]
+struct GaiExecutor(Arc<Executor<GaiTask> + Send + Sync>);
+
+fn dns_pool(threads: usize) -> CpuPool {
+    CpuPoolBuilder::new()
+        .name_prefix("hyper-dns")
+        .pool_size(threads)
+        .create()
+}
+
+impl Executor<oneshot::Execute<Work>> for GaiExecutor {
+    fn execute(&self, future: oneshot::Execute<Work>) -> Result<(), ExecuteError<oneshot::Execute<Work>>> {
+        self.0.execute(GaiTask { work: future })
+            .map_err(|err| ExecuteError::new(err.kind(), err.into_future().work))
+    }
+}
+
+// The scope id of an IPv6 zone, which is either an index or, on Unix,
+// the name of an interface.
+fn scope_id(zone: &str) -> Option<u32> {
+    if let Ok(index) = zone.parse::<u32>() {
+        return Some(index);
+    }
+    interface_index(zone)
+}
+
+#[cfg(unix)]
+fn interface_index(name: &str) -> Option<u32> {
+    use std::ffi::CString;
+
+    let name = match CString::new(name) {
+        Ok(name) => name,
+        Err(_) => return None,
+    };
+    match unsafe { ::libc::if_nametoindex(name.as_ptr()) } {
+        0 => None,
+        index => Some(index),
+    }
+}
+
+#[cfg(not(unix))]
+fn interface_index(_name: &str) -> Option<u32> {
+    None
+}
+
+// Make this Future unnameable outside of this crate.
+mod sealed {
+    use super::*;
+    // Blocking task to be executed on a thread pool.
+    pub struct GaiTask {
+        pub(super) work: oneshot::Execute<Work>
+    }
+
+    impl fmt::Debug for GaiTask {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.pad("GaiTask")
+        }
+    }
+
+    impl Future for GaiTask {
+        type Item = ();
+        type Error = ();
+
+        fn poll(&mut self) -> Poll<(), ()> {
+            self.work.poll()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ip_addrs_try_parse_ipv6() {
+        let parse = |host| IpAddrs::try_parse(host, 80).map(|addrs| addrs.collect::<Vec<_>>());
+
+        let loopback = SocketAddr::from((Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 80));
+        assert_eq!(parse("[::1]"), Some(vec![loopback]));
+        assert_eq!(parse("::1"), Some(vec![loopback]));
+
+        let link_local = Ipv6Addr::new(0xfe80, 0, 0, 0, 0, 0, 0, 1);
+        assert_eq!(parse("[fe80::1%3]"), Some(vec![SocketAddrV6::new(link_local, 80, 0, 3).into()]));
+        assert_eq!(parse("[fe80::1%253]"), Some(vec![SocketAddrV6::new(link_local, 80, 0, 3).into()]));
+        // Without brackets, the zone isn't encoded.
+        assert_eq!(parse("fe80::1%3"), Some(vec![SocketAddrV6::new(link_local, 80, 0, 3).into()]));
+        assert_eq!(parse("fe80::1%251"), Some(vec![SocketAddrV6::new(link_local, 80, 0, 251).into()]));
+        assert_eq!(parse("fe80::1%no-such-interface"), None);
+        assert_eq!(parse("[fe80::1"), None);
+    }
+
+    #[test]
+    fn test_ip_addrs_from_iter() {
+        let addrs = vec![
+            SocketAddr::from(([127, 0, 0, 1], 80)),
+            SocketAddr::from((Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 80)),
+        ];
+        let collected = addrs.iter().cloned().collect::<IpAddrs>();
+        assert_eq!(collected.as_slice(), &addrs[..]);
+    }
+
+    // Answers `A` queries with a CNAME and 192.0.2.1, `AAAA` queries with
+    // 2001:db8::1, and names starting with `missing` with NXDOMAIN.
+    fn stub_answer(query: &[u8]) -> Vec<u8> {
+        let mut res = query.to_vec();
+        let qtype = read_u16(&res, res.len() - 4);
+        res[2] = 0x81;
+        if res[12..].starts_with(b"\x07missing") {
+            res[3] = 0x83;
+        } else {
+            res[3] = 0x80;
+            if qtype == QTYPE_A {
+                res[7] = 2;
+                res.extend_from_slice(&[0xc0, 0x0c, 0, 5, 0, 1, 0, 0, 0, 60, 0, 2, 0xc0, 0x0c]);
+                res.extend_from_slice(&[0xc0, 0x0c, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 192, 0, 2, 1]);
+            } else {
+                res[7] = 1;
+                res.extend_from_slice(&[0xc0, 0x0c, 0, 28, 0, 1, 0, 0, 0, 60, 0, 16]);
+                res.extend_from_slice(&[0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1]);
+            }
+        }
+        res
+    }
+
+    fn stub_dns_server() -> SocketAddr {
+        use std::net::UdpSocket;
+        use std::thread;
+
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = sock.local_addr().unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            while let Ok((n, from)) = sock.recv_from(&mut buf) {
+                sock.send_to(&stub_answer(&buf[..n]), from).unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn test_udp_resolver_a_and_aaaa() {
+        use tokio::runtime::current_thread::Runtime;
+
+        let resolver = UdpResolver::new(vec![stub_dns_server()]);
+        let mut rt = Runtime::new().unwrap();
+        let addrs = rt.block_on(resolver.resolve(Name::new("example.test.".to_owned(), 80))).expect("resolve");
+        assert_eq!(addrs.collect::<Vec<_>>(), vec![
+            SocketAddr::from((Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 80)),
+            SocketAddr::from(([192, 0, 2, 1], 80)),
+        ]);
+
+        let err = rt.block_on(resolver.resolve(Name::new("missing.test".to_owned(), 80))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_udp_resolver_asks_next_server_after_timeout() {
+        use tokio::runtime::current_thread::Runtime;
+
+        let silent = ::std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut resolver = UdpResolver::new(vec![silent.local_addr().unwrap(), stub_dns_server()]);
+        resolver.set_timeout(Duration::from_millis(50));
+
+        let mut rt = Runtime::new().unwrap();
+        let addrs = rt.block_on(resolver.resolve(Name::new("example.test".to_owned(), 80))).expect("resolve");
+        assert_eq!(addrs.count(), 2);
+
+        let mut resolver = UdpResolver::new(vec![silent.local_addr().unwrap()]);
+        resolver.set_timeout(Duration::from_millis(50));
+        let err = rt.block_on(resolver.resolve(Name::new("example.test".to_owned(), 80))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    // Answers every query over UDP at `addr` as truncated.
+    fn truncating_dns_server(addr: SocketAddr) {
+        use std::net::UdpSocket;
+        use std::thread;
+
+        let sock = UdpSocket::bind(addr).unwrap();
+        thread::spawn(move || {
+            let mut buf = [0; 512];
+            while let Ok((n, from)) = sock.recv_from(&mut buf) {
+                // Only the header and question fit, with TC set.
+                let mut res = buf[..n].to_vec();
+                res[2] = 0x83;
+                sock.send_to(&res, from).unwrap();
+            }
+        });
+    }
+
+    #[test]
+    fn test_udp_resolver_retries_truncated_over_tcp() {
+        use std::io::{Read, Write};
+        use std::net::TcpListener;
+        use std::thread;
+        use tokio::runtime::current_thread::Runtime;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        truncating_dns_server(addr);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut len = [0; 2];
+                stream.read_exact(&mut len).unwrap();
+                let mut query = vec![0; read_u16(&len, 0) as usize];
+                stream.read_exact(&mut query).unwrap();
+                let res = stub_answer(&query);
+                stream.write_all(&[(res.len() >> 8) as u8, res.len() as u8]).unwrap();
+                stream.write_all(&res).unwrap();
+            }
+        });
+
+        let resolver = UdpResolver::new(vec![addr]);
+        let mut rt = Runtime::new().unwrap();
+        let addrs = rt.block_on(resolver.resolve(Name::new("example.test".to_owned(), 80))).expect("resolve");
+        assert_eq!(addrs.collect::<Vec<_>>(), vec![
+            SocketAddr::from((Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1), 80)),
+            SocketAddr::from(([192, 0, 2, 1], 80)),
+        ]);
+    }
+
+    #[test]
+    fn test_udp_resolver_times_out_waiting_on_tcp() {
+        use std::net::TcpListener;
+        use std::thread;
+        use tokio::runtime::current_thread::Runtime;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        truncating_dns_server(addr);
+        // Connections are accepted and held open, but never answered.
+        thread::spawn(move || listener.incoming().collect::<Vec<_>>());
+
+        let mut resolver = UdpResolver::new(vec![addr]);
+        resolver.set_timeout(Duration::from_millis(100));
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(resolver.resolve(Name::new("example.test".to_owned(), 80))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_parse_response_checks_question() {
+        let query = encode_query(0x1234, "example.test", QTYPE_A).unwrap();
+        let answer = stub_answer(&query);
+        assert_eq!(
+            parse_response(&answer, &query).unwrap(),
+            Response::Answer(vec![IpAddr::from([192, 0, 2, 1])]),
+        );
+
+        // Servers may echo the name in another case.
+        let upper = encode_query(0x1234, "EXAMPLE.test", QTYPE_A).unwrap();
+        assert_eq!(parse_response(&stub_answer(&upper), &query).unwrap(), Response::Answer(vec![IpAddr::from([192, 0, 2, 1])]));
+
+        // The same id, but for another name or type, isn't an answer.
+        let other = encode_query(0x1234, "example.other", QTYPE_A).unwrap();
+        assert_eq!(parse_response(&stub_answer(&other), &query).unwrap(), Response::Unrelated);
+        let aaaa = encode_query(0x1234, "example.test", QTYPE_AAAA).unwrap();
+        assert_eq!(parse_response(&stub_answer(&aaaa), &query).unwrap(), Response::Unrelated);
+        let other_id = encode_query(0x4321, "example.test", QTYPE_A).unwrap();
+        assert_eq!(parse_response(&stub_answer(&other_id), &query).unwrap(), Response::Unrelated);
+
+        let mut truncated = answer.clone();
+        truncated[2] |= 0x02;
+        assert_eq!(parse_response(&truncated, &query).unwrap(), Response::Truncated);
+        assert_eq!(parse_response(&answer[..20], &query).unwrap(), Response::Unrelated);
+    }
+
+    #[test]
+    fn test_encode_query_rejects_invalid_names() {
+        assert!(encode_query(1, "example.test", QTYPE_A).is_some());
+        assert!(encode_query(1, "", QTYPE_A).is_none());
+        assert!(encode_query(1, "a..b", QTYPE_A).is_none());
+        assert!(encode_query(1, &"a".repeat(64), QTYPE_A).is_none());
+    }
+
+    #[test]
+    fn test_gai_resolver_error_names_host() {
+        let resolver = GaiResolver::new(1);
+        let err = resolver.resolve(Name::new("bogus-host.invalid".to_owned(), 8080)).wait().unwrap_err();
+        assert!(err.to_string().starts_with("failed to resolve bogus-host.invalid:8080: "), "{}", err);
+        let source = err.get_ref().and_then(|e| e.downcast_ref::<ResolveError>()).map(|e| e.source.kind());
+        assert_eq!(source, Some(err.kind()));
+    }
+
+    #[test]
+    fn test_gai_resolver_bracketed_ipv6() {
+        let resolver = GaiResolver::new(1);
+        let addrs = resolver.resolve(Name::new("[::1]".to_owned(), 80)).wait().expect("resolve");
+        let loopback = SocketAddr::from((Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 80));
+        assert_eq!(addrs.collect::<Vec<_>>(), vec![loopback]);
+    }
+
+    #[test]
+    fn test_internationalized_names_resolve_in_ascii_form() {
+        let name = Name::new("B\u{fc}cher.example".to_owned(), 80);
+        assert_eq!(name.to_ascii().unwrap(), Name::new("xn--bcher-kva.example".to_owned(), 80));
+
+        // A label that can't be converted is never looked up.
+        let invalid = || Name::new("b\u{fc} cher.example".to_owned(), 80);
+        let err = GaiResolver::new(1).resolve(invalid()).wait().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("b\u{fc} cher"), "{}", err);
+        let err = UdpResolver::new(vec![([127, 0, 0, 1], 53).into()]).resolve(invalid()).wait().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("b\u{fc} cher"), "{}", err);
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn test_ip_addrs_try_parse_ipv6_zone_name() {
+        let lo = if cfg!(target_os = "linux") { "lo" } else { "lo0" };
+        for host in &[format!("fe80::1%{}", lo), format!("[fe80::1%25{}]", lo)] {
+            let addrs = IpAddrs::try_parse(host, 80).expect("loopback zone");
+            let addrs = addrs.collect::<Vec<_>>();
+            match addrs.first() {
+                Some(&SocketAddr::V6(ref addr)) if addrs.len() == 1 => assert_ne!(addr.scope_id(), 0),
+                _ => panic!("unexpected addrs {:?}", addrs),
+            }
+        }
+    }
+
+    #[test]
+    fn test_ip_addrs_split_by_preference() {
+        let v4_addr = (Ipv4Addr::new(127, 0, 0, 1), 80).into();
+        let v6_addr = (Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 80).into();
+
+        let (mut preferred, mut fallback) =
+            IpAddrs::new(vec![v4_addr, v6_addr]).split_by_preference();
+        assert!(preferred.next().unwrap().is_ipv4());
+        assert!(fallback.next().unwrap().is_ipv6());
+
+        let (mut preferred, mut fallback) =
+            IpAddrs::new(vec![v6_addr, v4_addr]).split_by_preference();
+        assert!(preferred.next().unwrap().is_ipv6());
+        assert!(fallback.next().unwrap().is_ipv4());
+
+        let (preferred, fallback) = IpAddrs::new(vec![v4_addr, v6_addr, v4_addr]).split_preferred(true);
+        assert_eq!(preferred.as_slice(), &[v6_addr]);
+        assert_eq!(fallback.as_slice(), &[v4_addr, v4_addr]);
+
+        let (preferred, fallback) = IpAddrs::new(Vec::new()).split_preferred(false);
+        assert!(preferred.is_empty() && fallback.is_empty());
+    }
+
+    #[test]
+    fn test_ip_addrs_deduped() {
+        let v4 = |n| SocketAddr::from(([10, 0, 0, n], 80));
+        let v6 = SocketAddr::from((Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 80));
+        let addrs = IpAddrs::new(vec![v4(1), v6, v4(2), v4(1), v6, v4(3), v4(2)]);
+        assert_eq!(addrs.deduped().as_slice(), &[v4(1), v6, v4(2), v4(3)]);
+
+        // The same IP with another port is another address.
+        let other_port = SocketAddr::from(([10, 0, 0, 1], 8080));
+        let addrs = IpAddrs::new(vec![v4(1), other_port, v4(1)]);
+        assert_eq!(addrs.deduped().as_slice(), &[v4(1), other_port]);
+        assert!(IpAddrs::new(Vec::new()).deduped().is_empty());
+    }
+
+    #[test]
+    fn test_ip_addrs_interleaved() {
+        let v4 = |n| SocketAddr::from(([10, 0, 0, n], 80));
+        let v6 = |n| SocketAddr::from((Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, n), 80));
+        let addrs = IpAddrs::new(vec![v6(1), v6(2), v4(1), v4(2)]);
+        assert_eq!(addrs.interleaved().as_slice(), &[v6(1), v4(1), v6(2), v4(2)]);
+
+        // The first family leads, and the longer family's rest trails.
+        let addrs = IpAddrs::new(vec![v4(1), v4(2), v4(3), v6(1)]);
+        assert_eq!(addrs.interleaved().as_slice(), &[v4(1), v6(1), v4(2), v4(3)]);
+        assert!(IpAddrs::new(Vec::new()).interleaved().is_empty());
+    }
+
+    #[test]
+    fn test_ip_addrs_len_and_clone() {
+        let v4_addr = (Ipv4Addr::new(127, 0, 0, 1), 80).into();
+        let v6_addr = (Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 80).into();
+
+        let empty = IpAddrs::new(Vec::new());
+        assert_eq!(empty.len(), 0);
+        assert!(empty.is_empty());
+
+        let mut addrs = vec![v4_addr, v6_addr].into_iter().collect::<IpAddrs>();
+        assert_eq!(addrs.len(), 2);
+        assert_eq!(addrs.next(), Some(v4_addr));
+        // A clone only holds what wasn't iterated yet.
+        let rest = addrs.clone();
+        assert_eq!(rest.as_slice(), &[v6_addr]);
+        assert_eq!(rest.size_hint(), (1, Some(1)));
+    }
+
+    #[test]
+    fn test_ip_addrs_with_preference() {
+        let v4_a = (Ipv4Addr::new(127, 0, 0, 1), 80).into();
+        let v4_b = (Ipv4Addr::new(127, 0, 0, 2), 80).into();
+        let v6_a = (Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 80).into();
+        let v6_b = (Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 2), 80).into();
+        let mixed = vec![v6_a, v4_a, v6_b, v4_b];
+
+        let order = |pref| IpAddrs::with_preference(mixed.clone(), pref).collect::<Vec<_>>();
+        assert_eq!(order(IpFamilyPreference::System), mixed);
+        assert_eq!(order(IpFamilyPreference::Ipv4First), vec![v4_a, v4_b, v6_a, v6_b]);
+        assert_eq!(order(IpFamilyPreference::Ipv6First), vec![v6_a, v6_b, v4_a, v4_b]);
+        assert_eq!(order(IpFamilyPreference::Ipv4Only), vec![v4_a, v4_b]);
+        assert_eq!(order(IpFamilyPreference::Ipv6Only), vec![v6_a, v6_b]);
+    }
+
+    #[test]
+    fn test_ip_addrs_shuffle_within_families() {
+        let v4 = |n| SocketAddr::from((Ipv4Addr::new(10, 0, 0, n), 80));
+        let v6 = |n| SocketAddr::from((Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, n as u16), 80));
+        let addrs = vec![v6(1), v4(1), v4(2), v6(2), v4(3), v4(4)];
+
+        let mut rng = ShuffleRng::new(42);
+        let shuffled = IpAddrs::new(addrs.clone()).shuffle_within_families(&mut rng).collect::<Vec<_>>();
+        assert_eq!(shuffled, vec![v6(2), v4(4), v4(1), v6(1), v4(2), v4(3)]);
+
+        // The same seed gives the same order.
+        let mut rng = ShuffleRng::new(42);
+        let again = IpAddrs::new(addrs).shuffle_within_families(&mut rng).collect::<Vec<_>>();
+        assert_eq!(again, shuffled);
+    }
+
+    #[test]
+    fn test_order_srv_by_priority_and_weight() {
+        let record = |priority, weight, target: &str| SrvRecord::new(priority, weight, 80, target.to_owned());
+        let records = vec![record(20, 1, "backup"), record(10, 0, "light"), record(10, 9, "heavy")];
+
+        let mut rng = ShuffleRng::new(42);
+        let mut heavy_first = 0;
+        for _ in 0..1000 {
+            let ordered = order_srv(records.clone(), &mut rng);
+            assert_eq!(ordered.len(), 3);
+            assert_eq!(ordered[2].target(), "backup");
+            if ordered[0].target() == "heavy" {
+                heavy_first += 1;
+            }
+        }
+        // Picked first with a chance of 9 in 10.
+        assert!(heavy_first > 800 && heavy_first < 980, "{}", heavy_first);
+    }
+
+    #[test]
+    fn test_ip_addrs_rotate_within_families() {
+        let v4 = |n| SocketAddr::from(([10, 0, 0, n], 80));
+        let v6 = |n| SocketAddr::from((Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, n as u16), 80));
+        let addrs = vec![v6(1), v4(1), v4(2), v6(2), v4(3)];
+
+        let rotated = IpAddrs::new(addrs.clone()).rotate_within_families(1).collect::<Vec<_>>();
+        assert_eq!(rotated, vec![v6(2), v4(2), v4(3), v6(1), v4(1)]);
+        let rotated = IpAddrs::new(addrs.clone()).rotate_within_families(3).collect::<Vec<_>>();
+        assert_eq!(rotated, vec![v6(2), v4(1), v4(2), v6(1), v4(3)]);
+    }
+
+    #[test]
+    fn test_ip_addrs_next_filter() {
+        let v4_addr = (Ipv4Addr::new(127, 0, 0, 1), 80).into();
+        let v6_addr = (Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1), 80).into();
+        let local_v4 = Some(Ipv4Addr::new(127, 0, 0, 1));
+        let local_v6 = Some(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1));
+
+        let addrs = IpAddrs::new(vec![v6_addr, v4_addr]);
+        assert_eq!(addrs.next_filter(0, local_v4, None), Some(1));
+        assert_eq!(addrs.next_filter(2, local_v4, None), None);
+        assert_eq!(addrs.next_filter(0, None, local_v6), Some(0));
+        assert_eq!(addrs.next_filter(1, None, local_v6), None);
+        assert_eq!(addrs.next_filter(0, local_v4, local_v6), Some(0));
+        assert_eq!(addrs.next_filter(1, None, None), Some(1));
+    }
+
+    #[test]
+    fn test_caching_resolver_skips_inner_within_ttl() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use futures::future::{self, FutureResult};
+
+        struct CountingResolver(Arc<AtomicUsize>);
+
+        impl Resolve for CountingResolver {
+            type Future = FutureResult<IpAddrs, io::Error>;
+
+            fn resolve(&self, name: Name) -> Self::Future {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                let addr = (Ipv4Addr::new(127, 0, 0, 1), name.port()).into();
+                future::ok(IpAddrs::new(vec![addr]))
+            }
+        }
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let resolver = CachingResolver::new(
+            CountingResolver(calls.clone()),
+            Duration::from_secs(60),
+            10,
+        );
+
+        let name = Name::new("example.test".to_owned(), 80);
+        let first = resolver.resolve(name.clone()).wait().unwrap().collect::<Vec<_>>();
+        let second = resolver.resolve(name).wait().unwrap().collect::<Vec<_>>();
+        assert_eq!(first, second);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // A different port is a different entry.
+        resolver.resolve(Name::new("example.test".to_owned(), 8080)).wait().unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+
+        let expiring = CachingResolver::new(
+            CountingResolver(calls.clone()),
+            Duration::from_millis(0),
+            10,
+        );
+        let name = Name::new("example.test".to_owned(), 80);
+        expiring.resolve(name.clone()).wait().unwrap();
+        expiring.resolve(name).wait().unwrap();
+        assert_eq!(calls.load(Ordering::SeqCst), 4);
+    }
+
+    #[test]
+    fn test_caching_resolver_negative_ttl() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::thread;
+        use futures::future::{self, FutureResult};
+
+        // Fails the first lookup, and resolves every other one.
+        struct FlakyResolver(Arc<AtomicUsize>);
+
+        impl Resolve for FlakyResolver {
+            type Future = FutureResult<IpAddrs, io::Error>;
+
+            fn resolve(&self, name: Name) -> Self::Future {
+                if self.0.fetch_add(1, Ordering::SeqCst) == 0 {
+                    return future::err(io::Error::new(io::ErrorKind::NotFound, "no such host"));
+                }
+                let addr = (Ipv4Addr::new(127, 0, 0, 1), name.port()).into();
+                future::ok(IpAddrs::new(vec![addr]))
+            }
+        }
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let mut resolver = CachingResolver::new(FlakyResolver(calls.clone()), Duration::from_secs(60), 10);
+        resolver.set_dns_negative_ttl(Some(Duration::from_millis(50)));
+
+        let name = Name::new("example.test".to_owned(), 80);
+        let err = resolver.resolve(name.clone()).wait().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        let err = resolver.resolve(name.clone()).wait().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().starts_with("no such host"), "{}", err);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // Once the failure expired, the name resolves and is cached.
+        thread::sleep(Duration::from_millis(60));
+        resolver.resolve(name.clone()).wait().expect("resolve after expiry");
+        resolver.resolve(name).wait().expect("cached");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_cache_evicts_oldest_when_full() {
+        let cache = Cache::new(Duration::from_secs(60), 2);
+        let addrs = IpAddrs::new(vec![(Ipv4Addr::new(127, 0, 0, 1), 80).into()]);
+        let a = Name::new("a.test".to_owned(), 80);
+        let b = Name::new("b.test".to_owned(), 80);
+        let c = Name::new("c.test".to_owned(), 80);
+
+        cache.insert(a.clone(), &addrs);
+        ::std::thread::sleep(Duration::from_millis(1));
+        cache.insert(b.clone(), &addrs);
+        cache.insert(c.clone(), &addrs);
+
+        assert!(cache.get(&a).is_none());
+        assert!(cache.get(&b).is_some());
+        assert!(cache.get(&c).is_some());
+    }
+
+    #[test]
+    fn test_cache_evicts_least_recently_used() {
+        let cache = Cache::new(Duration::from_secs(60), 2);
+        let addrs = IpAddrs::new(vec![(Ipv4Addr::new(127, 0, 0, 1), 80).into()]);
+        let a = Name::new("a.test".to_owned(), 80);
+        let b = Name::new("b.test".to_owned(), 80);
+        let c = Name::new("c.test".to_owned(), 80);
+
+        cache.insert(a.clone(), &addrs);
+        ::std::thread::sleep(Duration::from_millis(1));
+        cache.insert(b.clone(), &addrs);
+        ::std::thread::sleep(Duration::from_millis(1));
+        assert!(cache.get(&a).is_some());
+        cache.insert(c.clone(), &addrs);
+
+        assert!(cache.get(&a).is_some());
+        assert!(cache.get(&b).is_none());
+        assert!(cache.get(&c).is_some());
+
+        cache.remove(&a);
+        assert!(cache.get(&a).is_none());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_gai_resolver_clones_share_threads() {
+        use std::fs::{self, File};
+        use std::io::Read;
+
+        let comm = |path: ::std::path::PathBuf| {
+            let mut name = String::new();
+            File::open(path.join("comm")).and_then(|mut f| f.read_to_string(&mut name)).map(|_| name)
+        };
+        let threads = || fs::read_dir("/proc/self/task")
+            .unwrap()
+            .filter_map(|task| comm(task.unwrap().path()).ok())
+            .filter(|name| name.starts_with("gai-share-"))
+            .count();
+
+        let pool = CpuPoolBuilder::new()
+            .name_prefix("gai-share-")
+            .pool_size(2)
+            .create();
+        let resolver = GaiResolver::new_with_pool(pool);
+        let resolvers = vec![resolver.clone(), resolver.clone(), resolver];
+        for resolver in &resolvers {
+            let addrs = resolver.lookup("localhost", 8080).wait().expect("lookup");
+            assert!(addrs.clone().all(|addr| addr.port() == 8080), "{:?}", addrs);
+        }
+        // Threads name themselves once started, which a lookup waits for.
+        assert!(threads() <= 2, "{} threads", threads());
+        assert!(threads() >= 1, "{} threads", threads());
+    }
+
+    #[test]
+    fn test_gai_resolver_resize() {
+        let mut resolver = GaiResolver::new(1);
+        let old = resolver.clone();
+        resolver.resize(4).expect("resize");
+        for _ in 0..8 {
+            resolver.lookup("localhost", 80).wait().expect("lookup on the new pool");
+        }
+        old.lookup("localhost", 80).wait().expect("lookup on the old pool");
+        assert_eq!(resolver.resize(0).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+
+        let pool = CpuPoolBuilder::new().pool_size(1).create();
+        let mut resolver = GaiResolver::new_with_pool(pool);
+        assert_eq!(resolver.resize(2).unwrap_err().kind(), io::ErrorKind::Other);
+        resolver.lookup("localhost", 80).wait().expect("lookup");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_getaddrinfo_hints() {
+        let addrs = getaddrinfo("127.0.0.1", 8080, ::libc::AF_UNSPEC, ::libc::AI_NUMERICHOST).expect("numeric host");
+        assert_eq!(addrs, vec![SocketAddr::from(([127, 0, 0, 1], 8080))]);
+        // The flags reach `getaddrinfo`, which refuses to look up a name.
+        assert!(getaddrinfo("localhost", 8080, ::libc::AF_UNSPEC, ::libc::AI_NUMERICHOST).is_err());
+
+        let mapped = getaddrinfo("127.0.0.1", 80, ::libc::AF_INET6, ::libc::AI_V4MAPPED | ::libc::AI_NUMERICHOST).expect("mapped");
+        assert_eq!(mapped, vec![SocketAddr::from((Ipv4Addr::new(127, 0, 0, 1).to_ipv6_mapped(), 80))]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_gai_resolver_flags() {
+        let mut resolver = GaiResolver::new(1);
+        resolver.set_addr_config(true);
+        resolver.set_v4_mapped(true);
+        let addrs = resolver.lookup("localhost", 80).wait().expect("lookup");
+        assert!(addrs.clone().all(|addr| addr.is_ipv6()), "{:?}", addrs);
+    }
+}
@@ -0,0 +1,98 @@
+use std::fmt;
+use std::io;
+
+use futures::{Future, Poll};
+use tokio::net::TcpStream;
+
+use super::{Connect, Connected, Destination};
+
+/// A connector whose every connect fails with the same error.
+///
+/// This makes it easy to check how a `Client`, or a connector wrapping
+/// this one, handles a failed connect, without needing a server that
+/// refuses connections.
+#[derive(Clone, Debug)]
+pub struct FailConnector {
+    kind: io::ErrorKind,
+    message: String,
+}
+
+impl FailConnector {
+    /// Construct a new `FailConnector`, failing with an error of `kind`
+    /// described by `message`.
+    pub fn new<S: Into<String>>(kind: io::ErrorKind, message: S) -> FailConnector {
+        FailConnector {
+            kind: kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl Connect for FailConnector {
+    type Transport = TcpStream;
+    type Error = io::Error;
+    type Future = FailConnecting;
+
+    fn connect(&self, _dst: Destination) -> Self::Future {
+        FailConnecting {
+            error: Some(io::Error::new(self.kind, self.message.clone())),
+        }
+    }
+}
+
+/// A Future that fails right away, returned by a `FailConnector`.
+#[must_use = "futures do nothing unless polled"]
+pub struct FailConnecting {
+    error: Option<io::Error>,
+}
+
+impl Future for FailConnecting {
+    type Item = (TcpStream, Connected);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        Err(self.error.take().expect("FailConnecting polled after failing"))
+    }
+}
+
+impl fmt::Debug for FailConnecting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("FailConnecting")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use futures::Future;
+    use tokio::runtime::current_thread::Runtime;
+
+    use {Body, Client, Request};
+    use super::{Connect, Destination, FailConnector};
+
+    #[test]
+    fn test_fail_connector_error() {
+        let connector = FailConnector::new(io::ErrorKind::PermissionDenied, "no network");
+        let dst = Destination {
+            uri: "http://fail.test".parse().unwrap(),
+        };
+        let err = connector.connect(dst).wait().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::PermissionDenied);
+        assert_eq!(err.to_string(), "no network");
+    }
+
+    #[test]
+    fn test_client_propagates_connect_error() {
+        let connector = FailConnector::new(io::ErrorKind::ConnectionRefused, "refused");
+        let client = Client::builder().build::<_, Body>(connector);
+        let req = Request::builder()
+            .uri("http://fail.test/")
+            .body(Body::empty())
+            .unwrap();
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(client.request(req)).unwrap_err();
+        assert_eq!(err.to_string(), "an error occurred trying to connect: refused");
+    }
+}
@@ -0,0 +1,325 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use futures::{Async, Future, Poll};
+use http::Uri;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use super::{into_io_error, Connect, Connected, Destination};
+
+/// A connector that tries one connector, and falls back to another.
+///
+/// Each connect goes through the primary connector first. Only if that
+/// fails with an error the fallback predicate accepts is the same
+/// destination connected to with the fallback connector, such as a Unix
+/// socket when co-located, and TCP otherwise.
+#[derive(Clone)]
+pub struct FallbackConnector<A, B> {
+    fallback: B,
+    fallback_on: Arc<Fn(&io::Error) -> bool + Send + Sync>,
+    primary: A,
+}
+
+impl<A, B> FallbackConnector<A, B> {
+    /// Construct a new `FallbackConnector`, trying `primary` and then
+    /// `fallback`.
+    pub fn new(primary: A, fallback: B) -> FallbackConnector<A, B> {
+        FallbackConnector {
+            fallback: fallback,
+            fallback_on: Arc::new(|_: &io::Error| true),
+            primary: primary,
+        }
+    }
+
+    /// Set which errors of the primary connector are fallen back from.
+    ///
+    /// Any other error is returned right away.
+    ///
+    /// Default is any error.
+    pub fn set_fallback_on<F>(&mut self, fallback_on: F)
+    where
+        F: Fn(&io::Error) -> bool + Send + Sync + 'static,
+    {
+        self.fallback_on = Arc::new(fallback_on);
+    }
+
+    /// Get a reference to the primary connector.
+    pub fn primary(&self) -> &A {
+        &self.primary
+    }
+
+    /// Get a mutable reference to the primary connector.
+    pub fn primary_mut(&mut self) -> &mut A {
+        &mut self.primary
+    }
+
+    /// Get a reference to the fallback connector.
+    pub fn fallback(&self) -> &B {
+        &self.fallback
+    }
+
+    /// Get a mutable reference to the fallback connector.
+    pub fn fallback_mut(&mut self) -> &mut B {
+        &mut self.fallback
+    }
+}
+
+impl<A: fmt::Debug, B: fmt::Debug> fmt::Debug for FallbackConnector<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("FallbackConnector")
+            .field("primary", &self.primary)
+            .field("fallback", &self.fallback)
+            .finish()
+    }
+}
+
+impl<A, B> Connect for FallbackConnector<A, B>
+where
+    A: Connect + Clone,
+    B: Connect + Clone,
+{
+    type Transport = FallbackStream<A::Transport, B::Transport>;
+    type Error = io::Error;
+    type Future = FallbackConnecting<A, B>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let uri = dst.uri.clone();
+        FallbackConnecting {
+            connector: self.clone(),
+            state: State::Primary(self.primary.connect(dst)),
+            uri: Some(uri),
+        }
+    }
+}
+
+/// A Future representing work to connect, with the fallback connector if
+/// the primary one failed.
+#[must_use = "futures do nothing unless polled"]
+pub struct FallbackConnecting<A: Connect, B: Connect> {
+    connector: FallbackConnector<A, B>,
+    state: State<A::Future, B::Future>,
+    uri: Option<Uri>,
+}
+
+enum State<A, B> {
+    Primary(A),
+    Fallback(B, Option<io::Error>),
+}
+
+impl<A: Connect, B: Connect> Future for FallbackConnecting<A, B> {
+    type Item = (FallbackStream<A::Transport, B::Transport>, Connected);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let state = match self.state {
+                State::Primary(ref mut fut) => match fut.poll().map_err(into_io_error) {
+                    Ok(Async::Ready((io, connected))) => {
+                        return Ok(Async::Ready((FallbackStream::Primary(io), connected)));
+                    },
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        if !(self.connector.fallback_on)(&err) {
+                            return Err(err);
+                        }
+                        debug!("primary connect failed, falling back: {}", err);
+                        let dst = Destination {
+                            uri: self.uri.take().expect("FallbackConnecting polled after failing"),
+                        };
+                        State::Fallback(self.connector.fallback.connect(dst), Some(err))
+                    },
+                },
+                State::Fallback(ref mut fut, ref mut primary) => match fut.poll().map_err(into_io_error) {
+                    Ok(Async::Ready((io, connected))) => {
+                        return Ok(Async::Ready((FallbackStream::Fallback(io), connected)));
+                    },
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(err) => {
+                        let primary = primary.take().expect("FallbackConnecting polled after failing");
+                        return Err(io::Error::new(err.kind(), FallbackError {
+                            fallback: err,
+                            primary: primary,
+                        }));
+                    },
+                },
+            };
+            self.state = state;
+        }
+    }
+}
+
+impl<A: Connect, B: Connect> fmt::Debug for FallbackConnecting<A, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("FallbackConnecting")
+    }
+}
+
+/// The error when both connectors of a `FallbackConnector` failed.
+///
+/// This is the inner error of the `io::Error` returned by
+/// `FallbackConnector`, which has the kind of the fallback error, and can
+/// be retrieved with `get_ref()` and `downcast_ref::<FallbackError>()`.
+#[derive(Debug)]
+pub struct FallbackError {
+    fallback: io::Error,
+    primary: io::Error,
+}
+
+impl FallbackError {
+    /// Get the error of the primary connector.
+    pub fn primary(&self) -> &io::Error {
+        &self.primary
+    }
+
+    /// Get the error of the fallback connector.
+    pub fn fallback(&self) -> &io::Error {
+        &self.fallback
+    }
+}
+
+impl fmt::Display for FallbackError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (after falling back from: {})", self.fallback, self.primary)
+    }
+}
+
+impl StdError for FallbackError {
+    fn description(&self) -> &str {
+        "fallback connect failed"
+    }
+}
+
+/// The transport of a `FallbackConnector`, from whichever connector
+/// succeeded.
+#[derive(Debug)]
+pub enum FallbackStream<A, B> {
+    /// Connected with the primary connector.
+    Primary(A),
+    /// Connected with the fallback connector.
+    Fallback(B),
+}
+
+impl<A: Read, B: Read> Read for FallbackStream<A, B> {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            FallbackStream::Primary(ref mut io) => io.read(buf),
+            FallbackStream::Fallback(ref mut io) => io.read(buf),
+        }
+    }
+}
+
+impl<A: Write, B: Write> Write for FallbackStream<A, B> {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match *self {
+            FallbackStream::Primary(ref mut io) => io.write(buf),
+            FallbackStream::Fallback(ref mut io) => io.write(buf),
+        }
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        match *self {
+            FallbackStream::Primary(ref mut io) => io.flush(),
+            FallbackStream::Fallback(ref mut io) => io.flush(),
+        }
+    }
+}
+
+impl<A: AsyncRead, B: AsyncRead> AsyncRead for FallbackStream<A, B> {
+    #[inline]
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        match *self {
+            FallbackStream::Primary(ref io) => io.prepare_uninitialized_buffer(buf),
+            FallbackStream::Fallback(ref io) => io.prepare_uninitialized_buffer(buf),
+        }
+    }
+}
+
+impl<A: AsyncWrite, B: AsyncWrite> AsyncWrite for FallbackStream<A, B> {
+    #[inline]
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        match *self {
+            FallbackStream::Primary(ref mut io) => io.shutdown(),
+            FallbackStream::Fallback(ref mut io) => io.shutdown(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::TcpListener;
+
+    use tokio::runtime::current_thread::Runtime;
+
+    use super::{Connect, Destination, FallbackConnector, FallbackError, FallbackStream};
+    use super::super::{FailConnector, HttpConnector};
+
+    fn dst(s: &str) -> Destination {
+        Destination {
+            uri: s.parse().unwrap(),
+        }
+    }
+
+    fn refused() -> FailConnector {
+        FailConnector::new(io::ErrorKind::ConnectionRefused, "refused")
+    }
+
+    #[test]
+    fn test_primary_succeeds() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let connector = FallbackConnector::new(HttpConnector::new(1), refused());
+
+        let mut rt = Runtime::new().unwrap();
+        let (io, _) = rt.block_on(connector.connect(dst(&format!("http://{}", addr)))).expect("connect");
+        match io {
+            FallbackStream::Primary(io) => assert_eq!(io.peer_addr().unwrap(), addr),
+            FallbackStream::Fallback(_) => panic!("connected with the fallback connector"),
+        }
+    }
+
+    #[test]
+    fn test_primary_fails_fallback_succeeds() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let connector = FallbackConnector::new(refused(), HttpConnector::new(1));
+
+        let mut rt = Runtime::new().unwrap();
+        let (io, _) = rt.block_on(connector.connect(dst(&format!("http://{}", addr)))).expect("connect");
+        match io {
+            FallbackStream::Primary(_) => panic!("connected with the primary connector"),
+            FallbackStream::Fallback(io) => assert_eq!(io.peer_addr().unwrap(), addr),
+        }
+    }
+
+    #[test]
+    fn test_both_fail() {
+        let fallback = FailConnector::new(io::ErrorKind::TimedOut, "timed out");
+        let connector = FallbackConnector::new(refused(), fallback);
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(dst("http://fallback.test"))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(err.to_string(), "timed out (after falling back from: refused)");
+        let err = err.get_ref().and_then(|e| e.downcast_ref::<FallbackError>()).expect("FallbackError");
+        assert_eq!(err.primary().kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(err.fallback().kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_fallback_on() {
+        let fallback = FailConnector::new(io::ErrorKind::TimedOut, "timed out");
+        let mut connector = FallbackConnector::new(refused(), fallback);
+        connector.set_fallback_on(|err: &io::Error| err.kind() == io::ErrorKind::NotFound);
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(dst("http://fallback.test"))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        assert_eq!(err.to_string(), "refused");
+    }
+}
@@ -0,0 +1,4469 @@
+use std::borrow::Cow;
+use std::cmp;
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::{task, Async, Future, Poll};
+use futures::future::Executor;
+use futures_cpupool::CpuPool;
+use http::Uri;
+use net2::{TcpBuilder, TcpStreamExt};
+use tokio_reactor::Handle;
+use tokio_tcp::{TcpStream, ConnectFuture};
+use tokio_timer::Delay;
+
+use super::{ascii_lowercase, idna, normalize_host, retry, sockopt, Connect, ConnectEvent, ConnectObserver, ConnectTiming, Connected, Destination};
+use super::limit::{Acquire, ConnectLimit};
+use super::observer::CallbackObserver;
+use super::dns::{self, AddrOrder, GaiResolver, IpFamilyPreference, Name, Resolve};
+
+fn connect(
+    addr: &SocketAddr,
+    config: &Config,
+    local_ports: &mut Option<Range<u16>>,
+    handle: &Option<Handle>,
+) -> io::Result<ConnectFuture> {
+    let builder = match addr {
+        &SocketAddr::V4(_) => TcpBuilder::new_v4()?,
+        &SocketAddr::V6(_) => TcpBuilder::new_v6()?,
+    };
+
+    if let Some(mark) = config.fwmark {
+        sockopt::set_mark(&builder, mark)?;
+    }
+
+    if let Some(tos) = config.tos {
+        sockopt::set_tos(&builder, addr.is_ipv6(), tos)?;
+    }
+
+    if config.tcp_fastopen {
+        sockopt::set_fastopen_connect(&builder)?;
+    }
+
+    // The device must be bound before the address, since binding
+    // the address may already depend on the interface.
+    if let Some(ref iface) = config.interface {
+        sockopt::bind_device(&builder, iface)?;
+    }
+
+    let local_addr = config.local_address_for(addr);
+
+    // Reusing only matters for sockets bound to a local address or port.
+    if local_addr.is_some() || local_ports.is_some() || config.local_port.is_some() {
+        if config.reuse_address {
+            builder.reuse_address(true)?;
+        }
+        if config.reuse_port {
+            sockopt::set_reuse_port(&builder)?;
+        }
+    }
+
+    if let Some(ref mut ports) = *local_ports {
+        // Ports in TIME_WAIT can't be bound again without SO_REUSEADDR,
+        // which would quickly exhaust the range with rapid reconnects. On
+        // Windows it also allows binding ports other sockets are using, so
+        // they would no longer be skipped; there it's left to the caller.
+        if !cfg!(windows) {
+            builder.reuse_address(true)?;
+        }
+        let local_addr = local_addr.unwrap_or_else(|| unspecified(addr));
+        bind_port_range(&builder, local_addr, ports)?;
+    }
+    else if local_addr.is_some() {
+        // Caller has requested this socket be bound before calling connect
+        bind_first(&builder, config.local_addresses_for(addr), config.local_port.unwrap_or(0))?;
+    }
+    else if let Some(port) = config.local_port {
+        bind_first(&builder, vec![unspecified(addr)], port)?;
+    }
+    else if cfg!(windows) {
+        // Windows requires a socket be bound before calling connect
+        builder.bind(SocketAddr::new(unspecified(addr), 0))?;
+    }
+
+    let std_tcp = builder.to_tcp_stream()?;
+
+    // Buffer sizes should be set before connecting, since they affect
+    // the TCP window negotiated in the handshake.
+    if let Some(size) = config.send_buffer_size {
+        std_tcp.set_send_buffer_size(size)?;
+    }
+
+    if let Some(size) = config.recv_buffer_size {
+        std_tcp.set_recv_buffer_size(size)?;
+    }
+
+    let handle = match *handle {
+        Some(ref handle) => Cow::Borrowed(handle),
+        None => Cow::Owned(Handle::current()),
+    };
+
+    Ok(TcpStream::connect_std(std_tcp, addr, &handle))
+}
+
+fn default_ports() -> HashMap<String, u16> {
+    let mut ports = HashMap::new();
+    ports.insert("http".to_owned(), 80);
+    ports.insert("https".to_owned(), 443);
+    ports
+}
+
+// The "any" address of the same family as `addr`.
+fn unspecified(addr: &SocketAddr) -> IpAddr {
+    match addr {
+        &SocketAddr::V4(_) => [0, 0, 0, 0].into(),
+        &SocketAddr::V6(_) => [0, 0, 0, 0, 0, 0, 0, 0].into(),
+    }
+}
+
+// Bind to the first port in `ports` that isn't in use. Ports that were
+// tried are consumed from the range.
+fn bind_port_range(builder: &TcpBuilder, ip: IpAddr, ports: &mut Range<u16>) -> io::Result<()> {
+    for port in ports {
+        match builder.bind(SocketAddr::new(ip, port)) {
+            Ok(_) => return Ok(()),
+            Err(ref e) if e.kind() == io::ErrorKind::AddrInUse => {
+                trace!("local port {} in use, trying next", port);
+            },
+            Err(e) => return Err(bind_error(e, &SocketAddr::new(ip, port))),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AddrInUse,
+        format!("all local ports in range are in use on {}", ip),
+    ))
+}
+
+// Bind to `port` on the first of `ips` that can be bound.
+fn bind_first(builder: &TcpBuilder, ips: Vec<IpAddr>, port: u16) -> io::Result<()> {
+    let mut last_err = None;
+    for ip in ips {
+        let local_addr = SocketAddr::new(ip, port);
+        match builder.bind(local_addr) {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                trace!("failed to bind local address {}: {}", local_addr, e);
+                last_err = Some(bind_error(e, &local_addr));
+            },
+        }
+    }
+    Err(last_err.unwrap_or_else(|| io::Error::new(io::ErrorKind::AddrNotAvailable, "no local address to bind")))
+}
+
+fn bind_error(err: io::Error, local_addr: &SocketAddr) -> io::Error {
+    io::Error::new(err.kind(), format!("failed to bind local address {}: {}", local_addr, err))
+}
+
+/// A connector for the `http` scheme.
+///
+/// Performs DNS resolution in a thread pool, and then connects over TCP.
+///
+/// The resolver can be replaced with any type implementing
+/// [`Resolve`](dns::Resolve), see
+/// [`HttpConnector::new_with_resolver`](HttpConnector::new_with_resolver).
+pub struct HttpConnector<R = GaiResolver> {
+    config: Arc<Config>,
+    handle: Option<Handle>,
+    resolver: Arc<R>,
+}
+
+/// Which `Uri` schemes an `HttpConnector` connects to.
+///
+/// Whatever the scheme, an `HttpConnector` only connects over TCP, so
+/// `https` URIs need TLS layered on top by another connector.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SchemePolicy {
+    /// Only connect to `http` URIs.
+    HttpOnly,
+    /// Connect to `http` and `https` URIs.
+    HttpAndHttps,
+    /// Connect to URIs of any scheme.
+    Any,
+}
+
+impl Default for SchemePolicy {
+    fn default() -> SchemePolicy {
+        SchemePolicy::HttpOnly
+    }
+}
+
+#[derive(Clone)]
+struct Observer(Arc<ConnectObserver>);
+
+impl fmt::Debug for Observer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Observer")
+    }
+}
+
+#[derive(Clone)]
+struct UriOverride(Arc<Fn(&Uri) -> Option<dns::IpAddrs> + Send + Sync>);
+
+impl fmt::Debug for UriOverride {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("UriOverride")
+    }
+}
+
+#[derive(Clone)]
+struct DeadlineFn(Arc<Fn() -> Option<Instant> + Send + Sync>);
+
+impl fmt::Debug for DeadlineFn {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("DeadlineFn")
+    }
+}
+
+// Settings shared by an `HttpConnector`, its clones, and the futures it
+// returns. Cloned on write, so setters don't affect connects in progress.
+#[derive(Clone, Debug)]
+struct Config {
+    allowed_schemes: Option<Vec<String>>,
+    connect_deadline: Option<DeadlineFn>,
+    connect_limit: Option<ConnectLimit>,
+    connect_retries: usize,
+    connect_retry_delay: Duration,
+    connect_timeout: Option<Duration>,
+    dedupe_addresses: bool,
+    default_ports: HashMap<String, u16>,
+    dns_cache: Option<dns::Cache>,
+    dns_negative_cache: Option<dns::NegativeCache>,
+    dns_timeout: Option<Duration>,
+    fallback_delay: Option<Duration>,
+    fwmark: Option<u32>,
+    happy_eyeballs_timeout: Option<Duration>,
+    host_overrides: HashMap<String, Vec<SocketAddr>>,
+    idna: bool,
+    interface: Option<String>,
+    interleave_families: bool,
+    ip_family_preference: IpFamilyPreference,
+    keep_alive_interval: Option<Duration>,
+    keep_alive_retries: Option<u32>,
+    keep_alive_timeout: Option<Duration>,
+    linger: Option<Duration>,
+    local_address_strict: bool,
+    local_addresses: Vec<IpAddr>,
+    local_port: Option<u16>,
+    local_port_range: Option<Range<u16>>,
+    max_addrs_attempted: Option<usize>,
+    max_concurrent_attempts: usize,
+    nodelay: bool,
+    observer: Option<Observer>,
+    recv_buffer_size: Option<usize>,
+    resolve_localhost: bool,
+    reuse_address: bool,
+    reuse_port: bool,
+    rfc6724_sort: bool,
+    round_robin: Option<Arc<Mutex<HashMap<String, usize>>>>,
+    send_buffer_size: Option<usize>,
+    shuffle_rng: Option<Arc<Mutex<dns::ShuffleRng>>>,
+    srv_service: Option<String>,
+    tcp_fastopen: bool,
+    tos: Option<u32>,
+    uri_override: Option<UriOverride>,
+    user_timeout: Option<Duration>,
+}
+
+impl Config {
+    // The local addresses of the same family as `remote`, in order.
+    fn local_addresses_for(&self, remote: &SocketAddr) -> Vec<IpAddr> {
+        self.local_addresses
+            .iter()
+            .cloned()
+            .filter(|ip| ip.is_ipv6() == remote.is_ipv6())
+            .collect()
+    }
+
+    fn local_address_for(&self, remote: &SocketAddr) -> Option<IpAddr> {
+        self.local_addresses
+            .iter()
+            .cloned()
+            .find(|ip| ip.is_ipv6() == remote.is_ipv6())
+    }
+
+    fn has_local_address(&self) -> bool {
+        !self.local_addresses.is_empty()
+    }
+
+    // The first local IPv4 and IPv6 addresses, if any.
+    fn local_address_families(&self) -> (Option<Ipv4Addr>, Option<Ipv6Addr>) {
+        let v4 = self.local_addresses.iter().filter_map(|ip| match *ip {
+            IpAddr::V4(ip) => Some(ip),
+            IpAddr::V6(_) => None,
+        }).next();
+        let v6 = self.local_addresses.iter().filter_map(|ip| match *ip {
+            IpAddr::V4(_) => None,
+            IpAddr::V6(ip) => Some(ip),
+        }).next();
+        (v4, v6)
+    }
+
+    // Order (or filter) the addresses of `host` by the configured family
+    // preference, after deduplicating, shuffling or rotating, and sorting
+    // them if enabled.
+    fn prefer(&self, host: &str, addrs: dns::IpAddrs) -> io::Result<dns::IpAddrs> {
+        let addrs = if self.dedupe_addresses {
+            addrs.deduped()
+        } else {
+            addrs
+        };
+        let addrs = match (&self.shuffle_rng, &self.round_robin) {
+            (&Some(ref rng), _) => addrs.shuffle_within_families(&mut rng.lock().unwrap()),
+            (_, &Some(ref counters)) => {
+                let mut counters = counters.lock().unwrap();
+                let next = counters.entry(normalize_host(host)).or_insert(0);
+                let addrs = addrs.rotate_within_families(*next);
+                *next = next.wrapping_add(1);
+                addrs
+            },
+            _ => addrs,
+        };
+        let addrs = if self.rfc6724_sort {
+            addrs.sort_rfc6724()
+        } else {
+            addrs
+        };
+        let addrs = if self.ip_family_preference == IpFamilyPreference::System {
+            addrs
+        } else {
+            let addrs = dns::IpAddrs::with_preference(addrs.collect(), self.ip_family_preference);
+            if addrs.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    format!("no resolved addresses match preference {:?}", self.ip_family_preference),
+                ));
+            }
+            addrs
+        };
+        if self.interleave_families {
+            Ok(addrs.interleaved())
+        } else {
+            Ok(addrs)
+        }
+    }
+}
+
+impl HttpConnector {
+    /// Construct a new HttpConnector.
+    ///
+    /// Takes number of DNS worker threads.
+    #[inline]
+    pub fn new(threads: usize) -> HttpConnector {
+        HttpConnector::new_with_handle_opt(threads, None)
+    }
+
+    /// Construct a new HttpConnector with a specific Tokio handle.
+    pub fn new_with_handle(threads: usize, handle: Handle) -> HttpConnector {
+        HttpConnector::new_with_handle_opt(threads, Some(handle))
+    }
+
+    /// Start building an `HttpConnector` that registers sockets with
+    /// `handle`, with chainable settings.
+    pub fn builder(handle: &Handle) -> HttpConnectorBuilder {
+        HttpConnectorBuilder {
+            connect_timeout: None,
+            handle: handle.clone(),
+            keepalive: None,
+            local_address: None,
+            nodelay: false,
+            scheme_policy: SchemePolicy::HttpOnly,
+            threads: 4,
+        }
+    }
+
+    fn new_with_handle_opt(threads: usize, handle: Option<Handle>) -> HttpConnector {
+        HttpConnector::new_with_resolver(GaiResolver::new(threads), handle)
+    }
+
+    /// Construct a new HttpConnector.
+    ///
+    /// Takes an executor to run blocking tasks on.
+    pub fn new_with_executor<E: 'static>(executor: E, handle: Option<Handle>) -> HttpConnector
+        where E: Executor<dns::GaiTask> + Send + Sync
+    {
+        HttpConnector::new_with_resolver(GaiResolver::new_with_executor(executor), handle)
+    }
+
+    /// Construct a new HttpConnector.
+    ///
+    /// Takes a thread pool to resolve hostnames on, which may be shared by
+    /// several connectors, and the Tokio handle to register sockets with.
+    /// Clones of the connector use the same pool.
+    pub fn new_with_pool(pool: CpuPool, handle: &Handle) -> HttpConnector {
+        HttpConnector::new_with_resolver(GaiResolver::new_with_pool(pool), Some(handle.clone()))
+    }
+
+    /// Resize the threadpool hostnames are resolved on to `threads`
+    /// threads.
+    ///
+    /// Lookups already started on the old pool complete normally. Clones
+    /// of the connector made before keep using the old pool.
+    ///
+    /// This fails for connectors constructed with a pool or an executor,
+    /// since the connector doesn't own them. See
+    /// [`GaiResolver::resize`](dns::GaiResolver::resize).
+    pub fn resize_dns_threads(&mut self, threads: usize) -> io::Result<()> {
+        Arc::make_mut(&mut self.resolver).resize(threads)
+    }
+
+    /// Set whether `AI_ADDRCONFIG` is passed to `getaddrinfo`.
+    ///
+    /// See [`GaiResolver::set_addr_config`](dns::GaiResolver::set_addr_config).
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn set_addr_config(&mut self, enabled: bool) {
+        Arc::make_mut(&mut self.resolver).set_addr_config(enabled);
+    }
+}
+
+/// A builder to configure an `HttpConnector`.
+///
+/// Each method is the same as the `HttpConnector` setter of the same
+/// name, and has the same default.
+#[derive(Debug)]
+pub struct HttpConnectorBuilder {
+    connect_timeout: Option<Duration>,
+    handle: Handle,
+    keepalive: Option<Duration>,
+    local_address: Option<IpAddr>,
+    nodelay: bool,
+    scheme_policy: SchemePolicy,
+    threads: usize,
+}
+
+impl HttpConnectorBuilder {
+    /// Set the number of DNS worker threads.
+    ///
+    /// Default is `4`.
+    pub fn threads(&mut self, threads: usize) -> &mut Self {
+        self.threads = threads;
+        self
+    }
+
+    /// See [`HttpConnector::set_keepalive`](HttpConnector::set_keepalive).
+    pub fn keepalive(&mut self, dur: Option<Duration>) -> &mut Self {
+        self.keepalive = dur;
+        self
+    }
+
+    /// See [`HttpConnector::set_local_address`](HttpConnector::set_local_address).
+    pub fn local_address(&mut self, addr: Option<IpAddr>) -> &mut Self {
+        self.local_address = addr;
+        self
+    }
+
+    /// See [`HttpConnector::enforce_http`](HttpConnector::enforce_http).
+    pub fn enforce_http(&mut self, is_enforced: bool) -> &mut Self {
+        self.scheme_policy = if is_enforced { SchemePolicy::HttpOnly } else { SchemePolicy::Any };
+        self
+    }
+
+    /// See [`HttpConnector::set_scheme_policy`](HttpConnector::set_scheme_policy).
+    pub fn scheme_policy(&mut self, policy: SchemePolicy) -> &mut Self {
+        self.scheme_policy = policy;
+        self
+    }
+
+    /// See [`HttpConnector::set_nodelay`](HttpConnector::set_nodelay).
+    pub fn nodelay(&mut self, nodelay: bool) -> &mut Self {
+        self.nodelay = nodelay;
+        self
+    }
+
+    /// See [`HttpConnector::set_connect_timeout`](HttpConnector::set_connect_timeout).
+    pub fn connect_timeout(&mut self, dur: Option<Duration>) -> &mut Self {
+        self.connect_timeout = dur;
+        self
+    }
+
+    /// Build the configured `HttpConnector`.
+    pub fn build(&self) -> HttpConnector {
+        let mut connector = HttpConnector::new_with_handle(self.threads, self.handle.clone());
+        connector.set_scheme_policy(self.scheme_policy);
+        connector.set_keepalive(self.keepalive);
+        connector.set_local_address(self.local_address);
+        connector.set_nodelay(self.nodelay);
+        connector.set_connect_timeout(self.connect_timeout);
+        connector
+    }
+}
+
+impl<R> HttpConnector<R> {
+    /// Construct a new HttpConnector.
+    ///
+    /// Takes a resolver to use for looking up hostnames.
+    pub fn new_with_resolver(resolver: R, handle: Option<Handle>) -> HttpConnector<R> {
+        HttpConnector {
+            config: Arc::new(Config {
+                allowed_schemes: Some(vec!["http".to_owned()]),
+                connect_deadline: None,
+                connect_limit: None,
+                connect_retries: 0,
+                connect_retry_delay: Duration::from_millis(0),
+                connect_timeout: None,
+                dedupe_addresses: true,
+                default_ports: default_ports(),
+                dns_cache: None,
+                dns_negative_cache: None,
+                dns_timeout: None,
+                fallback_delay: None,
+                fwmark: None,
+                happy_eyeballs_timeout: Some(Duration::from_millis(250)),
+                host_overrides: HashMap::new(),
+                idna: true,
+                interface: None,
+                interleave_families: false,
+                ip_family_preference: IpFamilyPreference::System,
+                keep_alive_interval: None,
+                keep_alive_retries: None,
+                keep_alive_timeout: None,
+                linger: None,
+                local_address_strict: false,
+                local_addresses: Vec::new(),
+                local_port: None,
+                local_port_range: None,
+                max_addrs_attempted: None,
+                max_concurrent_attempts: 2,
+                nodelay: false,
+                observer: None,
+                recv_buffer_size: None,
+                resolve_localhost: false,
+                reuse_address: false,
+                reuse_port: false,
+                rfc6724_sort: false,
+                round_robin: None,
+                send_buffer_size: None,
+                shuffle_rng: None,
+                srv_service: None,
+                tcp_fastopen: false,
+                tos: None,
+                uri_override: None,
+                user_timeout: None,
+            }),
+            handle,
+            resolver: Arc::new(resolver),
+        }
+    }
+
+    /// Option to enforce all `Uri`s have the `http` scheme.
+    ///
+    /// This is the same as setting the scheme policy to
+    /// `SchemePolicy::HttpOnly`, or to `SchemePolicy::Any` when disabled.
+    ///
+    /// Enabled by default.
+    #[inline]
+    pub fn enforce_http(&mut self, is_enforced: bool) {
+        let policy = if is_enforced { SchemePolicy::HttpOnly } else { SchemePolicy::Any };
+        self.set_scheme_policy(policy);
+    }
+
+    /// Set which of the common `Uri` schemes may be connected to.
+    ///
+    /// This replaces any schemes set with
+    /// [`set_allowed_schemes`](HttpConnector::set_allowed_schemes), and is
+    /// replaced by a later [`enforce_http`](HttpConnector::enforce_http),
+    /// which sets `HttpOnly` or `Any`. `https` URIs are connected to over
+    /// plain TCP, on port 443 unless they have a port.
+    ///
+    /// Default is `SchemePolicy::HttpOnly`.
+    #[inline]
+    pub fn set_scheme_policy(&mut self, policy: SchemePolicy) {
+        let schemes = match policy {
+            SchemePolicy::HttpOnly => Some(vec!["http".to_owned()]),
+            SchemePolicy::HttpAndHttps => Some(vec!["http".to_owned(), "https".to_owned()]),
+            SchemePolicy::Any => None,
+        };
+        self.set_allowed_schemes(schemes);
+    }
+
+    /// Set the exact list of `Uri` schemes that may be connected to.
+    ///
+    /// Connecting to a `Uri` with any other scheme fails right away. If
+    /// `None`, any scheme is allowed, though a `Uri` must still have one.
+    /// Schemes are compared case-insensitively.
+    ///
+    /// This replaces the schemes of an earlier
+    /// [`set_scheme_policy`](HttpConnector::set_scheme_policy) or
+    /// [`enforce_http`](HttpConnector::enforce_http), and is replaced by
+    /// either of them called later.
+    ///
+    /// Default is only `http`.
+    #[inline]
+    pub fn set_allowed_schemes(&mut self, schemes: Option<Vec<String>>) {
+        let schemes = schemes.map(|schemes| schemes.iter().map(|s| ascii_lowercase(s)).collect());
+        self.config_mut().allowed_schemes = schemes;
+    }
+
+    /// Set the port to connect to for URIs of `scheme` without a port.
+    ///
+    /// Ports for `http` (80) and `https` (443) are registered by default.
+    /// Connecting to a URI without a port, whose scheme has no registered
+    /// port, fails.
+    #[inline]
+    pub fn set_default_port(&mut self, scheme: &str, port: u16) {
+        self.config_mut().default_ports.insert(scheme.to_owned(), port);
+    }
+
+    /// Set the ports to connect to for URIs without a port, by scheme.
+    ///
+    /// These replace any ports set before, but are merged over the built-in
+    /// ports for `http` (80) and `https` (443), which can be overridden.
+    #[inline]
+    pub fn set_default_ports(&mut self, ports: HashMap<String, u16>) {
+        let mut merged = default_ports();
+        merged.extend(ports);
+        self.config_mut().default_ports = merged;
+    }
+
+    /// Set whether internationalized hostnames are converted to their
+    /// ASCII form before anything else is done with them.
+    ///
+    /// Each label holding non-ASCII characters is lowercased and encoded
+    /// with Punycode, as with [`Name::to_ascii`](dns::Name::to_ascii), so
+    /// that `b\u{fc}cher.example` is resolved, cached and matched against
+    /// host overrides as `xn--bcher-kva.example`. A host that can't be
+    /// converted fails to connect with `io::ErrorKind::InvalidInput`,
+    /// naming the label.
+    ///
+    /// Default is `true`.
+    #[inline]
+    pub fn set_idna(&mut self, idna: bool) {
+        self.config_mut().idna = idna;
+    }
+
+    /// Connect to `addrs` for `host`, instead of resolving it.
+    ///
+    /// Hosts are matched case-insensitively, ignoring a trailing dot.
+    /// Adding an override for the same host again replaces it. An
+    /// internationalized `host` is stored in its ASCII form, such as
+    /// `xn--bcher-kva.example`, which is also how URIs hold it. Connecting
+    /// to a host overridden with no addresses fails with
+    /// `io::ErrorKind::NotFound`.
+    #[inline]
+    pub fn add_host_override(&mut self, host: &str, addrs: Vec<IpAddr>) {
+        let addrs = addrs.into_iter().map(|ip| SocketAddr::new(ip, 0)).collect();
+        self.config_mut().host_overrides.insert(normalize_host(&ascii_form(host)), addrs);
+    }
+
+    /// Set the addresses to connect to for hosts, instead of resolving
+    /// them.
+    ///
+    /// An address with port `0` is connected to on the port of the URI.
+    /// Hosts are matched case-insensitively, ignoring a trailing dot, and
+    /// even IP addresses can be overridden. These replace any overrides
+    /// set before, including with `add_host_override`.
+    ///
+    /// Default is no overrides.
+    #[inline]
+    pub fn set_overrides(&mut self, overrides: HashMap<String, Vec<SocketAddr>>) {
+        self.config_mut().host_overrides = overrides
+            .into_iter()
+            .map(|(host, addrs)| (normalize_host(&ascii_form(&host)), addrs))
+            .collect();
+    }
+
+    /// Set a service to look up the `SRV` records of, and connect to the
+    /// targets they name instead of each hostname.
+    ///
+    /// The service is given with its protocol, such as `_http._tcp`, and
+    /// looked up before the hostname. Targets are tried in order of
+    /// priority, and randomly by weight, as in RFC 2782. If no records are
+    /// found, the hostname is resolved as usual, but if the lookup fails,
+    /// so does connecting. The resolver must support
+    /// [`Resolve::resolve_srv`](dns::Resolve::resolve_srv) for records to
+    /// be found.
+    ///
+    /// The lookup and each target are resolved like hostnames, with the
+    /// DNS timeout, caches and observer. An observer sees the lookup of
+    /// the service's name end without addresses.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_srv_service(&mut self, service: Option<String>) {
+        self.config_mut().srv_service = service;
+    }
+
+    /// Set a function to pick the addresses to connect to from the whole
+    /// `Uri` of each request, instead of resolving its host.
+    ///
+    /// The function is called before any other override, and before IP
+    /// addresses are parsed. If it returns `None`, the host is connected
+    /// to as usual. An address with port `0` is connected to on the port
+    /// of the URI, or the default port of its scheme.
+    ///
+    /// Default is no override.
+    #[inline]
+    pub fn set_uri_resolver_override(&mut self, f: Arc<Fn(&Uri) -> Option<dns::IpAddrs> + Send + Sync>) {
+        self.config_mut().uri_override = Some(UriOverride(f));
+    }
+
+    /// Cache resolved addresses, so hot hostnames aren't resolved again
+    /// for every new connection.
+    ///
+    /// Addresses are kept for `ttl`, and at most `capacity` hostnames are
+    /// cached, evicting the least recently used. If connecting to every
+    /// address of a host fails, its entry is dropped, so the host is
+    /// resolved again next time. The cache is shared with clones of this
+    /// connector. See [`CachingResolver`](dns::CachingResolver) to add
+    /// caching to a resolver directly.
+    ///
+    /// Default is no caching.
+    #[inline]
+    pub fn set_dns_cache(&mut self, ttl: Duration, capacity: usize) {
+        self.config_mut().dns_cache = Some(dns::Cache::new(ttl, capacity));
+    }
+
+    /// Remember errors resolving a hostname for `ttl`, so connecting to it
+    /// again fails right away instead of asking the resolver again.
+    ///
+    /// Only errors of kind `io::ErrorKind::NotFound` are remembered, such
+    /// as a hostname that doesn't exist, so a timeout or a failure of the
+    /// resolver itself is asked about again the next time. Host overrides
+    /// are used even for a host with a remembered error. The cache is
+    /// shared with clones of this connector.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_dns_negative_cache(&mut self, ttl: Option<Duration>) {
+        self.config_mut().dns_negative_cache = ttl.map(dns::NegativeCache::new);
+    }
+
+    /// Set that all sockets have `SO_KEEPALIVE` set with the supplied duration.
+    ///
+    /// If `None`, the option will not be set.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_keepalive(&mut self, dur: Option<Duration>) {
+        self.config_mut().keep_alive_timeout = dur;
+    }
+
+    /// Set the time between keepalive probes (`TCP_KEEPINTVL`), in whole
+    /// seconds.
+    ///
+    /// This requires a duration set with `set_keepalive`, and is only
+    /// supported on Linux; otherwise connecting fails.
+    ///
+    /// Default is `None`, which keeps the system default.
+    #[inline]
+    pub fn set_keepalive_interval(&mut self, dur: Option<Duration>) {
+        self.config_mut().keep_alive_interval = dur;
+    }
+
+    /// Set how many unanswered keepalive probes drop the connection
+    /// (`TCP_KEEPCNT`).
+    ///
+    /// This requires a duration set with `set_keepalive`, and is only
+    /// supported on Linux; otherwise connecting fails.
+    ///
+    /// Default is `None`, which keeps the system default.
+    #[inline]
+    pub fn set_keepalive_retries(&mut self, retries: Option<u32>) {
+        self.config_mut().keep_alive_retries = retries;
+    }
+
+    /// Set how long sent data may stay unacknowledged before the
+    /// connection is dropped (`TCP_USER_TIMEOUT`), in milliseconds.
+    ///
+    /// Unlike keepalive, this only notices a dead peer while there's data
+    /// to send. It's only supported on Linux; otherwise connecting fails.
+    ///
+    /// Default is `None`, which keeps the system default.
+    #[inline]
+    pub fn set_user_timeout(&mut self, dur: Option<Duration>) {
+        self.config_mut().user_timeout = dur;
+    }
+
+    /// Set the keepalive idle time, probe interval and probe count at once.
+    ///
+    /// Unlike the separate setters, on platforms other than Linux this
+    /// only sets the idle time, instead of failing to connect.
+    #[inline]
+    pub fn set_keepalive_params(&mut self, idle: Duration, interval: Duration, retries: u32) {
+        self.set_keepalive(Some(idle));
+        if cfg!(any(target_os = "android", target_os = "linux")) {
+            self.set_keepalive_interval(Some(interval));
+            self.set_keepalive_retries(Some(retries));
+        }
+    }
+
+    /// Set that all sockets have `SO_LINGER` set with the supplied duration.
+    ///
+    /// With a duration, closing a socket waits up to that long for unsent
+    /// data to be sent. A duration of zero instead resets the connection
+    /// on close, which skips `TIME_WAIT` but discards unsent data. This
+    /// only affects closing, so it doesn't change how keepalive probes
+    /// detect a dead peer while the connection is open.
+    ///
+    /// Default is `None`, which keeps the system default.
+    #[inline]
+    pub fn set_linger(&mut self, dur: Option<Duration>) {
+        self.config_mut().linger = dur;
+    }
+
+    /// Set that all sockets have `SO_NODELAY` set to the supplied value `nodelay`.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn set_nodelay(&mut self, nodelay: bool) {
+        self.config_mut().nodelay = nodelay;
+    }
+
+    /// Set whether sockets have `TCP_FASTOPEN_CONNECT` set.
+    ///
+    /// With a cookie from an earlier connection to the same server, the
+    /// first request bytes are then sent along with the SYN, saving a round
+    /// trip. This is only supported on Linux, and ignored elsewhere.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn set_tcp_fastopen(&mut self, fastopen: bool) {
+        self.config_mut().tcp_fastopen = fastopen;
+    }
+
+    /// Set the `SO_MARK` of sockets, for policy routing such as with
+    /// `ip rule add fwmark`.
+    ///
+    /// This is only supported on Linux, and needs `CAP_NET_ADMIN`;
+    /// otherwise connecting fails.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_fwmark(&mut self, mark: Option<u32>) {
+        self.config_mut().fwmark = mark;
+    }
+
+    /// Set the `IP_TOS` (or `IPV6_TCLASS`) of sockets, to mark their
+    /// packets with a DSCP value.
+    ///
+    /// The DSCP is the upper 6 bits, such as `0xb8` for expedited
+    /// forwarding. Values above 255, or platforms that don't support the
+    /// option, fail to connect.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_tos(&mut self, tos: Option<u32>) {
+        self.config_mut().tos = tos;
+    }
+
+    /// Sets the value of the `SO_SNDBUF` option on the socket.
+    ///
+    /// The option is set before connecting, and the OS may round the
+    /// size, or clamp it to a system-wide limit.
+    ///
+    /// If `None`, the OS default is used.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_send_buffer_size(&mut self, size: Option<usize>) {
+        self.config_mut().send_buffer_size = size;
+    }
+
+    /// Sets the value of the `SO_RCVBUF` option on the socket.
+    ///
+    /// The option is set before connecting, and the OS may round the
+    /// size, or clamp it to a system-wide limit.
+    ///
+    /// If `None`, the OS default is used.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_recv_buffer_size(&mut self, size: Option<usize>) {
+        self.config_mut().recv_buffer_size = size;
+    }
+
+    /// Set whether `SO_REUSEADDR` is set on sockets before binding.
+    ///
+    /// When connections from a fixed local address churn quickly, this
+    /// allows binding ports still in `TIME_WAIT`. It is only set on sockets
+    /// bound to a local address, port or port range.
+    ///
+    /// On Windows, `SO_REUSEADDR` also allows binding a port another
+    /// socket is actively using, so unlike elsewhere it isn't set
+    /// implicitly for a local port range.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn set_reuse_address(&mut self, reuse: bool) {
+        self.config_mut().reuse_address = reuse;
+    }
+
+    /// Set whether `SO_REUSEPORT` is set on sockets before binding.
+    ///
+    /// Like `set_reuse_address`, this is only set on sockets bound to a
+    /// local address, port or port range. It is only supported on Linux and BSDs;
+    /// elsewhere connecting fails.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn set_reuse_port(&mut self, reuse: bool) {
+        self.config_mut().reuse_port = reuse;
+    }
+
+    /// Set that all sockets are bound to a network interface, such as
+    /// `"eth0"`, before connection.
+    ///
+    /// Unlike binding a local address, this forces traffic out of the
+    /// interface regardless of the routing table, and keeps working if
+    /// the interface's addresses change. It composes with
+    /// `set_local_address`: the interface is bound first.
+    ///
+    /// This uses `SO_BINDTODEVICE` and is only supported on Linux, where it
+    /// may also require the `CAP_NET_RAW` capability. On other platforms,
+    /// connecting fails with an `io::ErrorKind::Other` error.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_interface(&mut self, iface: Option<String>) {
+        self.config_mut().interface = iface;
+    }
+
+    /// Set that all sockets are bound to the configured address before connection.
+    ///
+    /// Resolved addresses of the other family are skipped, unless none are
+    /// of the same family, see
+    /// [`set_local_address_strict`](HttpConnector::set_local_address_strict).
+    /// To bind both IPv4 and IPv6 connections, use
+    /// [`set_local_addresses`](HttpConnector::set_local_addresses).
+    ///
+    /// If `None`, the sockets will not be bound.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_local_address(&mut self, addr: Option<IpAddr>) {
+        self.set_local_address_candidates(addr.into_iter().collect());
+    }
+
+    /// Set that all sockets are bound to the configured IPv4 or IPv6
+    /// address, depending on the family of the remote address.
+    ///
+    /// If only one of the addresses is set, resolved addresses of the
+    /// other family are skipped, as with `set_local_address`.
+    ///
+    /// If both are `None`, the sockets will not be bound.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_local_addresses(&mut self, addr_ipv4: Option<Ipv4Addr>, addr_ipv6: Option<Ipv6Addr>) {
+        let addrs = addr_ipv4
+            .map(IpAddr::V4)
+            .into_iter()
+            .chain(addr_ipv6.map(IpAddr::V6))
+            .collect();
+        self.set_local_address_candidates(addrs);
+    }
+
+    /// Set local addresses to try binding sockets to, in order.
+    ///
+    /// Each socket is bound to the first address of the same family as
+    /// the remote address that can be bound, so an address that is no
+    /// longer assigned to the host is skipped. Resolved addresses of a
+    /// family without any local address are skipped, as with
+    /// `set_local_address`. With a
+    /// [local port range](HttpConnector::set_local_port_range), only the
+    /// first address of the family is bound.
+    ///
+    /// If empty, the sockets will not be bound.
+    ///
+    /// Default is empty.
+    #[inline]
+    pub fn set_local_address_candidates(&mut self, addrs: Vec<IpAddr>) {
+        self.config_mut().local_addresses = addrs;
+    }
+
+    /// Set whether connecting fails when no resolved address is of the
+    /// family of a local address.
+    ///
+    /// If strict, such a connect fails with `io::ErrorKind::AddrNotAvailable`.
+    /// Otherwise, the addresses are connected to without binding the local
+    /// address. Either way, addresses of the other family are skipped when
+    /// some address is of the family of a local address.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn set_local_address_strict(&mut self, strict: bool) {
+        self.config_mut().local_address_strict = strict;
+    }
+
+    /// Set that all sockets are bound to a fixed local port before
+    /// connection.
+    ///
+    /// The port is bound on the local address of the remote address
+    /// family if one was set, otherwise on the unspecified address. Only
+    /// one connection to each remote address can use the port at a time,
+    /// so concurrent connects to the same address fail with
+    /// `io::ErrorKind::AddrInUse`, and reconnecting while an earlier
+    /// connection is in `TIME_WAIT` needs
+    /// [`set_reuse_address`](HttpConnector::set_reuse_address). A
+    /// [local port range](HttpConnector::set_local_port_range) takes
+    /// precedence over this.
+    ///
+    /// If `None`, the OS picks an ephemeral port.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_local_port(&mut self, port: Option<u16>) {
+        self.config_mut().local_port = port;
+    }
+
+    /// Set that all sockets are bound to a local port in `ports` before
+    /// connection.
+    ///
+    /// Ports are tried in order, skipping those already in use, and
+    /// `SO_REUSEADDR` is set so that ports in `TIME_WAIT` can be reused,
+    /// except on Windows, see
+    /// [`set_reuse_address`](HttpConnector::set_reuse_address).
+    /// If a local address of the remote address family was set, the port
+    /// is bound on that address, otherwise on the unspecified address.
+    ///
+    /// If `None`, the OS picks an ephemeral port.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_local_port_range(&mut self, ports: Option<Range<u16>>) {
+        self.config_mut().local_port_range = ports;
+    }
+
+    /// Set a function giving the deadline of each connect, as an `Instant`.
+    ///
+    /// The function is called when a connect starts, and if it returns a
+    /// deadline, resolving and connecting together must finish by then, or
+    /// the connect fails with `io::ErrorKind::TimedOut`. A deadline already
+    /// passed fails right away. This applies along with the durations of
+    /// `set_dns_timeout` and `set_connect_timeout`, which may fail sooner.
+    ///
+    /// Default is no deadline.
+    #[inline]
+    pub fn set_connect_deadline_fn(&mut self, f: Arc<Fn() -> Option<Instant> + Send + Sync>) {
+        self.config_mut().connect_deadline = Some(DeadlineFn(f));
+    }
+
+    /// Set a timeout for resolving a hostname.
+    ///
+    /// When resolving takes longer, the lookup is abandoned and the
+    /// connect fails with `io::ErrorKind::TimedOut`. This is independent
+    /// of the connect timeout.
+    ///
+    /// If `None`, resolving waits until the resolver gives up.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_dns_timeout(&mut self, dur: Option<Duration>) {
+        self.config_mut().dns_timeout = dur;
+    }
+
+    /// Set a timeout for each attempt to connect to a resolved address.
+    ///
+    /// If a hostname resolves to multiple addresses, the timeout applies
+    /// to each address separately. When an attempt times out, the next
+    /// address is tried, and if none remain, the connect fails with
+    /// `io::ErrorKind::TimedOut`. DNS resolution is not included.
+    ///
+    /// If `None`, an attempt waits until the OS gives up.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_connect_timeout(&mut self, dur: Option<Duration>) {
+        self.config_mut().connect_timeout = dur;
+    }
+
+    /// Set how many times to retry connecting to each resolved address.
+    ///
+    /// When connecting to an address is refused, reset or aborted, it is
+    /// retried up to `retries` times before moving on to the next address.
+    /// The wait before each retry starts at `base_delay` and doubles every
+    /// time. The error of the last attempt reports how many were made.
+    ///
+    /// Default is `0` retries.
+    #[inline]
+    pub fn set_connect_retries(&mut self, retries: usize, base_delay: Duration) {
+        let config = self.config_mut();
+        config.connect_retries = retries;
+        config.connect_retry_delay = base_delay;
+    }
+
+    /// Set the maximum number of resolved addresses to try connecting to.
+    ///
+    /// Once that many addresses failed, the connect fails without trying
+    /// the rest, and its `ConnectError` notes how many were skipped.
+    /// Retries of the same address don't count.
+    ///
+    /// Default is `None`, trying every address.
+    #[inline]
+    pub fn set_max_addrs_attempted(&mut self, max: Option<usize>) {
+        self.config_mut().max_addrs_attempted = max;
+    }
+
+    /// Set how many addresses may be connecting at once, across both
+    /// address families.
+    ///
+    /// When the fallback delay or Happy Eyeballs timeout passes with this
+    /// many attempts in flight, the next address waits for one of them to
+    /// fail. A value of `0` is taken as `1`.
+    ///
+    /// Default is `2`.
+    #[inline]
+    pub fn set_max_concurrent_attempts(&mut self, n: usize) {
+        self.config_mut().max_concurrent_attempts = n;
+    }
+
+    /// Set how many connects may be in progress at once.
+    ///
+    /// The limit is shared with clones of this connector made after it's
+    /// set. A connect waits its turn before resolving, in the order
+    /// connects were first polled, and keeps it until it connects, fails
+    /// or is dropped. A value of `0` is taken as `1`.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_max_pending_connects(&mut self, max: Option<usize>) {
+        self.config_mut().connect_limit = max.map(|max| ConnectLimit::new(cmp::max(max, 1)));
+    }
+
+    /// Get how many connects are in progress under the limit set with
+    /// [`set_max_pending_connects`](HttpConnector::set_max_pending_connects).
+    ///
+    /// This is `0` without a limit.
+    pub fn pending_connects(&self) -> usize {
+        self.config.connect_limit.as_ref().map_or(0, ConnectLimit::in_use)
+    }
+
+    /// Get how many connects are waiting for their turn under the limit
+    /// set with
+    /// [`set_max_pending_connects`](HttpConnector::set_max_pending_connects).
+    ///
+    /// This is `0` without a limit.
+    pub fn queued_connects(&self) -> usize {
+        self.config.connect_limit.as_ref().map_or(0, ConnectLimit::waiting)
+    }
+
+    /// Set a delay after which the next address is tried, while the
+    /// previous attempts are still connecting.
+    ///
+    /// Without a delay, the next address of a family is only tried after
+    /// connecting to the previous one failed. With one, slow attempts are
+    /// kept alive, and the first to succeed is used. Any others are
+    /// dropped.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_fallback_delay(&mut self, delay: Option<Duration>) {
+        self.config_mut().fallback_delay = delay;
+    }
+
+    /// Set timeout for [RFC 8305 (Happy Eyeballs)][RFC 8305] algorithm.
+    ///
+    /// If hostname resolves to both IPv4 and IPv6 addresses and connection
+    /// cannot be established using preferred address family before timeout
+    /// elapses, then connector will in parallel attempt connection using other
+    /// address family.
+    ///
+    /// If `None`, parallel connection attempts are disabled, and addresses
+    /// are tried one after another.
+    ///
+    /// Default is 250 milliseconds, the Connection Attempt Delay RFC 8305
+    /// recommends.
+    ///
+    /// [RFC 8305]: https://tools.ietf.org/html/rfc8305
+    #[inline]
+    pub fn set_happy_eyeballs_timeout(&mut self, dur: Option<Duration>) {
+        self.config_mut().happy_eyeballs_timeout = dur;
+    }
+
+    /// Set whether resolved addresses are tried in a random order.
+    ///
+    /// This spreads connections across a pool of servers behind one
+    /// hostname. Addresses are only shuffled among those of the same
+    /// family, so that an IP family preference still applies.
+    ///
+    /// This is the same as `set_addr_order` with `AddrOrder::Shuffle` or
+    /// `AddrOrder::Preserve`.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn set_shuffle_addresses(&mut self, shuffle: bool) {
+        self.set_addr_order(if shuffle { AddrOrder::Shuffle } else { AddrOrder::Preserve });
+    }
+
+    /// Set whether `localhost` is resolved like any other hostname.
+    ///
+    /// Otherwise, it's connected to on `::1` and then `127.0.0.1` without
+    /// asking the resolver, matching it case-insensitively and ignoring a
+    /// trailing dot. Overrides and the IP family preference still apply.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn set_resolve_localhost(&mut self, resolve: bool) {
+        self.config_mut().resolve_localhost = resolve;
+    }
+
+    /// Set the order resolved addresses are tried in.
+    ///
+    /// Like shuffling, round robin only reorders addresses among those of
+    /// the same family, so that an IP family preference still applies. Its
+    /// counters are shared by clones of the connector, and reset when the
+    /// order is set again. Either way, each address is still tried at most
+    /// once per connect.
+    ///
+    /// Default is `AddrOrder::Preserve`.
+    #[inline]
+    pub fn set_addr_order(&mut self, order: AddrOrder) {
+        let config = self.config_mut();
+        config.shuffle_rng = None;
+        config.round_robin = None;
+        match order {
+            AddrOrder::Preserve => (),
+            AddrOrder::Shuffle => config.shuffle_rng = Some(Arc::new(Mutex::new(dns::ShuffleRng::from_time()))),
+            AddrOrder::RoundRobin => config.round_robin = Some(Arc::new(Mutex::new(HashMap::new()))),
+        }
+    }
+
+    /// Shuffle resolved addresses with a generator seeded by `seed`.
+    ///
+    /// This is like `set_shuffle_addresses(true)`, but the generator is
+    /// shared by clones of the connector, so the same connects in the same
+    /// order shuffle addresses the same way, such as in tests.
+    ///
+    /// Default is shuffling with a seed taken from the current time, if
+    /// shuffling is turned on.
+    #[inline]
+    pub fn set_shuffle_seed(&mut self, seed: u64) {
+        let config = self.config_mut();
+        config.round_robin = None;
+        config.shuffle_rng = Some(Arc::new(Mutex::new(dns::ShuffleRng::new(seed))));
+    }
+
+    /// Set whether addresses are sorted by RFC 6724 destination address
+    /// selection before connecting.
+    ///
+    /// This tries first the addresses that best match the source address
+    /// the system would use to reach each one, such as a unique local IPv6
+    /// address when there's no global IPv6 source, and tries addresses
+    /// without a route last. Each source is found by connecting a UDP
+    /// socket, which sends nothing, and its prefix length is looked up in
+    /// the addresses of the machine's interfaces. Addresses that rank the
+    /// same keep their order, so shuffling still spreads connects across
+    /// them, and an `IpFamilyPreference` other than `System` still applies
+    /// after sorting. If no source can be found at all, the order is kept.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn set_rfc6724_sort(&mut self, sort: bool) {
+        self.config_mut().rfc6724_sort = sort;
+    }
+
+    /// Set whether repeated addresses are removed before connecting.
+    ///
+    /// Some resolvers return the same address more than once, which would
+    /// otherwise be tried again after failing. The first of each address is
+    /// kept, so the order addresses are tried in stays the same.
+    ///
+    /// Default is `true`.
+    #[inline]
+    pub fn set_dedupe_addresses(&mut self, dedupe: bool) {
+        self.config_mut().dedupe_addresses = dedupe;
+    }
+
+    /// Set whether resolved addresses alternate between IPv6 and IPv4.
+    ///
+    /// Without Happy Eyeballs, addresses are tried one after another, so a
+    /// broken path for one family delays every address of the other behind
+    /// it. Alternating, starting with the family of the first address, is a
+    /// cheaper way to reach a working family sooner. The order within each
+    /// family is kept, and this applies after `set_ip_family_preference`.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn set_interleave_families(&mut self, interleave: bool) {
+        self.config_mut().interleave_families = interleave;
+    }
+
+    /// Set an observer to be told about resolving and connecting, such as
+    /// to measure how long each takes.
+    ///
+    /// Default is no observer.
+    #[inline]
+    pub fn set_observer(&mut self, observer: Arc<ConnectObserver>) {
+        self.config_mut().observer = Some(Observer(observer));
+    }
+
+    /// Set a callback to be called with each resolve and connect event.
+    ///
+    /// This is a shorthand for an observer, and replaces any observer set
+    /// with `set_observer`. The callback is called from within polling the
+    /// connect future, so it must be quick and never block.
+    ///
+    /// Default is no callback.
+    #[inline]
+    pub fn set_connect_callback(&mut self, callback: Arc<Fn(ConnectEvent) + Send + Sync>) {
+        self.set_observer(Arc::new(CallbackObserver(move |event: ConnectEvent| callback(event))));
+    }
+
+    /// Set which address families to connect to, and in which order.
+    ///
+    /// The preference is applied to resolved addresses before connecting.
+    /// With `Ipv4Only` or `Ipv6Only`, if no address of that family was
+    /// resolved, the connect fails.
+    ///
+    /// Default is `IpFamilyPreference::System`.
+    #[inline]
+    pub fn set_ip_family_preference(&mut self, pref: IpFamilyPreference) {
+        self.config_mut().ip_family_preference = pref;
+    }
+
+    // private
+
+    fn config_mut(&mut self) -> &mut Config {
+        // If the are HttpConnecting futures in flight, or clones of this
+        // connector, this will clone the inner config. So mutating the
+        // config won't ever affect previous futures or clones.
+        Arc::make_mut(&mut self.config)
+    }
+}
+
+// R: Clone is not required, the resolver is shared behind an `Arc`.
+impl<R> Clone for HttpConnector<R> {
+    fn clone(&self) -> HttpConnector<R> {
+        HttpConnector {
+            config: self.config.clone(),
+            handle: self.handle.clone(),
+            resolver: self.resolver.clone(),
+        }
+    }
+}
+
+impl<R> fmt::Debug for HttpConnector<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Only the most useful settings, leaving out the resolver and handle.
+        let config = &self.config;
+        let enforce_http = config
+            .allowed_schemes
+            .as_ref()
+            .map_or(false, |schemes| schemes.len() == 1 && schemes[0] == "http");
+        f.debug_struct("HttpConnector")
+            .field("allowed_schemes", &config.allowed_schemes)
+            .field("connect_timeout", &config.connect_timeout)
+            .field("enforce_http", &enforce_http)
+            .field("happy_eyeballs_timeout", &config.happy_eyeballs_timeout)
+            .field("keep_alive_timeout", &config.keep_alive_timeout)
+            .field("local_address", &config.local_addresses.first())
+            .field("local_addresses", &config.local_addresses)
+            .field("nodelay", &config.nodelay)
+            .finish()
+    }
+}
+
+impl<R> Connect for HttpConnector<R>
+where
+    R: Resolve + Send + Sync + 'static,
+    R::Future: Send,
+{
+    type Transport = TcpStream;
+    type Error = io::Error;
+    type Future = HttpConnecting<R>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        trace!(
+            "Http::connect; scheme={}, host={}, port={:?}",
+            dst.scheme(),
+            dst.host(),
+            dst.port(),
+        );
+
+        let scheme = match dst.uri.scheme_part() {
+            Some(scheme) => scheme.as_str(),
+            None => return self.invalid_url(InvalidUrl::MissingScheme),
+        };
+        if let Some(ref allowed) = self.config.allowed_schemes {
+            let scheme = ascii_lowercase(scheme);
+            if !allowed.iter().any(|s| *s == scheme) {
+                return self.invalid_url(InvalidUrl::SchemeNotAllowed(scheme));
+            }
+        }
+
+        let host = match dst.uri.host() {
+            Some(s) => s,
+            None => return self.invalid_url(InvalidUrl::MissingAuthority),
+        };
+        let port = match dst.uri.port() {
+            Some(port) => port,
+            None => match self.config.default_ports.get(dst.scheme()) {
+                Some(&port) => port,
+                None => return self.invalid_url(InvalidUrl::UnknownPort(dst.scheme().to_owned())),
+            },
+        };
+        // Only kept around if something wants to look at it.
+        let uri = self.config.uri_override.as_ref().map(|_| dst.uri.clone());
+        // A bracketed host can only be an IPv6 address, so don't try to
+        // resolve one that doesn't parse, such as with an unknown zone.
+        if host.starts_with('[') && dns::IpAddrs::try_parse(host, port).is_none() {
+            return self.invalid_url(InvalidUrl::InvalidIpv6(host.to_owned()));
+        }
+
+        self.connecting(host.into(), port, uri)
+    }
+}
+
+// The ASCII form of an internationalized `host`, or `host` itself if it
+// has none.
+fn ascii_form(host: &str) -> String {
+    idna::to_ascii(host).unwrap_or_else(|_| host.to_owned())
+}
+
+impl<R: Resolve> HttpConnector<R> {
+    fn connecting(&self, host: String, port: u16, uri: Option<Uri>) -> HttpConnecting<R> {
+        HttpConnecting {
+            config: self.config.clone(),
+            state: State::Lazy(self.resolver.clone(), host, port, uri),
+            handle: self.handle.clone(),
+            dns_started: None,
+            connect_started: None,
+            deadline: self.config.connect_deadline.as_ref().and_then(|f| (f.0)()).map(Delay::new),
+            permit: self.config.connect_limit.as_ref().map(ConnectLimit::acquire),
+        }
+    }
+
+    fn invalid_url(&self, err: InvalidUrl) -> HttpConnecting<R> {
+        HttpConnecting {
+            config: self.config.clone(),
+            state: State::Error(Some(io::Error::new(io::ErrorKind::InvalidInput, err))),
+            handle: self.handle.clone(),
+            dns_started: None,
+            connect_started: None,
+            deadline: None,
+            permit: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum InvalidUrl {
+    MissingScheme,
+    SchemeNotAllowed(String),
+    MissingAuthority,
+    UnknownPort(String),
+    InvalidIpv6(String),
+    InvalidIdna(String),
+}
+
+impl fmt::Display for InvalidUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InvalidUrl::SchemeNotAllowed(ref scheme) => {
+                write!(f, "invalid URL, scheme {:?} is not allowed", scheme)
+            },
+            InvalidUrl::UnknownPort(ref scheme) => {
+                write!(f, "invalid URL, missing port and no default port for scheme {:?}", scheme)
+            },
+            InvalidUrl::InvalidIpv6(ref host) => {
+                write!(f, "invalid URL, {} is not an IPv6 address with a known zone", host)
+            },
+            InvalidUrl::InvalidIdna(ref label) => {
+                write!(f, "invalid URL, host label {:?} has no ASCII form", label)
+            },
+            _ => f.write_str(self.description()),
+        }
+    }
+}
+
+impl StdError for InvalidUrl {
+    fn description(&self) -> &str {
+        match *self {
+            InvalidUrl::MissingScheme => "invalid URL, missing scheme",
+            InvalidUrl::SchemeNotAllowed(_) => "invalid URL, scheme is not allowed",
+            InvalidUrl::MissingAuthority => "invalid URL, missing domain",
+            InvalidUrl::UnknownPort(_) => "invalid URL, missing port",
+            InvalidUrl::InvalidIpv6(_) => "invalid URL, invalid IPv6 address",
+            InvalidUrl::InvalidIdna(_) => "invalid URL, invalid internationalized host",
+        }
+    }
+}
+
+/// The error when connecting to every resolved address of a host failed.
+///
+/// This is the inner error of the `io::Error` returned by `HttpConnector`,
+/// which has the kind of the last attempt's error. It can be retrieved
+/// with `get_ref()` and `downcast_ref::<ConnectError>()`.
+#[derive(Debug)]
+pub struct ConnectError {
+    attempts: Vec<(SocketAddr, io::Error)>,
+    host: String,
+    port: u16,
+    skipped: usize,
+    timing: Option<ConnectTiming>,
+}
+
+impl ConnectError {
+    /// Get each address that was tried, with the error connecting to it.
+    pub fn attempts(&self) -> &[(SocketAddr, io::Error)] {
+        &self.attempts
+    }
+
+    /// Get the host that was connected to.
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    /// Get the port that was connected to.
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// Get how many addresses weren't tried, because of
+    /// [`set_max_addrs_attempted`](HttpConnector::set_max_addrs_attempted).
+    pub fn skipped(&self) -> usize {
+        self.skipped
+    }
+
+    /// Get how long resolving and connecting took before failing.
+    pub fn timing(&self) -> Option<ConnectTiming> {
+        self.timing
+    }
+}
+
+impl fmt::Display for ConnectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("failed to connect")?;
+        if !self.host.is_empty() {
+            write!(f, " to {}:{}", self.host, self.port)?;
+        }
+        for (i, &(ref addr, ref err)) in self.attempts.iter().enumerate() {
+            let sep = if i == 0 { ": " } else { ", " };
+            write!(f, "{}{} ({})", sep, addr, err)?;
+        }
+        match self.skipped {
+            0 => (),
+            1 => f.write_str(", and 1 more address not attempted")?,
+            n => write!(f, ", and {} more addresses not attempted", n)?,
+        }
+        match self.timing {
+            Some(ref timing) => write!(f, "; {}", timing),
+            None => Ok(()),
+        }
+    }
+}
+
+impl From<ConnectError> for io::Error {
+    fn from(err: ConnectError) -> io::Error {
+        let kind = err.attempts
+            .last()
+            .map(|&(_, ref e)| e.kind())
+            .unwrap_or(io::ErrorKind::Other);
+        io::Error::new(kind, err)
+    }
+}
+
+impl StdError for ConnectError {
+    fn description(&self) -> &str {
+        "failed to connect to any address"
+    }
+}
+/// A Future representing work to connect to a URL.
+#[must_use = "futures do nothing unless polled"]
+pub struct HttpConnecting<R: Resolve = GaiResolver> {
+    config: Arc<Config>,
+    state: State<R>,
+    handle: Option<Handle>,
+    // When resolving and connecting started, for `Connected`'s timings.
+    dns_started: Option<Instant>,
+    connect_started: Option<Instant>,
+    // From `set_connect_deadline_fn`.
+    deadline: Option<Delay>,
+    // Waits for, then holds, a turn with `set_max_pending_connects`.
+    permit: Option<Acquire>,
+}
+
+// A lookup of `name` with the resolver, timed out after the DNS timeout,
+// and reported to the observer and the negative cache.
+struct Lookup<F> {
+    future: F,
+    name: Name,
+    started: Instant,
+    timeout: Option<Delay>,
+}
+
+enum SrvTarget<F> {
+    Resolving(Lookup<F>),
+    Resolved(Vec<SocketAddr>),
+}
+
+enum State<R: Resolve> {
+    Lazy(Arc<R>, String, u16, Option<Uri>),
+    Resolving(Lookup<R::Future>),
+    SrvResolving(Lookup<dns::SrvFuture>, Arc<R>, Name),
+    SrvTargets(Vec<SrvTarget<R::Future>>, Name),
+    Connecting(ConnectingTcp, Name),
+    Error(Option<io::Error>),
+}
+
+impl<R: Resolve> Future for HttpConnecting<R> {
+    type Item = (TcpStream, Connected);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Some(ref mut deadline) = self.deadline {
+            // Checked first, so a passed deadline fails without a timer.
+            let passed = deadline.deadline() <= Instant::now() || match deadline.poll() {
+                Ok(Async::Ready(())) => true,
+                Ok(Async::NotReady) => false,
+                Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+            };
+            if passed {
+                self.permit = None;
+                return Err(io::Error::new(io::ErrorKind::TimedOut, "connect deadline passed"));
+            }
+        }
+        if let Some(ref mut permit) = self.permit {
+            if let Async::NotReady = permit.poll() {
+                return Ok(Async::NotReady);
+            }
+        }
+        let polled = self.poll_connect();
+        match polled {
+            Ok(Async::NotReady) => (),
+            // Done either way, so let the next connect have a turn.
+            _ => self.permit = None,
+        }
+        polled
+    }
+}
+
+impl<R: Resolve> HttpConnecting<R> {
+    fn poll_connect(&mut self) -> Poll<(TcpStream, Connected), io::Error> {
+        loop {
+            let state;
+            match self.state {
+                State::Lazy(ref resolver, ref mut host, port, ref mut uri) => {
+                    if self.config.idna && !idna::is_ascii(host) {
+                        *host = idna::to_ascii(host).map_err(|label| {
+                            io::Error::new(io::ErrorKind::InvalidInput, InvalidUrl::InvalidIdna(label))
+                        })?;
+                    }
+                    let overridden = match (uri.take(), &self.config.uri_override) {
+                        (Some(uri), &Some(ref f)) => (f.0)(&uri).map(|addrs| addrs.collect()),
+                        _ => None,
+                    };
+                    let config = &self.config;
+                    let overridden = overridden.or_else(|| {
+                        config.host_overrides.get(&normalize_host(host)).cloned()
+                    });
+                    if let Some(addrs) = overridden {
+                        let addrs = addrs
+                            .into_iter()
+                            .map(|addr| match addr.port() {
+                                0 => SocketAddr::new(addr.ip(), port),
+                                _ => addr,
+                            })
+                            .collect();
+                        state = State::Connecting(ConnectingTcp::new(
+                            self.config.prefer(host, dns::IpAddrs::new(addrs))?,
+                            self.config.clone(),
+                        ), Name::new(mem::replace(host, String::new()), port));
+                    } else if let Some(addrs) = dns::IpAddrs::try_parse(host, port) {
+                        // If the host is already an IP addr (v4 or v6),
+                        // skip resolving the dns and start connecting right away.
+                        state = State::Connecting(ConnectingTcp::new(
+                            self.config.prefer(host, addrs)?,
+                            self.config.clone(),
+                        ), Name::new(mem::replace(host, String::new()), port));
+                    } else if !self.config.resolve_localhost && normalize_host(host) == "localhost" {
+                        let addrs = vec![
+                            SocketAddr::new(Ipv6Addr::new(0, 0, 0, 0, 0, 0, 0, 1).into(), port),
+                            SocketAddr::new(Ipv4Addr::new(127, 0, 0, 1).into(), port),
+                        ];
+                        state = State::Connecting(ConnectingTcp::new(
+                            self.config.prefer(host, dns::IpAddrs::new(addrs))?,
+                            self.config.clone(),
+                        ), Name::new(mem::replace(host, String::new()), port));
+                    } else {
+                        let name = Name::new(mem::replace(host, String::new()), port);
+                        self.dns_started = Some(Instant::now());
+                        state = match self.config.srv_service {
+                            Some(ref service) => {
+                                let srv_name = Name::new(format!("{}.{}", service, name.as_str()), port);
+                                check_negative_cache(&srv_name, &self.config)?;
+                                let future = resolver.resolve_srv(srv_name.as_str());
+                                State::SrvResolving(Lookup::start(future, srv_name, &self.config), resolver.clone(), name)
+                            },
+                            None => lookup(&**resolver, name, &self.config)?,
+                        };
+                    }
+                },
+                State::SrvResolving(ref mut srv_lookup, ref resolver, ref name) => {
+                    let records = try_ready!(srv_lookup.poll(&self.config));
+                    srv_lookup.end(&self.config, Ok(&[]));
+                    if records.is_empty() {
+                        state = lookup(&**resolver, name.clone(), &self.config)?;
+                    } else if records.len() == 1 && records[0].target() == "." {
+                        // A target of "." means there is no such service.
+                        return Err(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("the SRV records of {} say the service isn't available", name.as_str()),
+                        ));
+                    } else {
+                        let mut rng = dns::ShuffleRng::from_time();
+                        let targets = match self.config.shuffle_rng {
+                            Some(ref rng) => dns::order_srv(records, &mut rng.lock().unwrap()),
+                            None => dns::order_srv(records, &mut rng),
+                        };
+                        let config = &self.config;
+                        let targets = targets
+                            .into_iter()
+                            .map(|record| {
+                                let target = Name::new(record.target().to_owned(), record.port());
+                                if check_negative_cache(&target, config).is_err() {
+                                    return SrvTarget::Resolved(Vec::new());
+                                }
+                                let cached = config.dns_cache.as_ref().and_then(|cache| cache.get(&target));
+                                match cached {
+                                    Some(addrs) => SrvTarget::Resolved(addrs.collect()),
+                                    None => {
+                                        let future = resolver.resolve(target.clone());
+                                        SrvTarget::Resolving(Lookup::start(future, target, config))
+                                    },
+                                }
+                            })
+                            .collect();
+                        state = State::SrvTargets(targets, name.clone());
+                    }
+                },
+                State::SrvTargets(ref mut targets, ref name) => {
+                    // Targets are resolved at once, and a target that
+                    // fails to resolve is skipped.
+                    let mut ready = true;
+                    for target in targets.iter_mut() {
+                        let addrs = match *target {
+                            SrvTarget::Resolving(ref mut lookup) => match lookup.poll(&self.config) {
+                                Ok(Async::Ready(addrs)) => {
+                                    lookup.end(&self.config, Ok(addrs.as_slice()));
+                                    if !addrs.is_empty() {
+                                        if let Some(ref cache) = self.config.dns_cache {
+                                            cache.insert(lookup.name.clone(), &addrs);
+                                        }
+                                    }
+                                    addrs.collect()
+                                },
+                                Ok(Async::NotReady) => {
+                                    ready = false;
+                                    continue;
+                                },
+                                Err(err) => {
+                                    debug!("failed to resolve SRV target of {}: {}", name.as_str(), err);
+                                    Vec::new()
+                                },
+                            },
+                            SrvTarget::Resolved(_) => continue,
+                        };
+                        *target = SrvTarget::Resolved(addrs);
+                    }
+                    if !ready {
+                        return Ok(Async::NotReady);
+                    }
+                    let addrs = targets
+                        .drain(..)
+                        .flat_map(|target| match target {
+                            SrvTarget::Resolved(addrs) => addrs,
+                            SrvTarget::Resolving(_) => unreachable!("every target is resolved"),
+                        })
+                        .collect::<Vec<_>>();
+                    if addrs.is_empty() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("no addresses found for the SRV targets of {}", name.as_str()),
+                        ));
+                    }
+                    state = State::Connecting(ConnectingTcp::new(
+                        self.config.prefer(name.as_str(), dns::IpAddrs::new(addrs))?,
+                        self.config.clone(),
+                    ), name.clone());
+                },
+                State::Resolving(ref mut lookup) => {
+                    let addrs = try_ready!(lookup.poll(&self.config));
+                    lookup.end(&self.config, Ok(addrs.as_slice()));
+                    let name = &lookup.name;
+                    // Some resolvers succeed without any addresses.
+                    if addrs.is_empty() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::NotFound,
+                            format!("dns returned no addresses for host {}", name.as_str()),
+                        ));
+                    }
+                    if let Some(ref cache) = self.config.dns_cache {
+                        cache.insert(name.clone(), &addrs);
+                    }
+                    state = State::Connecting(ConnectingTcp::new(
+                        self.config.prefer(name.as_str(), addrs)?,
+                        self.config.clone(),
+                    ), name.clone());
+                },
+                State::Connecting(ref mut c, ref name) => {
+                    let connect_started = *self.connect_started.get_or_insert_with(Instant::now);
+                    let dns_time = self.dns_started.map(|started| connect_started - started);
+                    let cache = &self.config.dns_cache;
+                    let sock = try_ready!(c.poll(&self.handle).map_err(|mut e| {
+                        if let Some(err) = e.get_mut().and_then(|e| e.downcast_mut::<ConnectError>()) {
+                            err.host = name.as_str().to_owned();
+                            err.port = name.port();
+                            err.timing = Some(ConnectTiming {
+                                dns: dns_time,
+                                tcp: connect_started.elapsed(),
+                                attempts: c.attempted,
+                            });
+                            // Every address failed, so resolve again next time.
+                            if let Some(ref cache) = *cache {
+                                cache.remove(name);
+                            }
+                        }
+                        e
+                    }));
+
+                    if let Some(dur) = self.config.keep_alive_timeout {
+                        sock.set_keepalive(Some(dur))?;
+                        if let Some(dur) = self.config.keep_alive_interval {
+                            sockopt::set_keepalive_interval(&sock, dur)?;
+                        }
+                        if let Some(retries) = self.config.keep_alive_retries {
+                            sockopt::set_keepalive_retries(&sock, retries)?;
+                        }
+                    } else if self.config.keep_alive_interval.is_some() || self.config.keep_alive_retries.is_some() {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidInput,
+                            "keepalive interval and retries require a keepalive duration",
+                        ));
+                    }
+
+                    sock.set_nodelay(self.config.nodelay)?;
+                    if let Some(dur) = self.config.linger {
+                        sock.set_linger(Some(dur))?;
+                    }
+                    if let Some(dur) = self.config.user_timeout {
+                        sockopt::set_user_timeout(&sock, dur)?;
+                    }
+
+                    // Only addresses from the resolver or the DNS cache
+                    // count, so this agrees with the timing's DNS duration.
+                    let mut connected = Connected::new().resolved(self.dns_started.is_some());
+                    connected.dns_time = dns_time;
+                    connected.connect_time = Some(connect_started.elapsed());
+                    connected.attempts = c.attempted;
+                    if let Ok(addr) = sock.peer_addr() {
+                        connected = connected.remote_addr(addr);
+                    }
+                    return Ok(Async::Ready((sock, connected)));
+                },
+                State::Error(ref mut e) => return Err(e.take().expect("polled more than once")),
+            }
+            self.state = state;
+        }
+    }
+}
+
+// Start resolving `name`, unless it's in a cache.
+fn lookup<R: Resolve>(resolver: &R, name: Name, config: &Arc<Config>) -> io::Result<State<R>> {
+    check_negative_cache(&name, config)?;
+    let cached = config.dns_cache
+        .as_ref()
+        .and_then(|cache| cache.get(&name));
+    if let Some(addrs) = cached {
+        return Ok(State::Connecting(ConnectingTcp::new(
+            config.prefer(name.as_str(), addrs)?,
+            config.clone(),
+        ), name));
+    }
+
+    let future = resolver.resolve(name.clone());
+    Ok(State::Resolving(Lookup::start(future, name, config)))
+}
+
+// Fail with the error `name` recently failed to resolve with, if any.
+fn check_negative_cache(name: &Name, config: &Config) -> io::Result<()> {
+    match config.dns_negative_cache.as_ref().and_then(|cache| cache.get(name)) {
+        Some(err) => Err(err),
+        None => Ok(()),
+    }
+}
+
+impl<F: Future<Error=io::Error>> Lookup<F> {
+    fn start(future: F, name: Name, config: &Config) -> Lookup<F> {
+        if let Some(ref observer) = config.observer {
+            observer.0.on_resolve_start(name.as_str());
+        }
+        Lookup {
+            future: future,
+            name: name,
+            started: Instant::now(),
+            timeout: config.dns_timeout.map(|dur| Delay::new(Instant::now() + dur)),
+        }
+    }
+
+    // Errors are reported here, and a successful result by the caller,
+    // with `end`, once it has the addresses.
+    fn poll(&mut self, config: &Config) -> Poll<F::Item, io::Error> {
+        let err = match self.future.poll() {
+            Ok(Async::Ready(item)) => return Ok(Async::Ready(item)),
+            Ok(Async::NotReady) => match self.timeout {
+                Some(ref mut timeout) => match timeout.poll() {
+                    // Dropping the future abandons the lookup.
+                    Ok(Async::Ready(())) => io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!("timed out resolving {}", self.name.as_str()),
+                    ),
+                    Ok(Async::NotReady) => return Ok(Async::NotReady),
+                    Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+                },
+                None => return Ok(Async::NotReady),
+            },
+            Err(err) => {
+                if let Some(ref cache) = config.dns_negative_cache {
+                    if err.kind() == io::ErrorKind::NotFound {
+                        cache.insert(self.name.clone(), &err);
+                    }
+                }
+                err
+            },
+        };
+        self.end(config, Err(&err));
+        Err(err)
+    }
+
+    fn end(&self, config: &Config, result: Result<&[SocketAddr], &io::Error>) {
+        if let Some(ref observer) = config.observer {
+            observer.0.on_resolve_end(self.name.as_str(), result, self.started.elapsed());
+        }
+    }
+}
+
+impl<R: Resolve> fmt::Debug for HttpConnecting<R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self.state {
+            State::Lazy(..) => "Lazy",
+            State::Resolving(..) => "Resolving",
+            State::SrvResolving(..) => "SrvResolving",
+            State::SrvTargets(..) => "SrvTargets",
+            State::Connecting(..) => "Connecting",
+            State::Error(..) => "Error",
+        };
+        f.debug_struct("HttpConnecting")
+            .field("state", &format_args!("{}", state))
+            .finish()
+    }
+}
+
+struct ConnectingTcp {
+    attempted: usize,
+    config: Arc<Config>,
+    preferred: ConnectingTcpRemote,
+    fallback: Option<ConnectingTcpFallback>,
+}
+
+impl ConnectingTcp {
+    fn new(remote_addrs: dns::IpAddrs, config: Arc<Config>) -> ConnectingTcp {
+        // Unless strict, addresses of a family without a local address are
+        // only skipped if some address can be bound.
+        let local_filter = config.has_local_address() && (config.local_address_strict || remote_addrs
+            .as_slice()
+            .iter()
+            .any(|addr| config.local_address_for(addr).is_some()));
+        if let Some(fallback_timeout) = config.happy_eyeballs_timeout {
+            let (preferred_addrs, fallback_addrs) = remote_addrs.split_by_preference();
+            if fallback_addrs.is_empty() {
+                return ConnectingTcp {
+                    attempted: 0,
+                    config,
+                    preferred: ConnectingTcpRemote::new(preferred_addrs, local_filter),
+                    fallback: None,
+                };
+            }
+
+            ConnectingTcp {
+                attempted: 0,
+                config,
+                preferred: ConnectingTcpRemote::new(preferred_addrs, local_filter),
+                fallback: Some(ConnectingTcpFallback {
+                    delay: Delay::new(Instant::now() + fallback_timeout),
+                    remote: ConnectingTcpRemote::new(fallback_addrs, local_filter),
+                }),
+            }
+        } else {
+            ConnectingTcp {
+                attempted: 0,
+                config,
+                preferred: ConnectingTcpRemote::new(remote_addrs, local_filter),
+                fallback: None,
+            }
+        }
+    }
+
+    // not a Future, since passing a &Handle to poll
+    fn poll(&mut self, handle: &Option<Handle>) -> Poll<TcpStream, io::Error> {
+        match self.fallback.take() {
+            None => self.preferred.poll(&self.config, &mut self.attempted, 0, handle),
+            Some(mut fallback) => match self.preferred.poll(&self.config, &mut self.attempted, fallback.remote.current.len(), handle) {
+                Ok(Async::Ready(stream)) => {
+                    // Preferred successful - drop fallback.
+                    Ok(Async::Ready(stream))
+                }
+                Ok(Async::NotReady) => match fallback.delay.poll().unwrap_or_else(|e| {
+                    // Without a timer, start the fallback now rather than never.
+                    debug!("happy eyeballs delay failed, starting fallback: {}", e);
+                    Async::Ready(())
+                }) {
+                    Async::Ready(()) => match self.poll_fallback(&mut fallback, handle) {
+                        Ok(Async::Ready(stream)) => {
+                            // Fallback successful - drop current preferred,
+                            // but keep fallback as new preferred.
+                            self.preferred = fallback.remote;
+                            Ok(Async::Ready(stream))
+                        }
+                        Ok(Async::NotReady) => {
+                            // Neither preferred nor fallback are ready.
+                            self.fallback = Some(fallback);
+                            Ok(Async::NotReady)
+                        }
+                        Err(e) => {
+                            // Fallback failed - resume with preferred only,
+                            // keeping its errors for the final report.
+                            let (mut errors, skipped) = failed_attempts(e);
+                            self.preferred.errors.append(&mut errors);
+                            self.preferred.skipped += skipped;
+                            Ok(Async::NotReady)
+                        }
+                    },
+                    Async::NotReady => {
+                        // Too early to attempt fallback.
+                        self.fallback = Some(fallback);
+                        Ok(Async::NotReady)
+                    }
+                }
+                Err(e) => {
+                    // Preferred failed - use fallback as new preferred,
+                    // keeping its errors for the final report.
+                    let (mut errors, skipped) = failed_attempts(e);
+                    errors.append(&mut fallback.remote.errors);
+                    self.preferred = fallback.remote;
+                    self.preferred.errors = errors;
+                    self.preferred.skipped += skipped;
+                    self.preferred.poll(&self.config, &mut self.attempted, 0, handle)
+                }
+            }
+        }
+    }
+
+    // Poll the fallback attempts, which may free up slots the preferred
+    // attempts are waiting on.
+    fn poll_fallback(&mut self, fallback: &mut ConnectingTcpFallback, handle: &Option<Handle>) -> Poll<TcpStream, io::Error> {
+        let in_flight = fallback.remote.current.len();
+        let polled = fallback.remote.poll(&self.config, &mut self.attempted, self.preferred.current.len(), handle);
+        if fallback.remote.current.len() < in_flight {
+            task::current().notify();
+        }
+        polled
+    }
+}
+
+// Take back the attempts of a family that failed, and how many of its
+// addresses were skipped, to report them with those of the other family.
+fn failed_attempts(err: io::Error) -> (Vec<(SocketAddr, io::Error)>, usize) {
+    match err.into_inner().map(|inner| inner.downcast::<ConnectError>()) {
+        Some(Ok(err)) => (err.attempts, err.skipped),
+        _ => (Vec::new(), 0),
+    }
+}
+
+struct ConnectingTcpFallback {
+    delay: Delay,
+    remote: ConnectingTcpRemote,
+}
+
+struct ConnectingTcpRemote {
+    addrs: dns::IpAddrs,
+    current: Vec<ConnectingAttempt>,
+    errors: Vec<(SocketAddr, io::Error)>,
+    // Whether addresses of a family without a local address are skipped.
+    local_filter: bool,
+    // Index of the next address in `addrs` to try.
+    next: usize,
+    next_delay: Option<Delay>,
+    // Addresses of the other family that were never tried.
+    skipped: usize,
+}
+
+impl ConnectingTcpRemote {
+    fn new(addrs: dns::IpAddrs, local_filter: bool) -> Self {
+        Self {
+            addrs: addrs,
+            current: Vec::new(),
+            errors: Vec::new(),
+            local_filter: local_filter,
+            next: 0,
+            next_delay: None,
+            skipped: 0,
+        }
+    }
+
+    // How many addresses are left that may still be tried.
+    fn remaining(&self, config: &Config) -> usize {
+        self.addrs.as_slice()[self.next..]
+            .iter()
+            .filter(|addr| !self.local_filter || config.local_address_for(addr).is_some())
+            .count()
+    }
+
+    // not a Future, since passing a &Handle to poll
+    //
+    // `attempted` counts addresses tried across both families, and
+    // `others` counts the attempts of the other family still in flight.
+    fn poll(&mut self, config: &Config, attempted: &mut usize, others: usize, handle: &Option<Handle>) -> Poll<TcpStream, io::Error> {
+        loop {
+            let mut i = 0;
+            while i < self.current.len() {
+                match self.current[i].poll(config, handle) {
+                    Ok(Async::Ready(sock)) => {
+                        if let Some(ref observer) = config.observer {
+                            let attempt = &self.current[i];
+                            observer.0.on_connected(&attempt.addr, attempt.started.elapsed());
+                        }
+                        // Drop any slower attempts still in flight.
+                        self.current.clear();
+                        return Ok(Async::Ready(sock));
+                    },
+                    Ok(Async::NotReady) => i += 1,
+                    Err(e) => {
+                        let attempt = self.current.remove(i);
+                        trace!("connect error for {}: {:?}", attempt.addr, e);
+                        if let Some(ref observer) = config.observer {
+                            observer.0.on_connect_failed(&attempt.addr, &e);
+                        }
+                        self.errors.push((attempt.addr, e));
+                    },
+                }
+            }
+
+            // Try the next address once every attempt failed, or when
+            // the current ones have been given a head start.
+            let start_next = self.current.is_empty() || match self.next_delay {
+                Some(ref mut delay) => match delay.poll() {
+                    Ok(Async::Ready(())) => true,
+                    Ok(Async::NotReady) => false,
+                    Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+                },
+                None => false,
+            };
+            let under_max = config.max_addrs_attempted.map_or(true, |max| *attempted < max);
+            let under_cap = self.current.len() + others < cmp::max(config.max_concurrent_attempts, 1);
+            if start_next && under_max && under_cap {
+                let (local_v4, local_v6) = if self.local_filter {
+                    config.local_address_families()
+                } else {
+                    (None, None)
+                };
+                if let Some(i) = self.addrs.next_filter(self.next, local_v4, local_v6) {
+                    let addr = self.addrs.as_slice()[i];
+                    self.next = i + 1;
+                    debug!("connecting to {}", addr);
+                    *attempted += 1;
+                    if let Some(ref observer) = config.observer {
+                        observer.0.on_connect_attempt(&addr);
+                    }
+                    // A socket that can't even be set up is just another
+                    // failed address; keep going with the rest.
+                    match ConnectingAttempt::new(&addr, config, handle) {
+                        Ok(attempt) => self.current.push(attempt),
+                        Err(e) => {
+                            trace!("connect error for {}: {:?}", addr, e);
+                            if let Some(ref observer) = config.observer {
+                                observer.0.on_connect_failed(&addr, &e);
+                            }
+                            self.errors.push((addr, e));
+                        },
+                    }
+                    self.next_delay = config.fallback_delay.map(|dur| Delay::new(Instant::now() + dur));
+                    continue;
+                }
+                self.next = self.addrs.len();
+                self.next_delay = None;
+            }
+
+            // Waiting on the other family for a slot.
+            let waiting = start_next && under_max && !under_cap && self.next < self.addrs.len();
+            if !self.current.is_empty() || waiting {
+                return Ok(Async::NotReady);
+            }
+
+            if self.errors.is_empty() && self.local_filter {
+                let family = match config.local_address_families() {
+                    (Some(_), None) => "IPv4",
+                    (None, Some(_)) => "IPv6",
+                    _ => "IPv4 or IPv6",
+                };
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    format!("no resolved addresses match local address family {}", family),
+                ));
+            }
+            if self.errors.is_empty() && !under_max {
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrNotAvailable,
+                    "maximum number of addresses attempted is zero",
+                ));
+            }
+            if self.errors.is_empty() {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "no addresses to connect to",
+                ));
+            }
+            return Err(ConnectError {
+                host: String::new(),
+                port: 0,
+                attempts: mem::replace(&mut self.errors, Vec::new()),
+                skipped: self.skipped + self.remaining(config),
+                timing: None,
+            }.into());
+        }
+    }
+}
+
+/// A single attempt to connect to one address, optionally bounded by
+/// a timeout.
+struct ConnectingAttempt {
+    addr: SocketAddr,
+    attempts: usize,
+    backoff: Option<Delay>,
+    future: ConnectFuture,
+    local_ports: Option<Range<u16>>,
+    started: Instant,
+    timeout: Option<Delay>,
+}
+
+impl ConnectingAttempt {
+    fn new(addr: &SocketAddr, config: &Config, handle: &Option<Handle>) -> io::Result<ConnectingAttempt> {
+        let mut local_ports = config.local_port_range.clone();
+        Ok(ConnectingAttempt {
+            addr: *addr,
+            attempts: 1,
+            backoff: None,
+            future: connect(addr, config, &mut local_ports, handle)?,
+            local_ports,
+            started: Instant::now(),
+            timeout: config.connect_timeout.map(|dur| Delay::new(Instant::now() + dur)),
+        })
+    }
+
+    // not a Future, since passing a &Handle to poll
+    fn poll(&mut self, config: &Config, handle: &Option<Handle>) -> Poll<TcpStream, io::Error> {
+        loop {
+            if let Some(mut backoff) = self.backoff.take() {
+                match backoff.poll() {
+                    Ok(Async::Ready(())) => self.retry(config, handle)?,
+                    Ok(Async::NotReady) => {
+                        self.backoff = Some(backoff);
+                        return Ok(Async::NotReady);
+                    },
+                    Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+                }
+            }
+
+            match self.future.poll() {
+                Ok(Async::Ready(sock)) => return Ok(Async::Ready(sock)),
+                Ok(Async::NotReady) => break,
+                Err(ref e) if self.can_retry_local_port(e) => {
+                    debug!("connect to {} from local port failed, trying next: {}", self.addr, e);
+                    self.future = connect(&self.addr, config, &mut self.local_ports, handle)?;
+                },
+                Err(ref e) if self.attempts <= config.connect_retries && is_transient(e) => {
+                    let delay = retry::backoff(config.connect_retry_delay, self.attempts);
+                    debug!("connect to {} failed, retrying in {:?}: {}", self.addr, delay, e);
+                    self.backoff = Some(Delay::new(Instant::now() + delay));
+                },
+                Err(e) => return Err(self.attempts_error(e, config)),
+            }
+        }
+
+        if let Some(ref mut timeout) = self.timeout {
+            match timeout.poll() {
+                Ok(Async::Ready(())) => {
+                    let err = io::Error::new(io::ErrorKind::TimedOut, "connect timed out");
+                    return Err(self.attempts_error(err, config));
+                },
+                Ok(Async::NotReady) => (),
+                Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+            }
+        }
+        Ok(Async::NotReady)
+    }
+
+    fn retry(&mut self, config: &Config, handle: &Option<Handle>) -> io::Result<()> {
+        self.attempts += 1;
+        self.local_ports = config.local_port_range.clone();
+        self.future = connect(&self.addr, config, &mut self.local_ports, handle)?;
+        self.timeout = config.connect_timeout.map(|dur| Delay::new(Instant::now() + dur));
+        Ok(())
+    }
+
+    // Without retries configured, errors are passed through untouched.
+    fn attempts_error(&self, err: io::Error, config: &Config) -> io::Error {
+        if config.connect_retries == 0 {
+            return err;
+        }
+        retry::attempts_error(err, self.attempts)
+    }
+
+    // The bound port may clash with an existing connection to the same
+    // remote address, in which case another port in the range may work.
+    fn can_retry_local_port(&self, err: &io::Error) -> bool {
+        let ports_left = match self.local_ports {
+            Some(ref ports) => ports.start < ports.end,
+            None => false,
+        };
+        ports_left && match err.kind() {
+            io::ErrorKind::AddrInUse | io::ErrorKind::AddrNotAvailable => true,
+            _ => false,
+        }
+    }
+}
+
+// Errors where the same address may well succeed a moment later.
+fn is_transient(err: &io::Error) -> bool {
+    match err.kind() {
+        io::ErrorKind::ConnectionRefused |
+        io::ErrorKind::ConnectionReset |
+        io::ErrorKind::ConnectionAborted => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp;
+    use std::collections::HashMap;
+    use std::io;
+    use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream};
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::{Duration, Instant};
+
+    use futures::Future;
+    use futures::future::{self, poll_fn, ExecuteError, Executor, FutureResult};
+    use http::Uri;
+    use net2::TcpBuilder;
+    use tokio_reactor::Reactor;
+    use tokio::runtime::current_thread::Runtime;
+
+    use super::{Connect, ConnectError, ConnectingTcp, Destination, HttpConnector, SchemePolicy};
+    use super::dns::{self, AddrOrder, IpFamilyPreference, Name, Resolve};
+    use super::super::tests::{discard_addr, ipv6_loopback};
+
+    #[test]
+    fn test_errors_missing_authority() {
+        let uri = "/foo/bar?baz".parse().unwrap();
+        let dst = Destination {
+            uri,
+        };
+        let connector = HttpConnector::new(1);
+
+        assert_eq!(connector.connect(dst).wait().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_errors_enforce_http() {
+        let uri = "https://example.domain/foo/bar?baz".parse().unwrap();
+        let dst = Destination {
+            uri,
+        };
+        let connector = HttpConnector::new(1);
+
+        assert_eq!(connector.connect(dst).wait().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+
+    #[test]
+    fn test_scheme_policies() {
+        let allowed = |policy, uri: &str| {
+            let mut connector = HttpConnector::new(1);
+            connector.set_scheme_policy(policy);
+            let dst = Destination {
+                uri: uri.parse().unwrap(),
+            };
+            match connector.connect(dst).state {
+                super::State::Lazy(_, _, port, _) => Ok(port),
+                super::State::Error(Some(ref err)) => Err(err.to_string()),
+                _ => panic!("unexpected state"),
+            }
+        };
+        let not_allowed = |scheme| Err(format!("invalid URL, scheme {:?} is not allowed", scheme));
+
+        assert_eq!(allowed(SchemePolicy::HttpOnly, "http://example.domain/"), Ok(80));
+        assert_eq!(allowed(SchemePolicy::HttpOnly, "https://example.domain/"), not_allowed("https"));
+        assert_eq!(allowed(SchemePolicy::HttpOnly, "ftp://example.domain/"), not_allowed("ftp"));
+
+        assert_eq!(allowed(SchemePolicy::HttpAndHttps, "http://example.domain/"), Ok(80));
+        assert_eq!(allowed(SchemePolicy::HttpAndHttps, "https://example.domain/"), Ok(443));
+        assert_eq!(allowed(SchemePolicy::HttpAndHttps, "ftp://example.domain/"), not_allowed("ftp"));
+
+        assert_eq!(allowed(SchemePolicy::Any, "http://example.domain/"), Ok(80));
+        assert_eq!(allowed(SchemePolicy::Any, "https://example.domain/"), Ok(443));
+        assert_eq!(allowed(SchemePolicy::Any, "ftp://example.domain:21/"), Ok(21));
+    }
+
+    #[test]
+    fn test_errors_scheme_not_allowed() {
+        let mut connector = HttpConnector::new(1);
+        connector.set_allowed_schemes(Some(vec!["http".to_owned(), "https".to_owned()]));
+
+        let dst = Destination {
+            uri: "ftp://example.domain/foo".parse().unwrap(),
+        };
+        let err = connector.connect(dst).wait().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("\"ftp\""), "{}", err);
+
+        // Allowed schemes get past the check, on to resolving.
+        let dst = Destination {
+            uri: "HTTPS://example.domain/foo".parse().unwrap(),
+        };
+        let connecting = connector.connect(dst);
+        match connecting.state {
+            super::State::Lazy(..) => (),
+            _ => panic!("https was not allowed"),
+        }
+
+        // Schemes are stored lowercased.
+        connector.set_allowed_schemes(Some(vec!["HTTP".to_owned()]));
+        assert_eq!(connector.config.allowed_schemes, Some(vec!["http".to_owned()]));
+        let dst = Destination {
+            uri: "http://example.domain/foo".parse().unwrap(),
+        };
+        match connector.connect(dst).state {
+            super::State::Lazy(..) => (),
+            _ => panic!("http was not allowed"),
+        }
+    }
+
+    #[test]
+    fn test_default_ports_map_custom_schemes() {
+        let mut connector = HttpConnector::new(1);
+        connector.enforce_http(false);
+        let mut ports = HashMap::new();
+        ports.insert("wss".to_owned(), 443);
+        connector.set_default_ports(ports);
+
+        let dst = Destination {
+            uri: "wss://example.domain/chat".parse().unwrap(),
+        };
+        match connector.connect(dst).state {
+            super::State::Lazy(_, _, port, _) => assert_eq!(port, 443),
+            _ => panic!("wss had no default port"),
+        }
+        // The built-in http port is kept.
+        assert_eq!(connector.config.default_ports.get("http"), Some(&80));
+    }
+
+    #[test]
+    fn test_errors_unknown_port() {
+        let mut connector = HttpConnector::new(1);
+        connector.enforce_http(false);
+
+        let dst = Destination {
+            uri: "gopher://example.domain/".parse().unwrap(),
+        };
+        let err = connector.connect(dst).wait().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("\"gopher\""), "{}", err);
+    }
+
+    #[test]
+    fn test_errors_invalid_ipv6_zone() {
+        let connector = HttpConnector::new(1);
+
+        let dst = Destination {
+            uri: "http://[fe80::1%25no-such-interface]:8080/".parse().unwrap(),
+        };
+        let err = connector.connect(dst).wait().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("[fe80::1%25no-such-interface]"), "{}", err);
+
+        let dst = Destination {
+            uri: "http://[fe80::1%253]:8080/".parse().unwrap(),
+        };
+        match connector.connect(dst).state {
+            super::State::Lazy(..) => (),
+            _ => panic!("numeric zone was rejected"),
+        }
+    }
+
+    #[test]
+    fn test_builder_applies_options() {
+        let reactor = Reactor::new().unwrap();
+        let connector = HttpConnector::builder(&reactor.handle())
+            .threads(1)
+            .keepalive(Some(Duration::from_secs(30)))
+            .local_address(Some([127, 0, 0, 1].into()))
+            .enforce_http(false)
+            .nodelay(true)
+            .connect_timeout(Some(Duration::from_secs(5)))
+            .build();
+
+        let config = &connector.config;
+        assert_eq!(config.keep_alive_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(config.local_addresses, vec![IpAddr::from([127, 0, 0, 1])]);
+        assert_eq!(config.allowed_schemes, None);
+        assert!(config.nodelay);
+        assert_eq!(config.connect_timeout, Some(Duration::from_secs(5)));
+        assert!(connector.handle.is_some());
+    }
+
+    #[test]
+    fn test_errors_missing_scheme() {
+        let uri = "example.domain".parse().unwrap();
+        let dst = Destination {
+            uri,
+        };
+        let connector = HttpConnector::new(1);
+
+        assert_eq!(connector.connect(dst).wait().unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn connect_timeout_tries_next_address() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let unanswered = Unanswered::new();
+
+        let mut connector = HttpConnector::new(1);
+        connector.set_connect_timeout(Some(Duration::from_millis(100)));
+        connector.set_happy_eyeballs_timeout(None);
+
+        let addrs = dns::IpAddrs::new(vec![unanswered.addr, addr]);
+        let mut connecting = ConnectingTcp::new(addrs, connector.config.clone());
+
+        let mut rt = Runtime::new().unwrap();
+        let sock = rt.block_on(poll_fn(|| connecting.poll(&None))).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn happy_eyeballs_falls_back_to_other_family() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let blackhole = discard_addr(1, 81);
+
+        let mut connector = HttpConnector::new(1);
+        connector.set_happy_eyeballs_timeout(Some(Duration::from_millis(100)));
+
+        let addrs = dns::IpAddrs::new(vec![blackhole, addr]);
+        let mut connecting = ConnectingTcp::new(addrs, connector.config.clone());
+
+        let mut rt = Runtime::new().unwrap();
+        let sock = rt.block_on(poll_fn(|| connecting.poll(&None))).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn happy_eyeballs_without_timer_starts_fallback() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let blackhole = discard_addr(1, 81);
+
+        let connector = HttpConnector::new(1);
+        let addrs = dns::IpAddrs::new(vec![blackhole, addr]);
+        let mut connecting = ConnectingTcp::new(addrs, connector.config.clone());
+
+        // Outside a runtime, the delay fails as there's no timer.
+        let sock = poll_fn(|| connecting.poll(&None)).wait().expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn test_nodelay_is_applied() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let uri = format!("http://{}", addr).parse().unwrap();
+        let dst = Destination {
+            uri,
+        };
+        let mut connector = HttpConnector::new(1);
+        connector.set_nodelay(true);
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert!(sock.nodelay().unwrap());
+    }
+
+    #[test]
+    fn test_linger_is_applied() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let dst = || Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let mut connector = HttpConnector::new(1);
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst())).expect("connect");
+        assert_eq!(sock.linger().unwrap(), None);
+
+        connector.set_linger(Some(Duration::from_secs(0)));
+        let (sock, _) = rt.block_on(connector.connect(dst())).expect("connect");
+        assert_eq!(sock.linger().unwrap(), Some(Duration::from_secs(0)));
+    }
+
+    // Resolves example.test to its addresses, with port 0 taking the port
+    // of the name.
+    struct StaticResolver {
+        addrs: Vec<SocketAddr>,
+        calls: Arc<AtomicUsize>,
+    }
+
+    impl StaticResolver {
+        fn new(addr: SocketAddr) -> StaticResolver {
+            StaticResolver::with_addrs(vec![addr])
+        }
+
+        fn with_addrs(addrs: Vec<SocketAddr>) -> StaticResolver {
+            StaticResolver {
+                addrs,
+                calls: Arc::new(AtomicUsize::new(0)),
+            }
+        }
+    }
+
+    impl Resolve for StaticResolver {
+        type Future = FutureResult<dns::IpAddrs, io::Error>;
+
+        fn resolve(&self, name: Name) -> Self::Future {
+            assert_eq!(name.as_str(), "example.test");
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            future::ok(dns::IpAddrs::new(self.addrs
+                .iter()
+                .map(|addr| match addr.port() {
+                    0 => SocketAddr::new(addr.ip(), name.port()),
+                    _ => *addr,
+                })
+                .collect()))
+        }
+    }
+
+    // An address that refuses connections, since its listener is dropped.
+    fn dead_addr() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()
+    }
+
+    // A listener that never accepts, with its backlog already filled by
+    // `_queued`, so the SYN of a connect to `addr` goes unanswered.
+    struct Unanswered {
+        addr: SocketAddr,
+        _listener: TcpListener,
+        _queued: TcpStream,
+    }
+
+    impl Unanswered {
+        fn new() -> Unanswered {
+            let listener = TcpBuilder::new_v4().unwrap()
+                .bind("127.0.0.1:0").unwrap()
+                .listen(0).unwrap();
+            let addr = listener.local_addr().unwrap();
+            let queued = TcpStream::connect(addr).unwrap();
+            Unanswered {
+                addr,
+                _listener: listener,
+                _queued: queued,
+            }
+        }
+    }
+
+    fn example_test(addr: SocketAddr) -> Destination {
+        Destination {
+            uri: format!("http://example.test:{}", addr.port()).parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_custom_resolver() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let resolver = StaticResolver::new(addr);
+        let calls = resolver.calls.clone();
+        let connector = HttpConnector::new_with_resolver(resolver, None);
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(example_test(addr))).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_connectors_share_pool() {
+        use futures_cpupool::Builder as CpuPoolBuilder;
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let pool = CpuPoolBuilder::new().pool_size(1).create();
+        let mut rt = ::tokio::runtime::Runtime::new().unwrap();
+        let handle = rt.reactor().clone();
+        let mut first = HttpConnector::new_with_pool(pool.clone(), &handle);
+        let mut second = HttpConnector::new_with_pool(pool, &handle);
+        first.set_resolve_localhost(true);
+        second.set_resolve_localhost(true);
+
+        for connector in &[first, second] {
+            let dst = Destination {
+                uri: format!("http://localhost:{}", addr.port()).parse().unwrap(),
+            };
+            let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+            assert_eq!(sock.peer_addr().unwrap(), addr);
+        }
+    }
+
+    // Records the order of callbacks.
+    #[derive(Default)]
+    struct RecordingObserver(::std::sync::Mutex<Vec<String>>);
+
+    impl super::ConnectObserver for RecordingObserver {
+        fn on_resolve_start(&self, host: &str) {
+            self.0.lock().unwrap().push(format!("resolve {}", host));
+        }
+
+        fn on_resolve_end(&self, host: &str, result: Result<&[SocketAddr], &io::Error>, _elapsed: Duration) {
+            self.0.lock().unwrap().push(format!("resolved {} {:?}", host, result.map_err(|e| e.kind())));
+        }
+
+        fn on_connect_attempt(&self, addr: &SocketAddr) {
+            self.0.lock().unwrap().push(format!("attempt {}", addr));
+        }
+
+        fn on_connect_failed(&self, addr: &SocketAddr, _err: &io::Error) {
+            self.0.lock().unwrap().push(format!("failed {}", addr));
+        }
+
+        fn on_connected(&self, addr: &SocketAddr, _elapsed: Duration) {
+            self.0.lock().unwrap().push(format!("connected {}", addr));
+        }
+    }
+
+    #[test]
+    fn test_observer_ip_literal() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let observer = Arc::new(RecordingObserver::default());
+        let mut connector = HttpConnector::new(1);
+        connector.set_observer(observer.clone());
+
+        let dst = Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(*observer.0.lock().unwrap(), vec![
+            format!("attempt {}", addr),
+            format!("connected {}", addr),
+        ]);
+    }
+
+    #[test]
+    fn test_observer_dns() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let dead = dead_addr();
+        let observer = Arc::new(RecordingObserver::default());
+        let mut connector = HttpConnector::new_with_resolver(StaticResolver::with_addrs(vec![dead, addr]), None);
+        connector.set_happy_eyeballs_timeout(None);
+        connector.set_observer(observer.clone());
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(connector.connect(example_test(addr))).expect("connect");
+        assert_eq!(*observer.0.lock().unwrap(), vec![
+            "resolve example.test".to_owned(),
+            format!("resolved example.test Ok({:?})", [dead, addr]),
+            format!("attempt {}", dead),
+            format!("failed {}", dead),
+            format!("attempt {}", addr),
+            format!("connected {}", addr),
+        ]);
+    }
+
+    #[test]
+    fn test_host_override_skips_resolver() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let resolver = StaticResolver::new(addr);
+        let calls = resolver.calls.clone();
+        let mut connector = HttpConnector::new_with_resolver(resolver, None);
+        connector.add_host_override("Example.Test", vec![[127, 0, 0, 1].into()]);
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, connected) = rt.block_on(connector.connect(example_test(addr))).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+        assert!(!connected.is_resolved);
+        assert_eq!(connected.timing().and_then(|timing| timing.dns()), None);
+    }
+
+    #[test]
+    fn test_overrides_set_ports_and_hosts() {
+        let (a, b) = (TcpListener::bind("127.0.0.1:0").unwrap(), TcpListener::bind("127.0.0.1:0").unwrap());
+        let (first, second) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+        let resolver = StaticResolver::new(first);
+        let calls = resolver.calls.clone();
+        let mut connector = HttpConnector::new_with_resolver(resolver, None);
+        let mut overrides = HashMap::new();
+        overrides.insert("EXAMPLE.test".to_owned(), vec![second]);
+        overrides.insert("127.0.0.2".to_owned(), vec![SocketAddr::new([127, 0, 0, 1].into(), 0)]);
+        connector.set_overrides(overrides);
+
+        let mut rt = Runtime::new().unwrap();
+        // A fixed port, with the host's trailing dot ignored.
+        let dst = Destination {
+            uri: format!("http://example.test.:{}", first.port()).parse().unwrap(),
+        };
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), second);
+        // Port 0 takes the port of the URI, and IP addresses are matched too.
+        let dst = Destination {
+            uri: format!("http://127.0.0.2:{}", first.port()).parse().unwrap(),
+        };
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), first);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_uri_resolver_override_by_path() {
+        let (a, b) = (TcpListener::bind("127.0.0.1:0").unwrap(), TcpListener::bind("127.0.0.1:0").unwrap());
+        let (first, second) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+        let resolver = StaticResolver::new(first);
+        let calls = resolver.calls.clone();
+        let mut connector = HttpConnector::new_with_resolver(resolver, None);
+        let mut ports = HashMap::new();
+        ports.insert("http".to_owned(), second.port());
+        connector.set_default_ports(ports);
+        connector.set_uri_resolver_override(Arc::new(|uri: &Uri| {
+            if uri.path().starts_with("/tenant/") {
+                Some(dns::IpAddrs::new(vec![SocketAddr::new([127, 0, 0, 1].into(), 0)]))
+            } else {
+                None
+            }
+        }));
+
+        let mut rt = Runtime::new().unwrap();
+        // Port 0 takes the default port of the scheme.
+        let dst = Destination {
+            uri: "http://example.test/tenant/1".parse().unwrap(),
+        };
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), second);
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        // Other paths are resolved as usual.
+        let dst = Destination {
+            uri: format!("http://example.test:{}/other", first.port()).parse().unwrap(),
+        };
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), first);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    // Has `SRV` records for `example.test`, with targets named by
+    // their IP address.
+    struct SrvResolver(Vec<dns::SrvRecord>);
+
+    impl Resolve for SrvResolver {
+        type Future = FutureResult<dns::IpAddrs, io::Error>;
+
+        fn resolve(&self, name: Name) -> Self::Future {
+            match name.as_str() {
+                "example.test" => future::ok(dns::IpAddrs::new(Vec::new())),
+                target => {
+                    let ip = target.split(".ip.test").next().unwrap().parse().unwrap();
+                    future::ok(dns::IpAddrs::new(vec![SocketAddr::new(ip, name.port())]))
+                },
+            }
+        }
+
+        fn resolve_srv(&self, name: &str) -> dns::SrvFuture {
+            let records = if name == "_http._tcp.example.test" {
+                self.0.clone()
+            } else {
+                Vec::new()
+            };
+            dns::SrvFuture::new(future::ok(records))
+        }
+    }
+
+    #[test]
+    fn test_srv_targets_by_priority() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let observer = Arc::new(RecordingObserver::default());
+        let mut connector = HttpConnector::new_with_resolver(SrvResolver(vec![
+            dns::SrvRecord::new(20, 0, addr.port(), "127.0.0.2.ip.test".to_owned()),
+            dns::SrvRecord::new(10, 0, addr.port(), "127.0.0.1.ip.test".to_owned()),
+        ]), None);
+        connector.set_srv_service(Some("_http._tcp".to_owned()));
+        connector.set_observer(observer.clone());
+
+        // The port of the URI is ignored for the port of the record.
+        let dst = Destination {
+            uri: "http://example.test:1/".parse().unwrap(),
+        };
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        // The SRV lookup is reported without addresses, and then the
+        // targets are resolved at once.
+        assert_eq!(*observer.0.lock().unwrap(), vec![
+            "resolve _http._tcp.example.test".to_owned(),
+            "resolved _http._tcp.example.test Ok([])".to_owned(),
+            "resolve 127.0.0.1.ip.test".to_owned(),
+            "resolve 127.0.0.2.ip.test".to_owned(),
+            format!("resolved 127.0.0.1.ip.test Ok([{}])", addr),
+            format!("resolved 127.0.0.2.ip.test Ok([127.0.0.2:{}])", addr.port()),
+            format!("attempt {}", addr),
+            format!("connected {}", addr),
+        ]);
+    }
+
+    #[test]
+    fn test_srv_falls_back_without_records() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let resolver = StaticResolver::new(addr);
+        let calls = resolver.calls.clone();
+        let mut connector = HttpConnector::new_with_resolver(resolver, None);
+        connector.set_srv_service(Some("_http._tcp".to_owned()));
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(example_test(addr))).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // A single target of "." means there's no such service.
+        let mut connector = HttpConnector::new_with_resolver(SrvResolver(vec![
+            dns::SrvRecord::new(0, 0, 0, ".".to_owned()),
+        ]), None);
+        connector.set_srv_service(Some("_http._tcp".to_owned()));
+        let err = rt.block_on(connector.connect(example_test(addr))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    // Looks up `SRV` records that never come, or fail with an error,
+    // and fails to resolve hostnames.
+    struct BrokenSrvResolver(Option<io::ErrorKind>);
+
+    impl Resolve for BrokenSrvResolver {
+        type Future = FutureResult<dns::IpAddrs, io::Error>;
+
+        fn resolve(&self, _name: Name) -> Self::Future {
+            future::err(io::Error::new(io::ErrorKind::AddrNotAvailable, "hostname was resolved"))
+        }
+
+        fn resolve_srv(&self, _name: &str) -> dns::SrvFuture {
+            match self.0 {
+                Some(kind) => dns::SrvFuture::new(future::err(io::Error::new(kind, "SRV lookup failed"))),
+                None => dns::SrvFuture::new(future::empty()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_srv_lookup_times_out() {
+        let observer = Arc::new(RecordingObserver::default());
+        let mut connector = HttpConnector::new_with_resolver(BrokenSrvResolver(None), None);
+        connector.set_srv_service(Some("_http._tcp".to_owned()));
+        connector.set_dns_timeout(Some(Duration::from_millis(50)));
+        connector.set_observer(observer.clone());
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(example_test(([127, 0, 0, 1], 80).into()))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(*observer.0.lock().unwrap(), vec![
+            "resolve _http._tcp.example.test".to_owned(),
+            "resolved _http._tcp.example.test Err(TimedOut)".to_owned(),
+        ]);
+    }
+
+    #[test]
+    fn test_srv_lookup_errors_are_returned() {
+        let mut connector = HttpConnector::new_with_resolver(BrokenSrvResolver(Some(io::ErrorKind::NotFound)), None);
+        connector.set_srv_service(Some("_http._tcp".to_owned()));
+        connector.set_dns_negative_cache(Some(Duration::from_secs(60)));
+
+        // The hostname isn't resolved instead.
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(example_test(([127, 0, 0, 1], 80).into()))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "SRV lookup failed");
+
+        // The failed SRV lookup is cached like any other.
+        let cache = connector.config.dns_negative_cache.as_ref().unwrap();
+        assert!(cache.get(&Name::new("_http._tcp.example.test".to_owned(), 80)).is_some());
+    }
+
+    #[test]
+    fn test_internationalized_hosts_use_ascii_form() {
+        // Unicode hosts never reach the connector in a URI, even
+        // percent-encoded, so they're matched in their ASCII form.
+        assert!("http://b\u{fc}cher.example/".parse::<Uri>().is_err());
+        assert!("http://b%C3%BCcher.example/".parse::<Uri>().is_err());
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut connector = HttpConnector::new(1);
+        connector.add_host_override("B\u{fc}cher.example", vec![addr.ip()]);
+        let dst = Destination {
+            uri: format!("http://XN--BCHER-KVA.example:{}/", addr.port()).parse().unwrap(),
+        };
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+
+        // A Unicode host is converted before the overrides are looked at.
+        let connecting = connector.connecting("b\u{fc}cher.example".to_owned(), addr.port(), None);
+        let (sock, _) = rt.block_on(connecting).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn test_idna_error_names_label() {
+        let mut connector = HttpConnector::new_with_resolver(NeverResolver, None);
+        let host = || "b\u{fc} cher.example".to_owned();
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connecting(host(), 80, None)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert!(err.to_string().contains("\"b\u{fc} cher\""), "{}", err);
+
+        // Without converting, the host is resolved as it is.
+        connector.set_idna(false);
+        connector.set_dns_timeout(Some(Duration::from_millis(10)));
+        let err = rt.block_on(connector.connecting(host(), 80, None)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_dns_cache_skips_resolver() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let resolver = StaticResolver::new(addr);
+        let calls = resolver.calls.clone();
+        let mut connector = HttpConnector::new_with_resolver(resolver, None);
+        connector.set_dns_cache(Duration::from_secs(60), 16);
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(connector.connect(example_test(addr))).expect("connect 1");
+        rt.block_on(connector.clone().connect(example_test(addr))).expect("connect 2");
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_dns_cache_invalidated_when_every_address_fails() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let dead = dead_addr();
+        let resolver = StaticResolver::new(dead);
+        let calls = resolver.calls.clone();
+        let mut connector = HttpConnector::new_with_resolver(resolver, None);
+        connector.set_dns_cache(Duration::from_secs(60), 16);
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(connector.connect(example_test(addr))).unwrap_err();
+        rt.block_on(connector.connect(example_test(addr))).unwrap_err();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    // Fails every lookup with an error of its kind, counting them.
+    struct FailingResolver(io::ErrorKind, Arc<AtomicUsize>);
+
+    impl Resolve for FailingResolver {
+        type Future = FutureResult<dns::IpAddrs, io::Error>;
+
+        fn resolve(&self, _name: Name) -> Self::Future {
+            self.1.fetch_add(1, Ordering::SeqCst);
+            future::err(io::Error::new(self.0, "no such host"))
+        }
+    }
+
+    #[test]
+    fn test_dns_negative_cache() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let resolver = FailingResolver(io::ErrorKind::NotFound, calls.clone());
+        let mut connector = HttpConnector::new_with_resolver(resolver, None);
+        connector.set_dns_negative_cache(Some(Duration::from_millis(50)));
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(example_test(addr))).unwrap_err();
+        assert_eq!(err.to_string(), "no such host");
+        let err = rt.block_on(connector.connect(example_test(addr))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert!(err.to_string().contains("negative DNS cache"), "{}", err);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // An override is used anyway.
+        let mut overridden = connector.clone();
+        overridden.add_host_override("example.test", vec![addr.ip()]);
+        rt.block_on(overridden.connect(example_test(addr))).expect("override");
+
+        ::std::thread::sleep(Duration::from_millis(60));
+        rt.block_on(connector.connect(example_test(addr))).unwrap_err();
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_dns_negative_cache_skips_other_errors() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let resolver = FailingResolver(io::ErrorKind::Other, calls.clone());
+        let mut connector = HttpConnector::new_with_resolver(resolver, None);
+        connector.set_dns_negative_cache(Some(Duration::from_secs(60)));
+
+        let mut rt = Runtime::new().unwrap();
+        let dst = || example_test(([127, 0, 0, 1], 80).into());
+        let err = rt.block_on(connector.connect(dst())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+        let err = rt.block_on(connector.connect(dst())).unwrap_err();
+        assert_eq!(err.to_string(), "no such host");
+        assert_eq!(calls.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_socket_buffer_sizes_are_applied() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let uri = format!("http://{}", addr).parse().unwrap();
+        let dst = Destination {
+            uri,
+        };
+        let mut connector = HttpConnector::new(1);
+        connector.set_send_buffer_size(Some(64 * 1024));
+        connector.set_recv_buffer_size(Some(64 * 1024));
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        // The kernel may round (Linux doubles) the requested size.
+        assert!(sock.send_buffer_size().unwrap() >= 64 * 1024);
+        assert!(sock.recv_buffer_size().unwrap() >= 64 * 1024);
+    }
+
+    #[test]
+    fn test_socket_buffer_sizes_reset_to_default() {
+        use std::net::TcpStream as StdTcpStream;
+        use net2::TcpStreamExt;
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let dst = || Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let mut connector = HttpConnector::new(1);
+        connector.set_send_buffer_size(Some(4 * 1024 * 1024));
+        connector.set_send_buffer_size(None);
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst())).expect("connect");
+        let plain = StdTcpStream::connect(addr).unwrap();
+        assert_eq!(sock.send_buffer_size().unwrap(), plain.send_buffer_size().unwrap());
+    }
+
+    #[test]
+    fn test_local_port() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let port = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let mut connector = HttpConnector::new(1);
+        connector.set_local_address(Some("127.0.0.1".parse().unwrap()));
+        connector.set_local_port(Some(port));
+        connector.set_reuse_address(true);
+
+        let dst = Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(sock.local_addr().unwrap().port(), port);
+        let (_, peer) = server.accept().unwrap();
+        assert_eq!(peer, sock.local_addr().unwrap());
+    }
+
+    #[test]
+    fn test_local_port_range_skips_ports_in_use() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        // A listening socket can't be shared even with SO_REUSEADDR.
+        let taken = TcpListener::bind("127.0.0.1:0").unwrap();
+        let first = taken.local_addr().unwrap().port();
+
+        let mut connector = HttpConnector::new(1);
+        connector.set_local_address(Some([127, 0, 0, 1].into()));
+        connector.set_local_port_range(Some(first..first + 5));
+
+        let uri = format!("http://{}", addr).parse().unwrap();
+        let dst = Destination {
+            uri,
+        };
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        let port = sock.local_addr().unwrap().port();
+        assert!(port > first && port < first + 5, "local port {} not in range", port);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_windows_local_address() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut connector = HttpConnector::new(1);
+        connector.set_local_address(Some([127, 0, 0, 1].into()));
+
+        let dst = Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        let local = sock.local_addr().unwrap();
+        assert_eq!(local.ip(), IpAddr::from([127, 0, 0, 1]));
+        assert_ne!(local.port(), 0);
+        let (_, peer) = server.accept().unwrap();
+        assert_eq!(peer, local);
+    }
+
+    #[test]
+    fn test_local_address_candidates_fall_back() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+
+        let mut connector = HttpConnector::new(1);
+        // 192.0.2.1 is reserved for documentation, so never assigned here.
+        connector.set_local_address_candidates(vec![
+            [0, 0, 0, 0, 0, 0, 0, 1].into(),
+            [192, 0, 2, 1].into(),
+            [127, 0, 0, 1].into(),
+        ]);
+        let dst = Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(sock.local_addr().unwrap().ip(), IpAddr::from([127, 0, 0, 1]));
+
+        // With no address that can be bound, the last error is reported.
+        connector.set_local_address_candidates(vec![[192, 0, 2, 1].into()]);
+        let dst = Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let err = rt.block_on(connector.connect(dst)).unwrap_err();
+        assert!(err.to_string().contains("192.0.2.1"), "{}", err);
+    }
+
+    #[test]
+    fn test_local_address_skips_other_family() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let v6 = SocketAddr::new([0, 0, 0, 0, 0, 0, 0, 1].into(), addr.port());
+
+        let mut connector = HttpConnector::new(1);
+        connector.set_local_addresses(Some([127, 0, 0, 1].into()), None);
+        connector.set_happy_eyeballs_timeout(None);
+
+        let addrs = dns::IpAddrs::new(vec![v6, addr]);
+        let mut connecting = ConnectingTcp::new(addrs, connector.config.clone());
+
+        let mut rt = Runtime::new().unwrap();
+        let sock = rt.block_on(poll_fn(|| connecting.poll(&None))).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+
+        connector.set_local_address_strict(true);
+        let addrs = dns::IpAddrs::new(vec![v6]);
+        let mut connecting = ConnectingTcp::new(addrs, connector.config.clone());
+        let err = rt.block_on(poll_fn(|| connecting.poll(&None))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+        assert_eq!(err.to_string(), "no resolved addresses match local address family IPv4");
+    }
+
+    #[test]
+    fn test_local_address_falls_back_to_other_family() {
+        // Skip if the host has no IPv6 loopback.
+        let server = match ipv6_loopback() {
+            Some(server) => server,
+            None => return,
+        };
+        let addr = server.local_addr().unwrap();
+
+        let mut connector = HttpConnector::new(1);
+        connector.set_local_address(Some([127, 0, 0, 1].into()));
+        let mut connecting = ConnectingTcp::new(dns::IpAddrs::new(vec![addr]), connector.config.clone());
+
+        // Not strict, so the address is connected to without binding.
+        let mut rt = Runtime::new().unwrap();
+        let sock = rt.block_on(poll_fn(|| connecting.poll(&None))).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        assert!(sock.local_addr().unwrap().is_ipv6());
+    }
+
+    #[test]
+    fn test_dedupe_addresses() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        // Resolved twice.
+        let refused = dead_addr();
+        let attempts = |dedupe: bool| {
+            let resolver = StaticResolver::with_addrs(vec![refused, refused, addr]);
+            let mut connector = HttpConnector::new_with_resolver(resolver, None);
+            let observer = Arc::new(super::super::CountingObserver::new());
+            connector.set_observer(observer.clone());
+            connector.set_dedupe_addresses(dedupe);
+
+            let mut rt = Runtime::new().unwrap();
+            let (sock, _) = rt.block_on(connector.connect(example_test(addr))).expect("connect");
+            assert_eq!(sock.peer_addr().unwrap(), addr);
+            observer.attempts()
+        };
+        assert_eq!(attempts(true), 2);
+        assert_eq!(attempts(false), 3);
+    }
+
+    #[test]
+    fn test_interleave_families() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let v6 = |n| discard_addr(n, addr.port());
+        let attempts = |interleave: bool| {
+            let resolver = StaticResolver::with_addrs(vec![v6(1), v6(2), addr]);
+            let mut connector = HttpConnector::new_with_resolver(resolver, None);
+            connector.set_happy_eyeballs_timeout(None);
+            connector.set_connect_timeout(Some(Duration::from_millis(100)));
+            connector.set_interleave_families(interleave);
+            let observer = Arc::new(super::super::CountingObserver::new());
+            connector.set_observer(observer.clone());
+
+            let mut rt = Runtime::new().unwrap();
+            let (sock, _) = rt.block_on(connector.connect(example_test(addr))).expect("connect");
+            assert_eq!(sock.peer_addr().unwrap(), addr);
+            observer.attempts()
+        };
+        assert_eq!(attempts(true), 2);
+        assert_eq!(attempts(false), 3);
+    }
+
+    #[test]
+    fn test_empty_resolution_is_an_error() {
+        struct EmptyResolver;
+
+        impl Resolve for EmptyResolver {
+            type Future = FutureResult<dns::IpAddrs, io::Error>;
+
+            fn resolve(&self, _name: Name) -> Self::Future {
+                future::ok(dns::IpAddrs::new(Vec::new()))
+            }
+        }
+
+        let mut connector = HttpConnector::new_with_resolver(EmptyResolver, None);
+        connector.set_dns_cache(Duration::from_secs(60), 8);
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(example_test(([127, 0, 0, 1], 80).into()))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "dns returned no addresses for host example.test");
+        // Nothing was cached, so the name isn't skipped next time.
+        assert!(connector.config.dns_cache.as_ref().unwrap().get(&Name::new("example.test".to_owned(), 80)).is_none());
+    }
+
+    #[test]
+    fn test_ip_family_preference_only_errors_without_matches() {
+        let addr: SocketAddr = "[::1]:80".parse().unwrap();
+        let mut connector = HttpConnector::new_with_resolver(StaticResolver::new(addr), None);
+        connector.set_ip_family_preference(IpFamilyPreference::Ipv4Only);
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(example_test(addr))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+        assert!(err.to_string().contains("Ipv4Only"), "{}", err);
+    }
+
+    #[test]
+    fn test_ip_family_preference_applies_to_literals() {
+        let mut connector = HttpConnector::new(1);
+        connector.set_ip_family_preference(IpFamilyPreference::Ipv6Only);
+        let dst = Destination {
+            uri: "http://127.0.0.1:80".parse().unwrap(),
+        };
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(dst)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+        assert!(err.to_string().contains("Ipv6Only"), "{}", err);
+    }
+
+    #[test]
+    fn test_ip_family_preference_with_local_address() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let v6 = SocketAddr::new("::1".parse().unwrap(), addr.port());
+        let observer = Arc::new(RecordingObserver::default());
+        let mut connector = HttpConnector::new_with_resolver(StaticResolver::with_addrs(vec![v6, addr]), None);
+        connector.set_ip_family_preference(IpFamilyPreference::Ipv6First);
+        connector.set_local_addresses(Some([127, 0, 0, 1].into()), None);
+        connector.set_observer(observer.clone());
+
+        // The preference orders, the local address filters, and the
+        // unmatched family is skipped without an error.
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(example_test(addr))).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        assert!(!observer.0.lock().unwrap().contains(&format!("attempt {}", v6)));
+    }
+
+    #[test]
+    fn test_connect_retries_until_listener_accepts() {
+        use std::thread;
+
+        // Find a free port, and start listening on it only after the
+        // first couple of connects were refused.
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let server = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(120));
+            let listener = TcpListener::bind(addr).unwrap();
+            listener.accept().unwrap();
+        });
+
+        let mut connector = HttpConnector::new(1);
+        connector.set_connect_retries(6, Duration::from_millis(50));
+        let dst = Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_connect_retries_error_reports_attempts() {
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+
+        let mut connector = HttpConnector::new(1);
+        connector.set_connect_retries(2, Duration::from_millis(1));
+        let dst = Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(dst)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        assert!(err.to_string().contains("after 3 attempts"), "{}", err);
+        // The error of the address keeps its last attempt's error.
+        let connect_err = err.get_ref().and_then(|e| e.downcast_ref::<ConnectError>()).expect("ConnectError");
+        let attempts_err = &connect_err.attempts()[0].1;
+        let last = attempts_err.get_ref().and_then(|e| e.cause()).expect("last error");
+        assert!(!last.to_string().contains("attempts"), "{}", last);
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_interface_is_bound() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let dst = |addr: &SocketAddr| Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let mut rt = Runtime::new().unwrap();
+
+        let mut connector = HttpConnector::new(1);
+        connector.set_interface(Some("lo".to_owned()));
+        rt.block_on(connector.connect(dst(&addr))).expect("connect via lo");
+
+        connector.set_interface(Some("hyper-none0".to_owned()));
+        let err = rt.block_on(connector.connect(dst(&addr))).unwrap_err();
+        assert_ne!(err.kind(), io::ErrorKind::Other, "{}", err);
+
+        connector.set_interface(Some("hyper-too-long-name0".to_owned()));
+        let err = rt.block_on(connector.connect(dst(&addr))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput, "{}", err);
+    }
+
+    #[cfg(not(any(target_os = "android", target_os = "linux")))]
+    #[test]
+    fn test_interface_is_unsupported() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let dst = Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let mut connector = HttpConnector::new(1);
+        connector.set_interface(Some("lo".to_owned()));
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(dst)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Other);
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    fn get_int_opt<S: ::std::os::unix::io::AsRawFd>(sock: &S, level: ::libc::c_int, opt: ::libc::c_int) -> ::libc::c_int {
+        use std::mem;
+        use libc;
+
+        let mut val: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let ret = unsafe {
+            libc::getsockopt(
+                sock.as_raw_fd(),
+                level,
+                opt,
+                &mut val as *mut libc::c_int as *mut libc::c_void,
+                &mut len,
+            )
+        };
+        assert_eq!(ret, 0, "getsockopt: {}", io::Error::last_os_error());
+        val
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_keepalive_probes_are_applied() {
+        use libc;
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let dst = || Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let mut connector = HttpConnector::new(1);
+        connector.set_keepalive_interval(Some(Duration::from_secs(7)));
+        connector.set_keepalive_retries(Some(3));
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(dst())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        connector.set_keepalive(Some(Duration::from_secs(60)));
+        let (sock, _) = rt.block_on(connector.connect(dst())).expect("connect");
+        assert_eq!(get_int_opt(&sock, libc::IPPROTO_TCP, libc::TCP_KEEPINTVL), 7);
+        assert_eq!(get_int_opt(&sock, libc::IPPROTO_TCP, libc::TCP_KEEPCNT), 3);
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_user_timeout_is_applied() {
+        use libc;
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let dst = Destination {
+            uri: format!("http://{}", server.local_addr().unwrap()).parse().unwrap(),
+        };
+        let mut connector = HttpConnector::new(1);
+        connector.set_user_timeout(Some(Duration::from_millis(2500)));
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(get_int_opt(&sock, libc::IPPROTO_TCP, libc::TCP_USER_TIMEOUT), 2500);
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_keepalive_params_are_applied() {
+        use libc;
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let dst = Destination {
+            uri: format!("http://{}", server.local_addr().unwrap()).parse().unwrap(),
+        };
+        let mut connector = HttpConnector::new(1);
+        connector.set_keepalive_params(Duration::from_secs(45), Duration::from_secs(5), 4);
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(get_int_opt(&sock, libc::IPPROTO_TCP, libc::TCP_KEEPIDLE), 45);
+        assert_eq!(get_int_opt(&sock, libc::IPPROTO_TCP, libc::TCP_KEEPINTVL), 5);
+        assert_eq!(get_int_opt(&sock, libc::IPPROTO_TCP, libc::TCP_KEEPCNT), 4);
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_fwmark_is_applied_to_every_attempt() {
+        use libc;
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let dead = dead_addr();
+        let mut connector = HttpConnector::new_with_resolver(StaticResolver::with_addrs(vec![dead, addr]), None);
+        connector.set_happy_eyeballs_timeout(None);
+        connector.set_fwmark(Some(42));
+
+        let mut rt = Runtime::new().unwrap();
+        match rt.block_on(connector.connect(example_test(addr))) {
+            Ok((sock, _)) => {
+                assert_eq!(sock.peer_addr().unwrap(), addr);
+                assert_eq!(get_int_opt(&sock, libc::SOL_SOCKET, libc::SO_MARK), 42);
+            },
+            // Without CAP_NET_ADMIN, the error has to say so.
+            Err(err) => assert_eq!(err.kind(), io::ErrorKind::PermissionDenied, "{}", err),
+        }
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_tos_is_applied() {
+        use libc;
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let dst = Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let mut connector = HttpConnector::new(1);
+        connector.set_tos(Some(0xb8));
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(get_int_opt(&sock, libc::IPPROTO_IP, libc::IP_TOS), 0xb8);
+
+        connector.set_tos(Some(256));
+        let dst = Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let err = rt.block_on(connector.connect(dst)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_tos_is_applied_ipv6() {
+        use libc;
+
+        // Skip if the host has no IPv6 loopback.
+        let server = match ipv6_loopback() {
+            Some(server) => server,
+            None => return,
+        };
+        let addr = server.local_addr().unwrap();
+        let dst = Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let mut connector = HttpConnector::new(1);
+        connector.set_tos(Some(0xb8));
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(get_int_opt(&sock, libc::IPPROTO_IPV6, libc::IPV6_TCLASS), 0xb8);
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_tcp_fastopen_is_applied() {
+        use libc;
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let dst = || Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let mut connector = HttpConnector::new(1);
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst())).expect("connect");
+        assert_eq!(get_int_opt(&sock, libc::IPPROTO_TCP, libc::TCP_FASTOPEN_CONNECT), 0);
+
+        connector.set_tcp_fastopen(true);
+        let (sock, _) = rt.block_on(connector.connect(dst())).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        assert_eq!(get_int_opt(&sock, libc::IPPROTO_TCP, libc::TCP_FASTOPEN_CONNECT), 1);
+    }
+
+    #[test]
+    fn test_reuse_address_many_connections() {
+        use std::thread;
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let accepts = thread::spawn(move || {
+            for _ in 0..50 {
+                server.accept().unwrap();
+            }
+        });
+
+        let mut connector = HttpConnector::new(1);
+        connector.set_local_address(Some([127, 0, 0, 1].into()));
+        connector.set_reuse_address(true);
+
+        let mut rt = Runtime::new().unwrap();
+        for _ in 0..50 {
+            let dst = Destination {
+                uri: format!("http://{}", addr).parse().unwrap(),
+            };
+            // Dropped right away, leaving the client side in TIME_WAIT.
+            rt.block_on(connector.connect(dst)).expect("connect");
+        }
+        accepts.join().unwrap();
+    }
+
+    #[cfg(any(target_os = "android", target_os = "linux"))]
+    #[test]
+    fn test_reuse_port_only_when_bound() {
+        use libc;
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let dst = || Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let mut connector = HttpConnector::new(1);
+        connector.set_reuse_address(true);
+        connector.set_reuse_port(true);
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst())).expect("connect");
+        assert_eq!(get_int_opt(&sock, libc::SOL_SOCKET, libc::SO_REUSEADDR), 0);
+        assert_eq!(get_int_opt(&sock, libc::SOL_SOCKET, libc::SO_REUSEPORT), 0);
+
+        connector.set_local_address(Some([127, 0, 0, 1].into()));
+        let (sock, _) = rt.block_on(connector.connect(dst())).expect("connect");
+        assert_ne!(get_int_opt(&sock, libc::SOL_SOCKET, libc::SO_REUSEADDR), 0);
+        assert_ne!(get_int_opt(&sock, libc::SOL_SOCKET, libc::SO_REUSEPORT), 0);
+    }
+
+    #[test]
+    fn test_bind_error_names_local_address() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let dst = Destination {
+            uri: format!("http://{}", server.local_addr().unwrap()).parse().unwrap(),
+        };
+        // TEST-NET-1, which isn't assigned to any local interface.
+        let mut connector = HttpConnector::new(1);
+        connector.set_local_address(Some([192, 0, 2, 1].into()));
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(dst)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrNotAvailable);
+        assert!(err.to_string().contains("failed to bind local address 192.0.2.1:0"), "{}", err);
+    }
+
+    #[test]
+    fn test_default_port_for_custom_scheme() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let dst = |s: String| Destination {
+            uri: s.parse().unwrap(),
+        };
+
+        let mut connector = HttpConnector::new(1);
+        connector.enforce_http(false);
+        connector.set_default_port("foo", addr.port());
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst("foo://127.0.0.1/".to_owned()))).expect("default port");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+
+        // An explicit port still wins over the default.
+        connector.set_default_port("foo", 1);
+        let uri = format!("foo://127.0.0.1:{}/", addr.port());
+        let (sock, _) = rt.block_on(connector.connect(dst(uri))).expect("explicit port");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn test_connected_remote_addr() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let dst = Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let connector = HttpConnector::new(1);
+
+        let mut rt = Runtime::new().unwrap();
+        let (_, connected) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(connected.remote_addr, Some(addr));
+        assert!(!connected.is_resolved);
+
+        let connector = HttpConnector::new_with_resolver(StaticResolver::new(addr), None);
+        let (_, connected) = rt.block_on(connector.connect(example_test(addr))).expect("connect");
+        assert_eq!(connected.remote_addr, Some(addr));
+        assert!(connected.is_resolved);
+    }
+
+    #[test]
+    fn test_connect_refused_then_accepted() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let dead = dead_addr();
+        let mut connector = HttpConnector::new_with_resolver(StaticResolver::with_addrs(vec![dead, addr]), None);
+        connector.set_happy_eyeballs_timeout(None);
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(example_test(addr))).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn test_connect_error_lists_all_attempts() {
+        // Both bound at once, so they can't be given the same port.
+        let (a, b) = (TcpListener::bind("127.0.0.1:0").unwrap(), TcpListener::bind("127.0.0.1:0").unwrap());
+        let (first, second) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+        drop((a, b));
+        let mut connector = HttpConnector::new(1);
+        connector.set_happy_eyeballs_timeout(None);
+
+        let addrs = dns::IpAddrs::new(vec![first, second]);
+        let mut connecting = ConnectingTcp::new(addrs, connector.config.clone());
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(poll_fn(|| connecting.poll(&None))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+
+        let connect_err = err.get_ref().and_then(|e| e.downcast_ref::<ConnectError>()).expect("ConnectError");
+        let tried = connect_err.attempts().iter().map(|&(addr, _)| addr).collect::<Vec<_>>();
+        assert_eq!(tried, vec![first, second]);
+        let msg = err.to_string();
+        assert!(msg.contains(&first.to_string()) && msg.contains(&second.to_string()), "{}", msg);
+    }
+
+    #[test]
+    fn test_filtered_addresses_are_consumed() {
+        let (a, b) = (TcpListener::bind("127.0.0.1:0").unwrap(), TcpListener::bind("127.0.0.1:0").unwrap());
+        let (first, second) = (a.local_addr().unwrap(), b.local_addr().unwrap());
+        drop((a, b));
+        let v6 = SocketAddr::new([0, 0, 0, 0, 0, 0, 0, 1].into(), first.port());
+
+        let mut connector = HttpConnector::new(1);
+        connector.set_local_addresses(Some([127, 0, 0, 1].into()), None);
+        connector.set_happy_eyeballs_timeout(None);
+
+        let addrs = dns::IpAddrs::new(vec![v6, first, v6, second, v6]);
+        let mut connecting = ConnectingTcp::new(addrs, connector.config.clone());
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(poll_fn(|| connecting.poll(&None))).unwrap_err();
+        let connect_err = err.get_ref().and_then(|e| e.downcast_ref::<ConnectError>()).expect("ConnectError");
+        let tried = connect_err.attempts().iter().map(|&(addr, _)| addr).collect::<Vec<_>>();
+        // Each matching address once, in order, never starting over.
+        assert_eq!(tried, vec![first, second]);
+    }
+
+    #[test]
+    fn test_unreachable_addresses_are_attempted_once() {
+        let port = dead_addr().port();
+        let dead = (2..5)
+            .map(|i| SocketAddr::new([127, 0, 0, i].into(), port))
+            .collect::<Vec<_>>();
+        let observer = Arc::new(RecordingObserver::default());
+        let mut connector = HttpConnector::new_with_resolver(StaticResolver::with_addrs(dead.clone()), None);
+        connector.set_happy_eyeballs_timeout(None);
+        connector.set_observer(observer.clone());
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(example_test(dead[0]))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+
+        let attempts = observer.0.lock().unwrap().iter()
+            .filter(|event| event.starts_with("attempt "))
+            .cloned()
+            .collect::<Vec<_>>();
+        let expected = dead.iter().map(|addr| format!("attempt {}", addr)).collect::<Vec<_>>();
+        assert_eq!(attempts, expected);
+    }
+
+    #[test]
+    fn test_connect_error_for_ip_literal() {
+        let addr = dead_addr();
+        let dst = Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let connector = HttpConnector::new(1);
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(dst)).unwrap_err();
+        let connect_err = err.get_ref().and_then(|e| e.downcast_ref::<ConnectError>()).expect("ConnectError");
+        assert_eq!(connect_err.attempts().len(), 1);
+        assert_eq!(connect_err.attempts()[0].0, addr);
+        assert_eq!((connect_err.host(), connect_err.port()), ("127.0.0.1", addr.port()));
+        assert!(err.to_string().starts_with(&format!("failed to connect to {}: {} (", addr, addr)), "{}", err);
+    }
+
+    #[test]
+    fn test_connect_error_names_host() {
+        let addr = dead_addr();
+        let v6 = discard_addr(1, addr.port());
+
+        let mut connector = HttpConnector::new_with_resolver(StaticResolver::with_addrs(vec![v6, addr]), None);
+        connector.set_happy_eyeballs_timeout(None);
+        connector.set_connect_timeout(Some(Duration::from_millis(100)));
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(example_test(addr))).unwrap_err();
+        let msg = err.to_string();
+        assert!(msg.starts_with(&format!("failed to connect to example.test:{}: ", addr.port())), "{}", msg);
+        assert!(msg.contains(&v6.to_string()) && msg.contains(&addr.to_string()), "{}", msg);
+    }
+
+    struct NeverResolver;
+
+    impl Resolve for NeverResolver {
+        type Future = future::Empty<dns::IpAddrs, io::Error>;
+
+        fn resolve(&self, _name: Name) -> Self::Future {
+            future::empty()
+        }
+    }
+
+    #[test]
+    fn test_dns_timeout() {
+        let mut connector = HttpConnector::new_with_resolver(NeverResolver, None);
+        connector.set_dns_timeout(Some(Duration::from_millis(50)));
+        let dst = Destination {
+            uri: "http://example.test".parse().unwrap(),
+        };
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(dst)).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(err.to_string().contains("example.test"), "{}", err);
+    }
+
+    #[test]
+    fn test_dns_timeout_abandons_slow_lookup() {
+        use std::sync::mpsc;
+        use std::thread;
+        use futures::future::{ExecuteError, Executor};
+
+        // Runs each lookup on its own thread, after a pause.
+        struct SlowExecutor(mpsc::Sender<()>);
+
+        impl Executor<dns::GaiTask> for SlowExecutor {
+            fn execute(&self, task: dns::GaiTask) -> Result<(), ExecuteError<dns::GaiTask>> {
+                let done = self.0.clone();
+                thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(200));
+                    let _ = task.wait();
+                    let _ = done.send(());
+                });
+                Ok(())
+            }
+        }
+
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let (tx, rx) = mpsc::channel();
+        let mut connector = HttpConnector::new_with_executor(SlowExecutor(tx), None);
+        connector.set_resolve_localhost(true);
+        connector.set_dns_timeout(Some(Duration::from_millis(50)));
+        let dst = || Destination {
+            uri: format!("http://localhost:{}", addr.port()).parse().unwrap(),
+        };
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(dst())).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(err.to_string().contains("localhost"), "{}", err);
+
+        // The abandoned lookup finishes later, and its result is dropped
+        // without affecting the next connect.
+        rx.recv_timeout(Duration::from_secs(5)).expect("lookup finished");
+        connector.set_dns_timeout(None);
+        let (sock, _) = rt.block_on(connector.connect(dst())).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+    }
+
+    #[test]
+    fn test_connected_reports_winning_family() {
+        // Skip if the host has no IPv6 loopback.
+        let server = match ipv6_loopback() {
+            Some(server) => server,
+            None => return,
+        };
+        let addr = server.local_addr().unwrap();
+        // IPv4 is tried first, but nothing listens on it.
+        let dead = dead_addr();
+        let connector = HttpConnector::new_with_resolver(StaticResolver::with_addrs(vec![dead, addr]), None);
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, connected) = rt.block_on(connector.connect(example_test(addr))).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        assert!(connected.is_ipv6());
+        assert!(connected.http_info().expect("http info").is_ipv6());
+    }
+
+    #[test]
+    fn test_max_pending_connects() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut connector = HttpConnector::new_with_resolver(NeverResolver, None);
+        connector.set_max_pending_connects(Some(1));
+        let clone = connector.clone();
+
+        let mut rt = Runtime::new().unwrap();
+        // Never resolves, so keeps its turn.
+        let mut stuck = Some(connector.connect(example_test(addr)));
+        let mut waiting = clone.connect(Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        });
+        rt.block_on(poll_fn(|| {
+            assert!(stuck.as_mut().unwrap().poll()?.is_not_ready());
+            assert!(waiting.poll()?.is_not_ready());
+            assert_eq!((connector.pending_connects(), connector.queued_connects()), (1, 1));
+            Ok::<_, io::Error>(::futures::Async::Ready(()))
+        })).unwrap();
+
+        // Dropping the stuck connect gives the turn to the next.
+        stuck.take();
+        let (sock, _) = rt.block_on(waiting).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        assert_eq!((clone.pending_connects(), clone.queued_connects()), (0, 0));
+    }
+
+    #[test]
+    fn test_connected_reports_timings() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let connector = HttpConnector::new_with_resolver(StaticResolver::new(addr), None);
+
+        let mut rt = Runtime::new().unwrap();
+        let (_, connected) = rt.block_on(connector.connect(example_test(addr))).expect("connect");
+        assert!(connected.dns_time().is_some());
+        assert!(connected.connect_time().is_some());
+
+        // Nothing is resolved for an IP address.
+        let dst = Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let (_, connected) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(connected.dns_time(), None);
+        assert!(connected.connect_time().is_some());
+    }
+
+    #[test]
+    fn test_connect_timing() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let connector = HttpConnector::new_with_resolver(StaticResolver::new(addr), None);
+
+        let mut rt = Runtime::new().unwrap();
+        let (_, connected) = rt.block_on(connector.connect(example_test(addr))).expect("connect");
+        let timing = connected.timing().expect("timing");
+        assert_eq!(timing.dns(), connected.dns_time());
+        assert!(timing.dns().is_some());
+        assert_eq!(timing.attempts(), 1);
+
+        let dst = Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+        let (_, connected) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(connected.timing().expect("timing").dns(), None);
+
+        // Failures report it too, only resolving if there was a name.
+        let dead = dead_addr();
+        let connector = HttpConnector::new_with_resolver(StaticResolver::new(dead), None);
+        let err = rt.block_on(connector.connect(example_test(dead))).unwrap_err();
+        let timing = err.get_ref()
+            .and_then(|e| e.downcast_ref::<ConnectError>())
+            .and_then(ConnectError::timing)
+            .expect("timing");
+        assert!(timing.dns().is_some());
+        assert_eq!(timing.attempts(), 1);
+        assert!(err.to_string().contains("; dns "), "{}", err);
+        assert!(err.to_string().ends_with(", 1 attempt"), "{}", err);
+
+        let dst = Destination {
+            uri: format!("http://{}", dead).parse().unwrap(),
+        };
+        let err = rt.block_on(connector.connect(dst)).unwrap_err();
+        assert!(err.to_string().contains("; tcp "), "{}", err);
+        assert!(!err.to_string().contains("dns"), "{}", err);
+    }
+
+    #[test]
+    fn test_connect_bracketed_ipv6_literal() {
+        // Skip if the host has no IPv6 loopback.
+        let server = match ipv6_loopback() {
+            Some(server) => server,
+            None => return,
+        };
+        let addr = server.local_addr().unwrap();
+        let connector = HttpConnector::new(1);
+        let dst = Destination {
+            uri: format!("http://[::1]:{}/", addr.port()).parse().unwrap(),
+        };
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, connected) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        assert!(!connected.is_resolved);
+    }
+
+    #[test]
+    fn test_fallback_delay_starts_next_address() {
+        // Skip if the host has no IPv6 loopback.
+        let server = match ipv6_loopback() {
+            Some(server) => server,
+            None => return,
+        };
+        let addr = server.local_addr().unwrap();
+        let slow = discard_addr(1, addr.port());
+
+        let mut connector = HttpConnector::new(1);
+        connector.set_happy_eyeballs_timeout(None);
+        connector.set_fallback_delay(Some(Duration::from_millis(50)));
+
+        let addrs = dns::IpAddrs::new(vec![slow, addr]);
+        let mut connecting = ConnectingTcp::new(addrs, connector.config.clone());
+
+        let mut rt = Runtime::new().unwrap();
+        let sock = rt.block_on(poll_fn(|| connecting.poll(&None))).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        assert!(connecting.preferred.current.is_empty());
+    }
+
+    #[test]
+    fn test_max_concurrent_attempts() {
+        // Skip if the host has no IPv6 loopback.
+        let server = match ipv6_loopback() {
+            Some(server) => server,
+            None => return,
+        };
+        let addr = server.local_addr().unwrap();
+        let slow = (1..4).map(|n| discard_addr(n, addr.port())).collect::<Vec<_>>();
+
+        let observer = Arc::new(RecordingObserver::default());
+        let mut connector = HttpConnector::new(1);
+        connector.set_happy_eyeballs_timeout(None);
+        connector.set_fallback_delay(Some(Duration::from_millis(10)));
+        connector.set_connect_timeout(Some(Duration::from_millis(100)));
+        connector.set_observer(observer.clone());
+
+        let mut addrs = slow.clone();
+        addrs.push(addr);
+        let mut connecting = ConnectingTcp::new(dns::IpAddrs::new(addrs), connector.config.clone());
+
+        let mut rt = Runtime::new().unwrap();
+        let sock = rt.block_on(poll_fn(|| connecting.poll(&None))).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+
+        let events = observer.0.lock().unwrap();
+        let mut in_flight = 0;
+        let mut most = 0;
+        for event in events.iter() {
+            if event.starts_with("attempt ") {
+                in_flight += 1;
+            } else {
+                in_flight -= 1;
+            }
+            most = cmp::max(most, in_flight);
+        }
+        assert_eq!(most, 2, "{:?}", *events);
+        // The third slow address only starts once the first timed out.
+        assert_eq!(events[..3], [
+            format!("attempt {}", slow[0]),
+            format!("attempt {}", slow[1]),
+            format!("failed {}", slow[0]),
+        ]);
+    }
+
+    #[test]
+    fn test_no_addrs_is_an_error() {
+        let connector = HttpConnector::new(1);
+        let mut connecting = ConnectingTcp::new(dns::IpAddrs::new(Vec::new()), connector.config.clone());
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(poll_fn(|| connecting.poll(&None))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "no addresses to connect to");
+    }
+
+    #[test]
+    fn test_uri_resolver_override_without_addrs() {
+        let mut connector = HttpConnector::new_with_resolver(NeverResolver, None);
+        connector.set_uri_resolver_override(Arc::new(|_: &Uri| Some(dns::IpAddrs::new(Vec::new()))));
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(example_test(([127, 0, 0, 1], 80).into()))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+
+    #[test]
+    fn test_resize_dns_threads() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut connector = HttpConnector::new(1);
+        connector.set_resolve_localhost(true);
+        connector.set_ip_family_preference(IpFamilyPreference::Ipv4Only);
+        connector.resize_dns_threads(8).expect("resize");
+
+        let dst = Destination {
+            uri: format!("http://localhost:{}", addr.port()).parse().unwrap(),
+        };
+        let mut rt = Runtime::new().unwrap();
+        let (sock, connected) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        assert!(connected.is_resolved);
+
+        let mut connector = HttpConnector::new_with_executor(::futures_cpupool::CpuPool::new(1), None);
+        assert!(connector.resize_dns_threads(2).is_err());
+    }
+
+    #[test]
+    fn test_debug() {
+        let mut connector = HttpConnector::new(1);
+        connector.set_keepalive(Some(Duration::from_secs(75)));
+        connector.set_nodelay(true);
+        connector.set_local_address(Some([127, 0, 0, 1].into()));
+        let debug = format!("{:?}", connector);
+        assert!(debug.starts_with("HttpConnector { allowed_schemes: Some([\"http\"]), "), "{}", debug);
+        assert!(debug.contains("enforce_http: true"), "{}", debug);
+        assert!(debug.contains("keep_alive_timeout: Some(75s)"), "{}", debug);
+        assert!(debug.contains("local_address: Some(127.0.0.1)"), "{}", debug);
+        assert!(debug.contains("nodelay: true"), "{}", debug);
+
+        let connecting = connector.connect(example_test(([127, 0, 0, 1], 80).into()));
+        assert_eq!(format!("{:?}", connecting), "HttpConnecting { state: Lazy }");
+        let connecting = connector.connect(Destination {
+            uri: "https://example.test".parse().unwrap(),
+        });
+        assert_eq!(format!("{:?}", connecting), "HttpConnecting { state: Error }");
+    }
+
+    #[test]
+    fn test_connect_deadline_fn() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let mut connector = HttpConnector::new_with_resolver(StaticResolver::new(addr), None);
+        connector.set_connect_deadline_fn(Arc::new(|| Some(Instant::now() - Duration::from_millis(1))));
+
+        // Fails without polling a timer, or even a runtime.
+        let err = connector.connect(example_test(addr)).wait().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert_eq!(err.to_string(), "connect deadline passed");
+
+        connector.set_connect_deadline_fn(Arc::new(|| Some(Instant::now() + Duration::from_millis(50))));
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(connector.connect(example_test(addr))).expect("connect before the deadline");
+
+        let mut connector = HttpConnector::new_with_resolver(NeverResolver, None);
+        connector.set_connect_deadline_fn(Arc::new(|| Some(Instant::now() + Duration::from_millis(50))));
+        let err = rt.block_on(connector.connect(example_test(addr))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+
+        // No deadline from the function is no deadline.
+        let mut connector = HttpConnector::new_with_resolver(StaticResolver::new(addr), None);
+        connector.set_connect_deadline_fn(Arc::new(|| None));
+        rt.block_on(connector.connect(example_test(addr))).expect("connect without a deadline");
+    }
+
+    #[test]
+    fn test_host_override_without_addrs() {
+        let mut connector = HttpConnector::new_with_resolver(NeverResolver, None);
+        connector.add_host_override("example.test", Vec::new());
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(example_test(([127, 0, 0, 1], 80).into()))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+        assert_eq!(err.to_string(), "no addresses to connect to");
+    }
+
+    #[test]
+    fn test_max_addrs_attempted() {
+        let listeners = (0..5).map(|_| TcpListener::bind("127.0.0.1:0").unwrap()).collect::<Vec<_>>();
+        let addrs = listeners.iter().map(|l| l.local_addr().unwrap()).collect::<Vec<_>>();
+        drop(listeners);
+
+        let mut connector = HttpConnector::new(1);
+        connector.set_happy_eyeballs_timeout(None);
+        connector.set_max_addrs_attempted(Some(2));
+
+        let mut connecting = ConnectingTcp::new(dns::IpAddrs::new(addrs.clone()), connector.config.clone());
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(poll_fn(|| connecting.poll(&None))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        let connect_err = err.get_ref().and_then(|e| e.downcast_ref::<ConnectError>()).expect("ConnectError");
+        let tried = connect_err.attempts().iter().map(|&(addr, _)| addr).collect::<Vec<_>>();
+        assert_eq!(tried, &addrs[..2]);
+        assert_eq!(connect_err.skipped(), 3);
+        assert!(err.to_string().ends_with(", and 3 more addresses not attempted"), "{}", err);
+    }
+
+    #[test]
+    fn test_max_addrs_attempted_with_fallback_family() {
+        let v4 = (0..2).map(|_| TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap()).collect::<Vec<_>>();
+        // Skip if the host has no IPv6 loopback.
+        let v6 = match (0..2).map(|_| ipv6_loopback().map(|server| server.local_addr().unwrap())).collect::<Option<Vec<_>>>() {
+            Some(v6) => v6,
+            None => return,
+        };
+
+        let mut connector = HttpConnector::new(1);
+        connector.set_max_addrs_attempted(Some(1));
+
+        let addrs = vec![v6[0], v6[1], v4[0], v4[1]];
+        let mut connecting = ConnectingTcp::new(dns::IpAddrs::new(addrs.clone()), connector.config.clone());
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(poll_fn(|| connecting.poll(&None))).unwrap_err();
+        let connect_err = err.get_ref().and_then(|e| e.downcast_ref::<ConnectError>()).expect("ConnectError");
+        assert_eq!(connect_err.attempts().len(), 1);
+        assert_eq!(connect_err.skipped(), 3);
+
+        // Without a maximum, the errors of both families are reported.
+        connector.set_max_addrs_attempted(None);
+        let mut connecting = ConnectingTcp::new(dns::IpAddrs::new(addrs.clone()), connector.config.clone());
+        let err = rt.block_on(poll_fn(|| connecting.poll(&None))).unwrap_err();
+        let connect_err = err.get_ref().and_then(|e| e.downcast_ref::<ConnectError>()).expect("ConnectError");
+        let tried = connect_err.attempts().iter().map(|&(addr, _)| addr).collect::<Vec<_>>();
+        assert_eq!(tried, addrs);
+        assert_eq!(connect_err.skipped(), 0);
+    }
+
+    // Queues DNS work instead of running it.
+    #[derive(Clone)]
+    struct QueueExecutor(Arc<Mutex<Vec<dns::GaiTask>>>);
+
+    impl Executor<dns::GaiTask> for QueueExecutor {
+        fn execute(&self, task: dns::GaiTask) -> Result<(), ExecuteError<dns::GaiTask>> {
+            self.0.lock().unwrap().push(task);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_dropping_connecting_cancels_dns_work() {
+        let queue = QueueExecutor(Arc::new(Mutex::new(Vec::new())));
+        let mut connector = HttpConnector::new_with_executor(queue.clone(), None);
+        connector.set_resolve_localhost(true);
+        let dst = Destination {
+            uri: "http://localhost".parse().unwrap(),
+        };
+
+        let mut connecting = connector.connect(dst);
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(future::lazy(|| -> Result<(), ()> {
+            assert!(connecting.poll().unwrap().is_not_ready());
+            Ok(())
+        })).unwrap();
+        drop(connecting);
+
+        // The queued lookup sees it was canceled, and skips resolving.
+        let before = dns::WORK_POLLS.with(|polls| polls.get());
+        let tasks = queue.0.lock().unwrap().drain(..).collect::<Vec<_>>();
+        assert_eq!(tasks.len(), 1);
+        for task in tasks {
+            task.wait().unwrap();
+        }
+        assert_eq!(dns::WORK_POLLS.with(|polls| polls.get()), before);
+    }
+
+    #[test]
+    fn test_localhost_skips_resolver() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let queue = QueueExecutor(Arc::new(Mutex::new(Vec::new())));
+        let mut connector = HttpConnector::new_with_executor(queue.clone(), None);
+        connector.set_ip_family_preference(IpFamilyPreference::Ipv4Only);
+        let dst = Destination {
+            uri: format!("http://LocalHost.:{}", addr.port()).parse().unwrap(),
+        };
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, connected) = rt.block_on(connector.connect(dst)).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        assert!(queue.0.lock().unwrap().is_empty());
+        assert!(!connected.is_resolved);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_dropping_connecting_closes_socket() {
+        use std::fs::File;
+        use std::io::Read;
+
+        // Whether a socket is connecting to `port` on 100::/64, where
+        // `discard_addr` connects stay in flight.
+        fn in_flight(port: u16) -> bool {
+            let remote = format!(":{:04X}", port);
+            let mut sockets = String::new();
+            File::open("/proc/net/tcp6").unwrap().read_to_string(&mut sockets).unwrap();
+            sockets.lines()
+                .filter_map(|line| line.split_whitespace().nth(2))
+                .any(|addr| addr.starts_with("0000000100000000") && addr.ends_with(&remote))
+        }
+
+        let addr = discard_addr(1, dead_addr().port());
+        let port = addr.port();
+        let connector = HttpConnector::new(1);
+        let dst = Destination {
+            uri: format!("http://{}", addr).parse().unwrap(),
+        };
+
+        let mut connecting = connector.connect(dst);
+        let mut rt = Runtime::new().unwrap();
+        let pending = rt.block_on(future::lazy(|| -> Result<bool, ()> {
+            Ok(connecting.poll().map(|ready| ready.is_not_ready()).unwrap_or(false))
+        })).unwrap();
+        // Skip if the host has no IPv6 route at all.
+        if !pending {
+            return;
+        }
+        assert!(in_flight(port), "connect to {} is not in flight", addr);
+
+        drop(connecting);
+        assert!(!in_flight(port), "socket outlived its HttpConnecting");
+    }
+
+    #[test]
+    fn test_round_robin_rotates_per_host_across_clones() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let dead = dead_addr();
+        let observer = Arc::new(RecordingObserver::default());
+        let mut connector = HttpConnector::new_with_resolver(StaticResolver::with_addrs(vec![dead, addr]), None);
+        connector.set_happy_eyeballs_timeout(None);
+        connector.set_addr_order(AddrOrder::RoundRobin);
+        connector.set_observer(observer.clone());
+
+        let mut rt = Runtime::new().unwrap();
+        for _ in 0..2 {
+            let connector = connector.clone();
+            rt.block_on(connector.connect(example_test(addr))).expect("connect");
+        }
+        // Another host has its own rotation.
+        let other = connector.config.prefer("other.test", dns::IpAddrs::new(vec![dead, addr])).unwrap();
+        assert_eq!(other.collect::<Vec<_>>(), vec![dead, addr]);
+
+        let attempts = observer.0.lock().unwrap()
+            .iter()
+            .filter(|event| event.starts_with("attempt "))
+            .cloned()
+            .collect::<Vec<_>>();
+        // Each address is tried once, starting from the next one.
+        assert_eq!(attempts, vec![
+            format!("attempt {}", dead),
+            format!("attempt {}", addr),
+            format!("attempt {}", addr),
+        ]);
+    }
+
+    #[test]
+    fn test_shuffle_addresses_keeps_family_preference() {
+        let v4 = |n| SocketAddr::from(([10, 0, 0, n], 80));
+        let v6 = |n| SocketAddr::from(([0, 0, 0, 0, 0, 0, 0, n as u16], 80));
+        let addrs = vec![v4(1), v6(1), v4(2), v6(2), v4(3)];
+
+        let shuffled = || {
+            let mut connector = HttpConnector::new(1);
+            connector.set_shuffle_seed(7);
+            connector.set_ip_family_preference(IpFamilyPreference::Ipv6First);
+            connector.config.prefer("example.test", dns::IpAddrs::new(addrs.clone())).unwrap().collect::<Vec<_>>()
+        };
+        // IPv6 first, and the same seed always shuffles the same way.
+        assert_eq!(shuffled(), vec![v6(1), v6(2), v4(2), v4(3), v4(1)]);
+        assert_eq!(shuffled(), shuffled());
+    }
+}
@@ -0,0 +1,176 @@
+//! Converting internationalized domain names to their ASCII form, with the
+//! Punycode of RFC 3492.
+//!
+//! Only the lowercasing and label separators of the UTS #46 mapping are
+//! applied, which covers names typed in any case, but not every character
+//! UTS #46 would map or disallow.
+
+const BASE: u32 = 36;
+const TMIN: u32 = 1;
+const TMAX: u32 = 26;
+const SKEW: u32 = 38;
+const DAMP: u32 = 700;
+const INITIAL_BIAS: u32 = 72;
+const INITIAL_N: u32 = 0x80;
+
+// The most bytes, or characters, a DNS label may have.
+const MAX_LABEL_LEN: usize = 63;
+
+/// Convert each `host` label holding non-ASCII characters to its `xn--`
+/// form, keeping ASCII labels as they are.
+///
+/// The error is the label that can't be converted.
+pub(super) fn to_ascii(host: &str) -> Result<String, String> {
+    let mut ascii = String::with_capacity(host.len());
+    for (i, label) in host.split(is_dot).enumerate() {
+        if i > 0 {
+            ascii.push('.');
+        }
+        if is_ascii(label) {
+            ascii.push_str(label);
+            continue;
+        }
+        let chars = label
+            .chars()
+            .flat_map(char::to_lowercase)
+            .map(|c| c as u32)
+            .collect::<Vec<_>>();
+        // ASCII in an internationalized label must still be a letter, a
+        // digit or a hyphen, and the letters are lowercase by now.
+        let invalid = chars.len() > MAX_LABEL_LEN || chars.iter().any(|&c| c < INITIAL_N && !is_ldh(c));
+        if invalid {
+            return Err(label.to_owned());
+        }
+        let encoded = punycode(&chars);
+        if encoded.len() + 4 > MAX_LABEL_LEN {
+            return Err(label.to_owned());
+        }
+        ascii.push_str("xn--");
+        ascii.push_str(&encoded);
+    }
+    Ok(ascii)
+}
+
+/// Whether `s` is ASCII, and so needs no converting.
+pub(super) fn is_ascii(s: &str) -> bool {
+    s.bytes().all(|b| b < 0x80)
+}
+
+// A letter, a digit or a hyphen, all a hostname label may hold.
+fn is_ldh(c: u32) -> bool {
+    (c >= 'a' as u32 && c <= 'z' as u32) || (c >= '0' as u32 && c <= '9' as u32) || c == '-' as u32
+}
+
+// Full stops UTS #46 maps to `.`, as well as `.` itself.
+fn is_dot(c: char) -> bool {
+    c == '.' || c == '\u{3002}' || c == '\u{ff0e}' || c == '\u{ff61}'
+}
+
+// The Punycode of a label, from section 6.3 of RFC 3492. With at most 63
+// characters in a label, none of the sums can overflow.
+fn punycode(input: &[u32]) -> String {
+    let mut output = input
+        .iter()
+        .filter(|&&c| c < INITIAL_N)
+        .map(|&c| c as u8 as char)
+        .collect::<String>();
+    let basic = output.len() as u32;
+    if basic > 0 {
+        output.push('-');
+    }
+
+    let mut n = INITIAL_N;
+    let mut delta = 0;
+    let mut bias = INITIAL_BIAS;
+    let mut handled = basic;
+    while (handled as usize) < input.len() {
+        let m = input
+            .iter()
+            .cloned()
+            .filter(|&c| c >= n)
+            .min()
+            .expect("an unhandled character");
+        delta += (m - n) * (handled + 1);
+        n = m;
+        for &c in input {
+            if c < n {
+                delta += 1;
+            }
+            if c == n {
+                let mut q = delta;
+                let mut k = BASE;
+                loop {
+                    let t = if k <= bias {
+                        TMIN
+                    } else if k >= bias + TMAX {
+                        TMAX
+                    } else {
+                        k - bias
+                    };
+                    if q < t {
+                        break;
+                    }
+                    output.push(digit(t + (q - t) % (BASE - t)));
+                    q = (q - t) / (BASE - t);
+                    k += BASE;
+                }
+                output.push(digit(q));
+                bias = adapt(delta, handled + 1, handled == basic);
+                delta = 0;
+                handled += 1;
+            }
+        }
+        delta += 1;
+        n += 1;
+    }
+    output
+}
+
+fn adapt(delta: u32, points: u32, first: bool) -> u32 {
+    let mut delta = if first { delta / DAMP } else { delta / 2 };
+    delta += delta / points;
+    let mut k = 0;
+    while delta > ((BASE - TMIN) * TMAX) / 2 {
+        delta /= BASE - TMIN;
+        k += BASE;
+    }
+    k + (BASE - TMIN + 1) * delta / (delta + SKEW)
+}
+
+fn digit(d: u32) -> char {
+    if d < 26 {
+        (b'a' + d as u8) as char
+    } else {
+        (b'0' + (d - 26) as u8) as char
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::to_ascii;
+
+    #[test]
+    fn test_to_ascii() {
+        assert_eq!(to_ascii("b\u{fc}cher.example").unwrap(), "xn--bcher-kva.example");
+        assert_eq!(to_ascii("M\u{dc}NCHEN.Example").unwrap(), "xn--mnchen-3ya.Example");
+        assert_eq!(to_ascii("example.test").unwrap(), "example.test");
+        // Samples from section 7.1 of RFC 3492.
+        assert_eq!(
+            to_ascii("\u{4ed6}\u{4eec}\u{4e3a}\u{4ec0}\u{4e48}\u{4e0d}\u{8bf4}\u{4e2d}\u{6587}").unwrap(),
+            "xn--ihqwcrb4cv8a8dqg056pqjye",
+        );
+        assert_eq!(
+            to_ascii("3\u{5e74}B\u{7d44}\u{91d1}\u{516b}\u{5148}\u{751f}").unwrap(),
+            "xn--3b-ww4c5e180e575a65lsy2b",
+        );
+        // An ideographic full stop separates labels too.
+        assert_eq!(to_ascii("b\u{fc}cher\u{3002}example").unwrap(), "xn--bcher-kva.example");
+    }
+
+    #[test]
+    fn test_to_ascii_errors_name_the_label() {
+        assert_eq!(to_ascii("b\u{fc}_cher.example").unwrap_err(), "b\u{fc}_cher");
+        let long = format!("{}.example", "\u{fc}".repeat(64));
+        assert_eq!(to_ascii(&long).unwrap_err(), "\u{fc}".repeat(64));
+    }
+}
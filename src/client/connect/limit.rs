@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+use futures::Async;
+use futures::task::{self, Task};
+
+// Limits how many connects are in progress at once, handing out permits
+// in the order they were asked for.
+#[derive(Clone)]
+pub(super) struct ConnectLimit {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    max: usize,
+    in_use: usize,
+    next_id: usize,
+    waiters: VecDeque<(usize, Task)>,
+}
+
+// Waits for a permit, and holds it until dropped.
+pub(super) struct Acquire {
+    limit: ConnectLimit,
+    state: AcquireState,
+}
+
+enum AcquireState {
+    Idle,
+    Waiting(usize),
+    Acquired,
+}
+
+impl ConnectLimit {
+    pub(super) fn new(max: usize) -> ConnectLimit {
+        ConnectLimit {
+            inner: Arc::new(Mutex::new(Inner {
+                max: max,
+                in_use: 0,
+                next_id: 0,
+                waiters: VecDeque::new(),
+            })),
+        }
+    }
+
+    pub(super) fn acquire(&self) -> Acquire {
+        Acquire {
+            limit: self.clone(),
+            state: AcquireState::Idle,
+        }
+    }
+
+    pub(super) fn in_use(&self) -> usize {
+        self.inner.lock().unwrap().in_use
+    }
+
+    pub(super) fn waiting(&self) -> usize {
+        self.inner.lock().unwrap().waiters.len()
+    }
+}
+
+impl Inner {
+    // Wake the first waiter, if a permit is free for it.
+    fn notify_front(&self) {
+        if self.in_use < self.max {
+            if let Some(&(_, ref task)) = self.waiters.front() {
+                task.notify();
+            }
+        }
+    }
+}
+
+impl Acquire {
+    pub(super) fn poll(&mut self) -> Async<()> {
+        let mut inner = self.limit.inner.lock().unwrap();
+        match self.state {
+            AcquireState::Acquired => return Async::Ready(()),
+            AcquireState::Idle => {
+                if inner.waiters.is_empty() && inner.in_use < inner.max {
+                    inner.in_use += 1;
+                    self.state = AcquireState::Acquired;
+                    return Async::Ready(());
+                }
+                let id = inner.next_id;
+                inner.next_id = inner.next_id.wrapping_add(1);
+                inner.waiters.push_back((id, task::current()));
+                self.state = AcquireState::Waiting(id);
+            },
+            AcquireState::Waiting(id) => {
+                let first = inner.waiters.front().map(|&(first, _)| first) == Some(id);
+                if first && inner.in_use < inner.max {
+                    inner.waiters.pop_front();
+                    inner.in_use += 1;
+                    self.state = AcquireState::Acquired;
+                    // More permits may be free for those behind.
+                    inner.notify_front();
+                    return Async::Ready(());
+                }
+                if let Some(waiter) = inner.waiters.iter_mut().find(|&&mut (waiter, _)| waiter == id) {
+                    waiter.1 = task::current();
+                }
+            },
+        }
+        Async::NotReady
+    }
+}
+
+impl Drop for Acquire {
+    fn drop(&mut self) {
+        let mut inner = self.limit.inner.lock().unwrap();
+        match self.state {
+            AcquireState::Idle => return,
+            AcquireState::Waiting(id) => inner.waiters.retain(|&(waiter, _)| waiter != id),
+            AcquireState::Acquired => inner.in_use -= 1,
+        }
+        inner.notify_front();
+    }
+}
+
+impl fmt::Debug for ConnectLimit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct("ConnectLimit")
+            .field("max", &inner.max)
+            .field("in_use", &inner.in_use)
+            .field("waiting", &inner.waiters.len())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::{Async, Future};
+    use futures::future::poll_fn;
+
+    use super::ConnectLimit;
+
+    #[test]
+    fn test_connect_limit_fifo() {
+        let limit = ConnectLimit::new(1);
+        poll_fn(|| {
+            let mut first = limit.acquire();
+            let mut second = limit.acquire();
+            let mut third = limit.acquire();
+            assert_eq!(first.poll(), Async::Ready(()));
+            assert_eq!(second.poll(), Async::NotReady);
+            assert_eq!(third.poll(), Async::NotReady);
+            assert_eq!((limit.in_use(), limit.waiting()), (1, 2));
+
+            // The third can't skip ahead of the second.
+            drop(first);
+            assert_eq!(third.poll(), Async::NotReady);
+            assert_eq!(second.poll(), Async::Ready(()));
+
+            // A waiter that gives up passes its turn on.
+            let mut fourth = limit.acquire();
+            assert_eq!(fourth.poll(), Async::NotReady);
+            drop(third);
+            drop(second);
+            assert_eq!(fourth.poll(), Async::Ready(()));
+            assert_eq!((limit.in_use(), limit.waiting()), (1, 0));
+            drop(fourth);
+            assert_eq!(limit.in_use(), 0);
+            Ok::<_, ()>(Async::Ready(()))
+        }).wait().unwrap();
+    }
+}
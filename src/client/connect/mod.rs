@@ -0,0 +1,544 @@
+//! The `Connect` trait, and supporting types.
+//!
+//! This module contains:
+//!
+//! - A default [`HttpConnector`](HttpConnector) that does DNS resolution and
+//!   establishes connections over TCP.
+//! - A [`FailConnector`](FailConnector) whose every connect fails, to test
+//!   how errors are handled.
+//! - A [`FallbackConnector`](FallbackConnector) to try one connector, and
+//!   then another if it failed.
+//! - A [`ConnectObserver`](ConnectObserver) trait to watch the progress of
+//!   an `HttpConnector`, with [`LogObserver`](LogObserver) and
+//!   [`CountingObserver`](CountingObserver) implementations.
+//! - A [`PinnedConnector`](PinnedConnector) to keep connecting to the
+//!   address a host was first connected to.
+//! - A [`ProxyConnector`](ProxyConnector) to connect through an HTTP proxy.
+//! - A [`RetryConnector`](RetryConnector) to retry any connector with
+//!   backoff.
+//! - A [`RouterConnector`](RouterConnector) to pick a connector by the
+//!   destination host.
+//! - A [`Socks5Connector`](Socks5Connector) to connect through a SOCKS5 proxy.
+//! - A [`TimeoutConnector`](TimeoutConnector) to bound how long any
+//!   connector may take.
+//! - A [`UnixConnector`](UnixConnector) for Unix domain sockets, on Unix
+//!   platforms.
+//! - The [`Connect`](Connect) trait and related types to build custom
+//!   connectors, and [`connector_fn`](connector_fn) to build one from a
+//!   function.
+//! - The [`dns`](dns) module, to customize how the `HttpConnector` resolves
+//!   hostnames.
+use std::error::Error as StdError;
+use std::fmt;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+#[cfg(feature = "runtime")] use std::io;
+
+use futures::{Future, IntoFuture};
+use futures::future::Map;
+use http::Uri;
+use tokio_io::{AsyncRead, AsyncWrite};
+
+#[cfg(feature = "runtime")] mod addrselect;
+#[cfg(feature = "runtime")] pub mod dns;
+#[cfg(feature = "runtime")] mod fail;
+#[cfg(feature = "runtime")] mod fallback;
+#[cfg(feature = "runtime")] mod http;
+#[cfg(feature = "runtime")] mod idna;
+#[cfg(feature = "runtime")] mod limit;
+#[cfg(feature = "runtime")] mod observer;
+#[cfg(feature = "runtime")] mod pinned;
+#[cfg(feature = "runtime")] mod proxy;
+#[cfg(feature = "runtime")] mod retry;
+#[cfg(feature = "runtime")] mod router;
+#[cfg(feature = "runtime")] mod socks;
+#[cfg(feature = "runtime")] mod sockopt;
+#[cfg(feature = "runtime")] mod timeout;
+#[cfg(all(unix, feature = "runtime"))] mod unix;
+#[cfg(feature = "runtime")] pub use self::fail::{FailConnecting, FailConnector};
+#[cfg(feature = "runtime")] pub use self::fallback::{FallbackConnecting, FallbackConnector, FallbackError, FallbackStream};
+#[cfg(feature = "runtime")] pub use self::http::{ConnectError, HttpConnector, HttpConnectorBuilder, SchemePolicy};
+#[cfg(feature = "runtime")] pub use self::observer::{ConnectEvent, ConnectObserver, CountingObserver, LogObserver};
+#[cfg(feature = "runtime")] pub use self::pinned::{PinnedConnecting, PinnedConnector};
+#[cfg(feature = "runtime")] pub use self::proxy::{ProxyConnecting, ProxyConnector, TunnelError};
+#[cfg(feature = "runtime")] pub use self::retry::{RetryConnecting, RetryConnector};
+#[cfg(feature = "runtime")] pub use self::router::{RouterConnecting, RouterConnector, RouterConnectorBuilder, RouterStream};
+#[cfg(feature = "runtime")] pub use self::socks::{Socks5Connecting, Socks5Connector};
+#[cfg(feature = "runtime")] pub use self::timeout::{TimeoutConnecting, TimeoutConnector};
+#[cfg(all(unix, feature = "runtime"))] pub use self::unix::{UnixConnecting, UnixConnector};
+
+/// Connect to a destination, returning an IO transport.
+///
+/// A connector receives a [`Destination`](Destination) describing how a
+/// connection should be estabilished, and returns a `Future` of the
+/// ready connection.
+pub trait Connect: Send + Sync {
+    /// The connected IO Stream.
+    type Transport: AsyncRead + AsyncWrite + Send + 'static;
+    /// An error occured when trying to connect.
+    type Error: Into<Box<StdError + Send + Sync>>;
+    /// A Future that will resolve to the connected Transport.
+    type Future: Future<Item=(Self::Transport, Connected), Error=Self::Error> + Send;
+    /// Connect to a destination.
+    fn connect(&self, dst: Destination) -> Self::Future;
+}
+
+/// A connector shared behind an `Arc` connects as the connector itself,
+/// so one connector can be used by many clients without cloning it.
+///
+/// # Example
+///
+/// ```
+/// # extern crate hyper;
+/// # fn main() {
+/// use std::sync::Arc;
+///
+/// use hyper::{Body, Client};
+/// use hyper::client::HttpConnector;
+///
+/// let connector = Arc::new(HttpConnector::new(1));
+/// let first: Client<_, Body> = Client::builder().build(connector.clone());
+/// let second: Client<_, Body> = Client::builder().build(connector);
+/// # drop((first, second));
+/// # }
+/// ```
+impl<C: Connect + ?Sized> Connect for Arc<C> {
+    type Transport = C::Transport;
+    type Error = C::Error;
+    type Future = C::Future;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        (**self).connect(dst)
+    }
+}
+
+/// Create a `Connect` from a function.
+///
+/// The function is called with each destination, and returns a future of
+/// the connected transport, which is reported with an empty
+/// [`Connected`](Connected).
+///
+/// # Example
+///
+/// A connector that connects every destination to a local server, such as
+/// for tests:
+///
+/// ```
+/// # extern crate hyper;
+/// # #[cfg(feature = "runtime")]
+/// # extern crate tokio;
+/// # #[cfg(feature = "runtime")]
+/// # fn main() {
+/// use hyper::{Body, Client};
+/// use hyper::client::connect::{connector_fn, Destination};
+/// use tokio::net::TcpStream;
+///
+/// let connector = connector_fn(|_dst: Destination| {
+///     TcpStream::connect(&([127, 0, 0, 1], 3000).into())
+/// });
+/// let client: Client<_, Body> = Client::builder().build(connector);
+/// # drop(client);
+/// # }
+/// # #[cfg(not(feature = "runtime"))]
+/// # fn main() {}
+/// ```
+pub fn connector_fn<F, R>(f: F) -> ConnectorFn<F>
+where
+    F: Fn(Destination) -> R,
+    R: IntoFuture,
+{
+    ConnectorFn {
+        f: f,
+    }
+}
+
+/// A `Connect` created from a function, with
+/// [`connector_fn`](connector_fn).
+#[derive(Clone)]
+pub struct ConnectorFn<F> {
+    f: F,
+}
+
+impl<F, R> Connect for ConnectorFn<F>
+where
+    F: Fn(Destination) -> R + Send + Sync,
+    R: IntoFuture,
+    R::Item: AsyncRead + AsyncWrite + Send + 'static,
+    R::Error: Into<Box<StdError + Send + Sync>>,
+    R::Future: Send,
+{
+    type Transport = R::Item;
+    type Error = R::Error;
+    type Future = Map<R::Future, fn(R::Item) -> (R::Item, Connected)>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        fn connected<T>(io: T) -> (T, Connected) {
+            (io, Connected::new())
+        }
+        (self.f)(dst).into_future().map(connected as fn(R::Item) -> (R::Item, Connected))
+    }
+}
+
+impl<F> fmt::Debug for ConnectorFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("impl Connect")
+            .finish()
+    }
+}
+
+/// A set of properties to describe where and how to try to connect.
+#[derive(Debug)]
+pub struct Destination {
+    //pub(super) alpn: Alpn,
+    pub(super) uri: Uri,
+}
+
+/// Extra information about the connected transport.
+///
+/// This can be used to inform recipients about things like if ALPN
+/// was used, or if connected to an HTTP proxy.
+#[derive(Debug)]
+pub struct Connected {
+    //alpn: Alpn,
+    pub(super) is_proxied: bool,
+    pub(super) remote_addr: Option<SocketAddr>,
+    pub(super) is_resolved: bool,
+    pub(super) dns_time: Option<Duration>,
+    pub(super) connect_time: Option<Duration>,
+    pub(super) attempts: usize,
+}
+
+/// How long the steps of a connect took.
+///
+/// Get it from [`Connected::timing`](Connected::timing) when connecting
+/// succeeded. When every address failed, it's part of the `Display` of
+/// the `ConnectError`, which also has it as
+/// [`ConnectError::timing`](ConnectError::timing).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ConnectTiming {
+    pub(super) dns: Option<Duration>,
+    pub(super) tcp: Duration,
+    pub(super) attempts: usize,
+}
+
+/// Information about a connection a response was received on.
+///
+/// When a connector reports the remote address of a connection, the
+/// `Client` adds this to the extensions of each `Response` received on it.
+#[derive(Clone, Copy, Debug)]
+pub struct HttpInfo {
+    remote_addr: SocketAddr,
+    is_resolved: bool,
+}
+
+/*TODO: when HTTP1 Upgrades to H2 are added, this will be needed
+#[derive(Debug)]
+pub(super) enum Alpn {
+    Http1,
+    //H2,
+    //Http1OrH2
+}
+*/
+
+impl Destination {
+    /// Get the protocol scheme.
+    #[inline]
+    pub fn scheme(&self) -> &str {
+        self.uri
+            .scheme_part()
+            .map(|s| s.as_str())
+            .unwrap_or("")
+    }
+
+    /// Get the hostname.
+    #[inline]
+    pub fn host(&self) -> &str {
+        self.uri
+            .host()
+            .unwrap_or("")
+    }
+
+    /// Get the port, if specified.
+    #[inline]
+    pub fn port(&self) -> Option<u16> {
+        self.uri.port()
+    }
+
+    /*
+    /// Returns whether this connection must negotiate HTTP/2 via ALPN.
+    pub fn must_h2(&self) -> bool {
+        match self.alpn {
+            Alpn::Http1 => false,
+            Alpn::H2 => true,
+        }
+    }
+    */
+}
+
+impl Connected {
+    /// Create new `Connected` type with empty metadata.
+    pub fn new() -> Connected {
+        Connected {
+            //alpn: Alpn::Http1,
+            is_proxied: false,
+            remote_addr: None,
+            is_resolved: false,
+            dns_time: None,
+            connect_time: None,
+            attempts: 0,
+        }
+    }
+
+    /// Set whether the connected transport is to an HTTP proxy.
+    ///
+    /// This setting will affect if HTTP/1 requests written on the transport
+    /// will have the request-target in absolute-form or origin-form (such as
+    /// `GET http://hyper.rs/guide HTTP/1.1` or `GET /guide HTTP/1.1`).
+    ///
+    /// Default is `false`.
+    pub fn proxy(mut self, is_proxied: bool) -> Connected {
+        self.is_proxied = is_proxied;
+        self
+    }
+
+    /// Set the remote address of the connected transport.
+    ///
+    /// Default is `None`.
+    pub fn remote_addr(mut self, addr: SocketAddr) -> Connected {
+        self.remote_addr = Some(addr);
+        self
+    }
+
+    /// Set whether the remote address was found by resolving a hostname,
+    /// rather than given as an IP address.
+    ///
+    /// The `HttpConnector` only counts addresses from its resolver or DNS
+    /// cache, not those from host overrides or its `localhost` shortcut.
+    ///
+    /// Default is `false`.
+    pub fn resolved(mut self, is_resolved: bool) -> Connected {
+        self.is_resolved = is_resolved;
+        self
+    }
+
+    /// Get whether the connection is to an IPv6 address.
+    ///
+    /// With several addresses tried, such as with Happy Eyeballs, this is
+    /// the family of the one that connected. It's `false` if no remote
+    /// address was set.
+    pub fn is_ipv6(&self) -> bool {
+        self.remote_addr.map_or(false, |addr| addr.is_ipv6())
+    }
+
+    /// Get how long resolving the hostname took.
+    ///
+    /// This is `None` if the connector didn't report it, or if nothing was
+    /// resolved, such as for an IP address or an overridden host.
+    pub fn dns_time(&self) -> Option<Duration> {
+        self.dns_time
+    }
+
+    /// Get how long connecting took once the addresses were known,
+    /// including any addresses that failed first.
+    ///
+    /// This is `None` if the connector didn't report it.
+    pub fn connect_time(&self) -> Option<Duration> {
+        self.connect_time
+    }
+
+    /// Get how long resolving and connecting took, and how many addresses
+    /// were tried.
+    ///
+    /// This is `None` if the connector didn't report how long connecting
+    /// took.
+    pub fn timing(&self) -> Option<ConnectTiming> {
+        self.connect_time.map(|tcp| ConnectTiming {
+            dns: self.dns_time,
+            tcp: tcp,
+            attempts: self.attempts,
+        })
+    }
+
+    pub(super) fn http_info(&self) -> Option<HttpInfo> {
+        self.remote_addr.map(|remote_addr| HttpInfo {
+            remote_addr,
+            is_resolved: self.is_resolved,
+        })
+    }
+
+    /*
+    /// Set that the connected transport negotiated HTTP/2 as it's
+    /// next protocol.
+    pub fn h2(mut self) -> Connected {
+        self.alpn = Alpn::H2;
+        self
+    }
+    */
+}
+
+impl ConnectTiming {
+    /// Get how long resolving the hostname took.
+    ///
+    /// This is `None` if nothing was resolved, such as for an IP address.
+    pub fn dns(&self) -> Option<Duration> {
+        self.dns
+    }
+
+    /// Get how long connecting took once the addresses were known.
+    pub fn tcp(&self) -> Duration {
+        self.tcp
+    }
+
+    /// Get how many addresses were tried.
+    pub fn attempts(&self) -> usize {
+        self.attempts
+    }
+}
+
+impl fmt::Display for ConnectTiming {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(dns) = self.dns {
+            write!(f, "dns {:?}, ", dns)?;
+        }
+        match self.attempts {
+            1 => write!(f, "tcp {:?}, 1 attempt", self.tcp),
+            n => write!(f, "tcp {:?}, {} attempts", self.tcp, n),
+        }
+    }
+}
+
+impl HttpInfo {
+    /// Get the remote address of the connection.
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+
+    /// Get whether the remote address was found by resolving a hostname.
+    ///
+    /// Addresses from host overrides, or the `localhost` shortcut of the
+    /// `HttpConnector`, weren't resolved.
+    pub fn is_resolved(&self) -> bool {
+        self.is_resolved
+    }
+
+    /// Get whether the remote address is an IPv6 address.
+    pub fn is_ipv6(&self) -> bool {
+        self.remote_addr.is_ipv6()
+    }
+}
+
+// Connectors wrapping another `Connect` keep its IO errors as they are.
+#[cfg(feature = "runtime")]
+fn into_io_error<E: Into<Box<StdError + Send + Sync>>>(err: E) -> io::Error {
+    match err.into().downcast::<io::Error>() {
+        Ok(err) => *err,
+        Err(err) => io::Error::new(io::ErrorKind::Other, err),
+    }
+}
+
+// `s` with its ASCII letters lowercased.
+#[cfg(feature = "runtime")]
+fn ascii_lowercase(s: &str) -> String {
+    // As of Rust 1.23, str gained this method inherently, and so the
+    // compiler says this trait is unused.
+    #[allow(unused, deprecated)]
+    use std::ascii::AsciiExt;
+
+    s.to_ascii_lowercase()
+}
+
+// A hostname as connectors compare it: lowercased, and without the
+// trailing dot of a fully qualified name.
+#[cfg(feature = "runtime")]
+fn normalize_host(host: &str) -> String {
+    let host = if host.ends_with('.') {
+        &host[..host.len() - 1]
+    } else {
+        host
+    };
+    ascii_lowercase(host)
+}
+
+// A host as given by `Uri::host()`, without the brackets around an IPv6
+// address.
+#[cfg(feature = "runtime")]
+fn unbracket(host: &str) -> &str {
+    if host.starts_with('[') && host.ends_with(']') {
+        &host[1..host.len() - 1]
+    } else {
+        host
+    }
+}
+
+#[cfg(all(test, feature = "runtime"))]
+mod tests {
+    use std::io;
+    use std::net::{Ipv6Addr, SocketAddr, TcpListener};
+    use std::sync::Arc;
+
+    use futures::future;
+    use tokio::net::TcpStream;
+    use tokio::runtime::current_thread::Runtime;
+
+    use {Body, Client, Request};
+    use super::{connector_fn, Connect, Destination, FailConnector, HttpConnector};
+
+    fn dst(s: &str) -> Destination {
+        Destination {
+            uri: s.parse().unwrap(),
+        }
+    }
+
+    // A listener on the IPv6 loopback, or `None` if the host has none, so
+    // the test can be skipped.
+    pub(super) fn ipv6_loopback() -> Option<TcpListener> {
+        TcpListener::bind("[::1]:0").ok()
+    }
+
+    // The `n`th address of 100::/64, which is a discard-only prefix, so
+    // the SYN of a connect to it goes unanswered until it times out, or
+    // fails right away on hosts without IPv6.
+    pub(super) fn discard_addr(n: u16, port: u16) -> SocketAddr {
+        SocketAddr::new(Ipv6Addr::new(0x100, 0, 0, 0, 0, 0, 0, n).into(), port)
+    }
+
+    #[test]
+    fn test_arc_connector() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let connector = Arc::new(HttpConnector::new(1));
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, _) = rt.block_on(connector.connect(dst(&format!("http://{}", addr)))).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+
+        let connector = Arc::new(FailConnector::new(io::ErrorKind::ConnectionRefused, "refused"));
+        let client = Client::builder().build::<_, Body>(connector.clone());
+        let req = Request::get("http://arc.test/").body(Body::empty()).unwrap();
+        let err = rt.block_on(client.request(req)).unwrap_err();
+        assert_eq!(err.to_string(), "an error occurred trying to connect: refused");
+        assert_eq!(Arc::strong_count(&connector), 2);
+    }
+
+    #[test]
+    fn test_connector_fn() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let connector = connector_fn(move |dst| {
+            assert_eq!(dst.host(), "fn.test");
+            TcpStream::connect(&addr)
+        });
+
+        let mut rt = Runtime::new().unwrap();
+        let (sock, connected) = rt.block_on(connector.connect(dst("http://fn.test"))).expect("connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        assert!(connected.remote_addr.is_none());
+
+        let connector = connector_fn(|_| future::err::<TcpStream, _>(io::Error::new(io::ErrorKind::Other, "nope")));
+        let client = Client::builder().build::<_, Body>(connector);
+        let req = Request::get("http://fn.test/").body(Body::empty()).unwrap();
+        let err = rt.block_on(client.request(req)).unwrap_err();
+        assert_eq!(err.to_string(), "an error occurred trying to connect: nope");
+    }
+}
@@ -0,0 +1,301 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Callbacks about the progress of connects made by an `HttpConnector`.
+///
+/// Set one with
+/// [`HttpConnector::set_observer`](HttpConnector::set_observer). The
+/// callbacks are called from within polling the connect future, so they
+/// must be quick and never block. Every method does nothing by default.
+pub trait ConnectObserver: Send + Sync {
+    /// Called when the resolver is asked to resolve `host`.
+    ///
+    /// This isn't called for IP addresses, overridden hosts, or hostnames
+    /// found in a cache.
+    fn on_resolve_start(&self, _host: &str) {}
+
+    /// Called when resolving `host` finished, after `elapsed`.
+    fn on_resolve_end(&self, _host: &str, _result: Result<&[SocketAddr], &io::Error>, _elapsed: Duration) {}
+
+    /// Called when connecting to `addr` starts.
+    fn on_connect_attempt(&self, _addr: &SocketAddr) {}
+
+    /// Called when connecting to `addr` failed.
+    fn on_connect_failed(&self, _addr: &SocketAddr, _err: &io::Error) {}
+
+    /// Called when connecting to `addr` succeeded, `elapsed` after it
+    /// started.
+    fn on_connected(&self, _addr: &SocketAddr, _elapsed: Duration) {}
+}
+
+/// An event passed to a callback set with
+/// [`HttpConnector::set_connect_callback`](HttpConnector::set_connect_callback).
+#[derive(Debug)]
+pub enum ConnectEvent<'a> {
+    /// The resolver was asked to resolve `host`.
+    ResolveStarted {
+        /// The hostname being resolved.
+        host: &'a str,
+    },
+    /// Resolving finished with `count` addresses.
+    ResolveCompleted {
+        /// How many addresses were resolved.
+        count: usize,
+    },
+    /// Resolving failed.
+    ResolveFailed {
+        /// The resolver's error.
+        error: &'a io::Error,
+    },
+    /// Connecting to `addr` started.
+    AttemptStarted {
+        /// The address being connected to.
+        addr: SocketAddr,
+    },
+    /// Connecting to `addr` failed.
+    AttemptFailed {
+        /// The address that failed.
+        addr: SocketAddr,
+        /// Why it failed.
+        error: &'a io::Error,
+    },
+    /// Connecting to `addr` succeeded, `elapsed` after it started.
+    Connected {
+        /// The address connected to.
+        addr: SocketAddr,
+        /// How long the attempt took.
+        elapsed: Duration,
+    },
+}
+
+// Adapts a callback to a `ConnectObserver`.
+pub(super) struct CallbackObserver<F>(pub(super) F);
+
+impl<F> ConnectObserver for CallbackObserver<F>
+where
+    F: Fn(ConnectEvent) + Send + Sync,
+{
+    fn on_resolve_start(&self, host: &str) {
+        (self.0)(ConnectEvent::ResolveStarted { host: host });
+    }
+
+    fn on_resolve_end(&self, _host: &str, result: Result<&[SocketAddr], &io::Error>, _elapsed: Duration) {
+        match result {
+            Ok(addrs) => (self.0)(ConnectEvent::ResolveCompleted { count: addrs.len() }),
+            Err(err) => (self.0)(ConnectEvent::ResolveFailed { error: err }),
+        }
+    }
+
+    fn on_connect_attempt(&self, addr: &SocketAddr) {
+        (self.0)(ConnectEvent::AttemptStarted { addr: *addr });
+    }
+
+    fn on_connect_failed(&self, addr: &SocketAddr, err: &io::Error) {
+        (self.0)(ConnectEvent::AttemptFailed { addr: *addr, error: err });
+    }
+
+    fn on_connected(&self, addr: &SocketAddr, elapsed: Duration) {
+        (self.0)(ConnectEvent::Connected { addr: *addr, elapsed: elapsed });
+    }
+}
+
+/// A `ConnectObserver` that logs each callback at the debug level.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct LogObserver {
+    _priv: (),
+}
+
+impl LogObserver {
+    /// Construct a new `LogObserver`.
+    pub fn new() -> LogObserver {
+        LogObserver {
+            _priv: (),
+        }
+    }
+}
+
+impl ConnectObserver for LogObserver {
+    fn on_resolve_start(&self, host: &str) {
+        debug!("resolving {}", host);
+    }
+
+    fn on_resolve_end(&self, host: &str, result: Result<&[SocketAddr], &io::Error>, elapsed: Duration) {
+        match result {
+            Ok(addrs) => debug!("resolved {} to {:?} in {:?}", host, addrs, elapsed),
+            Err(err) => debug!("failed to resolve {} in {:?}: {}", host, elapsed, err),
+        }
+    }
+
+    fn on_connect_attempt(&self, addr: &SocketAddr) {
+        debug!("connecting to {}", addr);
+    }
+
+    fn on_connect_failed(&self, addr: &SocketAddr, err: &io::Error) {
+        debug!("failed to connect to {}: {}", addr, err);
+    }
+
+    fn on_connected(&self, addr: &SocketAddr, elapsed: Duration) {
+        debug!("connected to {} in {:?}", addr, elapsed);
+    }
+}
+
+/// A `ConnectObserver` that counts each callback.
+#[derive(Debug, Default)]
+pub struct CountingObserver {
+    attempts: AtomicUsize,
+    connected: AtomicUsize,
+    failures: AtomicUsize,
+    resolve_errors: AtomicUsize,
+    resolves: AtomicUsize,
+}
+
+impl CountingObserver {
+    /// Construct a new `CountingObserver`, with every count at zero.
+    pub fn new() -> CountingObserver {
+        CountingObserver::default()
+    }
+
+    /// Get how many times a hostname was resolved, successfully or not.
+    pub fn resolves(&self) -> usize {
+        self.resolves.load(Ordering::Relaxed)
+    }
+
+    /// Get how many times resolving a hostname failed.
+    pub fn resolve_errors(&self) -> usize {
+        self.resolve_errors.load(Ordering::Relaxed)
+    }
+
+    /// Get how many addresses were connected to, successfully or not.
+    pub fn attempts(&self) -> usize {
+        self.attempts.load(Ordering::Relaxed)
+    }
+
+    /// Get how many addresses failed to connect.
+    pub fn failures(&self) -> usize {
+        self.failures.load(Ordering::Relaxed)
+    }
+
+    /// Get how many connections were established.
+    pub fn connected(&self) -> usize {
+        self.connected.load(Ordering::Relaxed)
+    }
+}
+
+impl ConnectObserver for CountingObserver {
+    fn on_resolve_start(&self, _host: &str) {
+        self.resolves.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_resolve_end(&self, _host: &str, result: Result<&[SocketAddr], &io::Error>, _elapsed: Duration) {
+        if result.is_err() {
+            self.resolve_errors.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn on_connect_attempt(&self, _addr: &SocketAddr) {
+        self.attempts.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_connect_failed(&self, _addr: &SocketAddr, _err: &io::Error) {
+        self.failures.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn on_connected(&self, _addr: &SocketAddr, _elapsed: Duration) {
+        self.connected.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::{Arc, Mutex};
+
+    use futures::future::{self, FutureResult};
+    use tokio::runtime::current_thread::Runtime;
+
+    use super::{ConnectEvent, CountingObserver};
+    use super::super::{Connect, Destination, HttpConnector};
+    use super::super::dns::{IpAddrs, Name, Resolve};
+
+    #[test]
+    fn test_counting_observer() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let observer = Arc::new(CountingObserver::new());
+        let mut connector = HttpConnector::new(1);
+        connector.set_observer(observer.clone());
+        connector.set_resolve_localhost(true);
+
+        let mut rt = Runtime::new().unwrap();
+        for host in &["127.0.0.1", "localhost"] {
+            let dst = Destination {
+                uri: format!("http://{}:{}", host, addr.port()).parse().unwrap(),
+            };
+            rt.block_on(connector.connect(dst)).expect("connect");
+        }
+
+        assert_eq!(observer.resolves(), 1);
+        assert_eq!(observer.resolve_errors(), 0);
+        assert_eq!(observer.connected(), 2);
+        // `localhost` may also resolve to `::1`, which isn't listening.
+        assert_eq!(observer.attempts(), 2 + observer.failures());
+    }
+
+    // Resolves every host to `addr`.
+    struct StaticResolver(SocketAddr);
+
+    impl Resolve for StaticResolver {
+        type Future = FutureResult<IpAddrs, io::Error>;
+
+        fn resolve(&self, _name: Name) -> Self::Future {
+            future::ok(IpAddrs::new(vec![self.0]))
+        }
+    }
+
+    // Connect to `uri`, returning the events the callback saw.
+    fn events<R: Resolve + Send + Sync + 'static>(mut connector: HttpConnector<R>, uri: String) -> Vec<String>
+    where
+        R::Future: Send,
+    {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let recorded = events.clone();
+        connector.set_connect_callback(Arc::new(move |event: ConnectEvent| {
+            let event = match event {
+                ConnectEvent::Connected { addr, .. } => format!("connected {}", addr),
+                other => format!("{:?}", other),
+            };
+            recorded.lock().unwrap().push(event);
+        }));
+
+        let dst = Destination {
+            uri: uri.parse().unwrap(),
+        };
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(connector.connect(dst)).expect("connect");
+        let events = events.lock().unwrap();
+        events.clone()
+    }
+
+    #[test]
+    fn test_connect_callback_events() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+
+        // Nothing is resolved for an IP address.
+        assert_eq!(events(HttpConnector::new(1), format!("http://{}", addr)), vec![
+            format!("AttemptStarted {{ addr: {:?} }}", addr),
+            format!("connected {}", addr),
+        ]);
+
+        let connector = HttpConnector::new_with_resolver(StaticResolver(addr), None);
+        assert_eq!(events(connector, format!("http://observed.test:{}", addr.port())), vec![
+            "ResolveStarted { host: \"observed.test\" }".to_owned(),
+            "ResolveCompleted { count: 1 }".to_owned(),
+            format!("AttemptStarted {{ addr: {:?} }}", addr),
+            format!("connected {}", addr),
+        ]);
+    }
+}
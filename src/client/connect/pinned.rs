@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use futures::{Async, Future, Poll};
+use http::Uri;
+
+use super::{into_io_error, normalize_host, Connect, Connected, Destination};
+
+/// A connector that keeps connecting to the address it first connected to.
+///
+/// The first connect to a host goes through the inner connector as usual,
+/// and the remote address it reports is pinned. Later connects to the same
+/// scheme, host and port are made to that address instead, so the
+/// hostname isn't resolved again. If connecting to a pinned address
+/// fails, the pin is dropped, and the host is connected to from scratch
+/// and pinned again.
+///
+/// Pins are shared between clones of a `PinnedConnector`. Since a pinned
+/// connect asks the inner connector for an IP address rather than the
+/// hostname, the inner connector should only connect over TCP, without
+/// TLS.
+#[derive(Clone, Debug)]
+pub struct PinnedConnector<C> {
+    inner: C,
+    pins: Arc<Mutex<HashMap<String, SocketAddr>>>,
+}
+
+impl<C> PinnedConnector<C> {
+    /// Construct a new `PinnedConnector` wrapping `inner`.
+    pub fn new(inner: C) -> PinnedConnector<C> {
+        PinnedConnector {
+            inner: inner,
+            pins: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Get the address pinned for `dst`, if any.
+    pub fn pinned(&self, dst: &Destination) -> Option<SocketAddr> {
+        self.pins.lock().unwrap().get(&pin_key(dst)).cloned()
+    }
+
+    /// Forget every pinned address, so each host is connected to from
+    /// scratch on its next connect.
+    pub fn clear_pins(&self) {
+        self.pins.lock().unwrap().clear();
+    }
+
+    /// Get a reference to the inner connector.
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the inner connector.
+    pub fn inner_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+}
+
+fn pin_key(dst: &Destination) -> String {
+    let host = normalize_host(dst.host());
+    match dst.port() {
+        Some(port) => format!("{}://{}:{}", dst.scheme(), host, port),
+        None => format!("{}://{}", dst.scheme(), host),
+    }
+}
+
+fn pinned_uri(scheme: &str, addr: &SocketAddr) -> Option<Uri> {
+    format!("{}://{}", scheme, addr).parse().ok()
+}
+
+impl<C> Connect for PinnedConnector<C>
+where
+    C: Connect + Clone,
+{
+    type Transport = C::Transport;
+    type Error = io::Error;
+    type Future = PinnedConnecting<C>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        // IP addresses don't need pinning.
+        if dst.host().trim_matches(|c| c == '[' || c == ']').parse::<IpAddr>().is_ok() {
+            return PinnedConnecting {
+                connector: self.clone(),
+                key: None,
+                state: State::Connecting(self.inner.connect(dst)),
+                uri: None,
+            };
+        }
+
+        let key = pin_key(&dst);
+        let pinned = self.pins.lock().unwrap().get(&key).cloned();
+        let state = match pinned.and_then(|addr| pinned_uri(dst.scheme(), &addr).map(|uri| (addr, uri))) {
+            Some((addr, uri)) => {
+                trace!("connecting to pinned address {} for {}", addr, key);
+                State::Pinned(self.inner.connect(Destination { uri: uri }), addr)
+            },
+            None => State::Connecting(self.inner.connect(Destination { uri: dst.uri.clone() })),
+        };
+        PinnedConnecting {
+            connector: self.clone(),
+            key: Some(key),
+            state: state,
+            uri: Some(dst.uri),
+        }
+    }
+}
+
+/// A Future representing work to connect, to a pinned address if any.
+#[must_use = "futures do nothing unless polled"]
+pub struct PinnedConnecting<C: Connect> {
+    connector: PinnedConnector<C>,
+    key: Option<String>,
+    state: State<C::Future>,
+    uri: Option<Uri>,
+}
+
+enum State<F> {
+    Pinned(F, SocketAddr),
+    Connecting(F),
+}
+
+impl<C: Connect> Future for PinnedConnecting<C> {
+    type Item = (C::Transport, Connected);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let state = match self.state {
+            State::Pinned(ref mut fut, addr) => match fut.poll().map_err(into_io_error) {
+                Ok(ready) => return Ok(ready),
+                Err(err) => {
+                    debug!("connecting to pinned address {} failed, connecting again: {}", addr, err);
+                    let key = self.key.as_ref().expect("pinned connect has a key");
+                    let mut pins = self.connector.pins.lock().unwrap();
+                    if pins.get(key) == Some(&addr) {
+                        pins.remove(key);
+                    }
+                    let dst = Destination {
+                        uri: self.uri.take().expect("pinned connect has a uri"),
+                    };
+                    State::Connecting(self.connector.inner.connect(dst))
+                },
+            },
+            State::Connecting(ref mut fut) => {
+                let (transport, connected) = try_ready!(fut.poll().map_err(into_io_error));
+                if let (Some(key), Some(addr)) = (self.key.take(), connected.remote_addr) {
+                    self.connector.pins.lock().unwrap().insert(key, addr);
+                }
+                return Ok(Async::Ready((transport, connected)));
+            },
+        };
+        self.state = state;
+        self.poll()
+    }
+}
+
+impl<C: Connect> fmt::Debug for PinnedConnecting<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("PinnedConnecting")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::{SocketAddr, TcpListener};
+    use std::sync::{Arc, Mutex};
+
+    use futures::future::{self, FutureResult};
+    use tokio::runtime::current_thread::Runtime;
+
+    use super::{Connect, Destination, PinnedConnector};
+    use super::super::HttpConnector;
+    use super::super::dns::{IpAddrs, Name, Resolve};
+
+    // Resolves every host to `addr`, recording each lookup.
+    #[derive(Clone)]
+    struct SwitchResolver {
+        addr: Arc<Mutex<SocketAddr>>,
+        lookups: Arc<Mutex<usize>>,
+    }
+
+    impl Resolve for SwitchResolver {
+        type Future = FutureResult<IpAddrs, io::Error>;
+
+        fn resolve(&self, _name: Name) -> Self::Future {
+            *self.lookups.lock().unwrap() += 1;
+            future::ok(IpAddrs::new(vec![*self.addr.lock().unwrap()]))
+        }
+    }
+
+    fn connector(addr: SocketAddr) -> (PinnedConnector<HttpConnector<SwitchResolver>>, SwitchResolver) {
+        let resolver = SwitchResolver {
+            addr: Arc::new(Mutex::new(addr)),
+            lookups: Arc::new(Mutex::new(0)),
+        };
+        (PinnedConnector::new(HttpConnector::new_with_resolver(resolver.clone(), None)), resolver)
+    }
+
+    fn dst() -> Destination {
+        Destination {
+            uri: "http://pinned.test".parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_pinned_connect_skips_resolver() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let (connector, resolver) = connector(addr);
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(connector.connect(dst())).expect("first connect");
+        assert_eq!(connector.pinned(&dst()), Some(addr));
+
+        let (sock, _) = rt.block_on(connector.clone().connect(dst())).expect("pinned connect");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        assert_eq!(*resolver.lookups.lock().unwrap(), 1);
+
+        connector.clear_pins();
+        assert_eq!(connector.pinned(&dst()), None);
+        rt.block_on(connector.connect(dst())).expect("connect after clear");
+        assert_eq!(*resolver.lookups.lock().unwrap(), 2);
+    }
+
+    #[test]
+    fn test_pinned_connect_failure_repins() {
+        let first = TcpListener::bind("127.0.0.1:0").unwrap();
+        let (connector, resolver) = connector(first.local_addr().unwrap());
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(connector.connect(dst())).expect("first connect");
+        drop(first);
+
+        let second = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = second.local_addr().unwrap();
+        *resolver.addr.lock().unwrap() = addr;
+        let (sock, _) = rt.block_on(connector.connect(dst())).expect("connect after pin failed");
+        assert_eq!(sock.peer_addr().unwrap(), addr);
+        assert_eq!(connector.pinned(&dst()), Some(addr));
+        assert_eq!(*resolver.lookups.lock().unwrap(), 2);
+    }
+}
@@ -0,0 +1,339 @@
+use std::cmp;
+use std::error::Error as StdError;
+use std::fmt;
+use std::io;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::{Future, Poll};
+use http::Uri;
+use tokio_timer::Delay;
+
+use super::{into_io_error, Connect, Connected, Destination};
+use super::dns::ShuffleRng;
+
+/// A connector that retries another connector when it fails.
+///
+/// Each retry calls the inner connector again from the start, so a
+/// hostname is resolved again too. Retries wait with an exponential
+/// backoff on the runtime's timer, without blocking a thread.
+#[derive(Clone, Debug)]
+pub struct RetryConnector<C> {
+    base_delay: Duration,
+    inner: C,
+    jitter: Option<Arc<Mutex<ShuffleRng>>>,
+    max_retries: usize,
+    retry_on: Vec<io::ErrorKind>,
+}
+
+impl<C> RetryConnector<C> {
+    /// Construct a new `RetryConnector` wrapping `inner`.
+    pub fn new(inner: C) -> RetryConnector<C> {
+        RetryConnector {
+            base_delay: Duration::from_millis(100),
+            inner: inner,
+            jitter: None,
+            max_retries: 3,
+            retry_on: vec![
+                io::ErrorKind::ConnectionRefused,
+                io::ErrorKind::ConnectionReset,
+                io::ErrorKind::TimedOut,
+            ],
+        }
+    }
+
+    /// Set how many times a failed connect is retried.
+    ///
+    /// Default is `3`.
+    #[inline]
+    pub fn set_max_retries(&mut self, retries: usize) {
+        self.max_retries = retries;
+    }
+
+    /// Set the delay before the first retry.
+    ///
+    /// The delay doubles with each retry after that.
+    ///
+    /// Default is 100 milliseconds.
+    #[inline]
+    pub fn set_base_delay(&mut self, delay: Duration) {
+        self.base_delay = delay;
+    }
+
+    /// Set whether each retry delay is randomized.
+    ///
+    /// If enabled, each delay is a random duration between half of and the
+    /// full backoff, so that many clients don't all retry at once.
+    ///
+    /// Default is `false`.
+    #[inline]
+    pub fn set_jitter(&mut self, jitter: bool) {
+        self.jitter = if jitter {
+            Some(Arc::new(Mutex::new(ShuffleRng::from_time())))
+        } else {
+            None
+        };
+    }
+
+    /// Set which kinds of errors are retried.
+    ///
+    /// Any other error, such as an invalid URL, fails right away.
+    ///
+    /// Default is `ConnectionRefused`, `ConnectionReset` and `TimedOut`.
+    #[inline]
+    pub fn set_retry_on(&mut self, kinds: Vec<io::ErrorKind>) {
+        self.retry_on = kinds;
+    }
+
+    /// Get a reference to the inner connector.
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the inner connector.
+    pub fn inner_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+
+    fn delay(&self, retries: usize) -> Duration {
+        let delay = backoff(self.base_delay, retries);
+        match self.jitter {
+            Some(ref rng) => {
+                let permille = rng.lock().unwrap().next_below(501) as u32;
+                delay / 2 + delay * permille / 1000
+            },
+            None => delay,
+        }
+    }
+}
+
+impl<C> Connect for RetryConnector<C>
+where
+    C: Connect + Clone,
+{
+    type Transport = C::Transport;
+    type Error = io::Error;
+    type Future = RetryConnecting<C>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let uri = dst.uri.clone();
+        RetryConnecting {
+            connector: self.clone(),
+            retries: 0,
+            state: State::Connecting(self.inner.connect(dst)),
+            uri: uri,
+        }
+    }
+}
+
+/// A Future representing work to connect, retrying on failure.
+#[must_use = "futures do nothing unless polled"]
+pub struct RetryConnecting<C: Connect> {
+    connector: RetryConnector<C>,
+    retries: usize,
+    state: State<C::Future>,
+    uri: Uri,
+}
+
+enum State<F> {
+    Connecting(F),
+    Waiting(Delay),
+}
+
+impl<C: Connect> Future for RetryConnecting<C> {
+    type Item = (C::Transport, Connected);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        loop {
+            let state;
+            match self.state {
+                State::Connecting(ref mut fut) => match fut.poll().map_err(into_io_error) {
+                    Ok(ready) => return Ok(ready),
+                    Err(err) => {
+                        if !self.connector.retry_on.contains(&err.kind()) {
+                            return Err(err);
+                        }
+                        if self.retries == self.connector.max_retries {
+                            if self.retries == 0 {
+                                return Err(err);
+                            }
+                            return Err(attempts_error(err, self.retries + 1));
+                        }
+                        self.retries += 1;
+                        let delay = self.connector.delay(self.retries);
+                        debug!("connect error, retrying in {:?}: {}", delay, err);
+                        state = State::Waiting(Delay::new(Instant::now() + delay));
+                    },
+                },
+                State::Waiting(ref mut delay) => {
+                    try_ready!(delay.poll().map_err(|e| io::Error::new(io::ErrorKind::Other, e)));
+                    let dst = Destination {
+                        uri: self.uri.clone(),
+                    };
+                    state = State::Connecting(self.connector.inner.connect(dst));
+                },
+            }
+            self.state = state;
+        }
+    }
+}
+
+impl<C: Connect> fmt::Debug for RetryConnecting<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("RetryConnecting")
+    }
+}
+
+// Exponential backoff: `base` before the first retry, then twice that,
+// and so on.
+pub(super) fn backoff(base: Duration, retries: usize) -> Duration {
+    let shift = cmp::min(retries.saturating_sub(1), 16) as u32;
+    base.checked_mul(1 << shift).unwrap_or(base)
+}
+
+// The last error of a connect that was retried, of the same kind, and
+// saying how many attempts were made.
+pub(super) fn attempts_error(last: io::Error, attempts: usize) -> io::Error {
+    io::Error::new(last.kind(), AttemptsError {
+        attempts: attempts,
+        last: last,
+    })
+}
+
+#[derive(Debug)]
+struct AttemptsError {
+    attempts: usize,
+    last: io::Error,
+}
+
+impl fmt::Display for AttemptsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} (after {} attempts)", self.last, self.attempts)
+    }
+}
+
+impl StdError for AttemptsError {
+    fn description(&self) -> &str {
+        "connect failed after retrying"
+    }
+
+    fn cause(&self) -> Option<&StdError> {
+        Some(&self.last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::TcpListener;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    use futures::future::{self, Either, FutureResult};
+    use tokio::net::TcpStream;
+    use tokio::runtime::current_thread::Runtime;
+
+    use super::{Connect, Connected, Destination, RetryConnector};
+    use super::super::HttpConnector;
+
+    // Refuses the first `fails` connects, then connects for real.
+    #[derive(Clone)]
+    struct FlakyConnector {
+        calls: Arc<AtomicUsize>,
+        error: io::ErrorKind,
+        fails: usize,
+        inner: HttpConnector,
+    }
+
+    impl FlakyConnector {
+        fn new(fails: usize, error: io::ErrorKind) -> FlakyConnector {
+            FlakyConnector {
+                calls: Arc::new(AtomicUsize::new(0)),
+                error: error,
+                fails: fails,
+                inner: HttpConnector::new(1),
+            }
+        }
+    }
+
+    impl Connect for FlakyConnector {
+        type Transport = TcpStream;
+        type Error = io::Error;
+        type Future = Either<FutureResult<(TcpStream, Connected), io::Error>, <HttpConnector as Connect>::Future>;
+
+        fn connect(&self, dst: Destination) -> Self::Future {
+            if self.calls.fetch_add(1, Ordering::SeqCst) < self.fails {
+                Either::A(future::err(io::Error::new(self.error, "flaky")))
+            } else {
+                Either::B(self.inner.connect(dst))
+            }
+        }
+    }
+
+    fn dst(listener: &TcpListener) -> Destination {
+        Destination {
+            uri: format!("http://{}", listener.local_addr().unwrap()).parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_retry_until_connected() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let inner = FlakyConnector::new(2, io::ErrorKind::ConnectionRefused);
+        let calls = inner.calls.clone();
+        let mut connector = RetryConnector::new(inner);
+        connector.set_base_delay(Duration::from_millis(10));
+        connector.set_jitter(true);
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(connector.connect(dst(&server))).expect("connect");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_retry_exhausted_reports_attempts() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let inner = FlakyConnector::new(10, io::ErrorKind::ConnectionRefused);
+        let calls = inner.calls.clone();
+        let mut connector = RetryConnector::new(inner);
+        connector.set_base_delay(Duration::from_millis(1));
+        connector.set_max_retries(2);
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(dst(&server))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        assert!(err.to_string().ends_with("(after 3 attempts)"), "{}", err);
+        let last = err.get_ref().and_then(|e| e.cause()).expect("last error");
+        assert_eq!(last.to_string(), "flaky");
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_retry_fails_fast_on_other_errors() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let inner = FlakyConnector::new(1, io::ErrorKind::InvalidInput);
+        let calls = inner.calls.clone();
+        let connector = RetryConnector::new(inner);
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(dst(&server))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_retry_delay_backoff() {
+        let mut connector = RetryConnector::new(());
+        connector.set_base_delay(Duration::from_millis(100));
+        assert_eq!(connector.delay(1), Duration::from_millis(100));
+        assert_eq!(connector.delay(3), Duration::from_millis(400));
+
+        connector.set_jitter(true);
+        for _ in 0..100 {
+            let delay = connector.delay(3);
+            assert!(delay >= Duration::from_millis(200) && delay <= Duration::from_millis(400), "{:?}", delay);
+        }
+    }
+}
@@ -0,0 +1,322 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::IpAddr;
+use std::sync::Arc;
+
+use futures::{Future, Poll};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use super::{into_io_error, normalize_host, unbracket, Connect, Connected, Destination};
+
+/// A connector that picks another connector by the destination host.
+///
+/// Routes are checked in the order they were added, and the first whose
+/// pattern matches the host connects; if none does, the default connector
+/// connects. Since the connectors may be of different types, their
+/// futures and transports are boxed.
+///
+/// Build one with [`RouterConnector::builder`](RouterConnector::builder).
+#[derive(Clone)]
+pub struct RouterConnector {
+    default: Arc<BoxConnect>,
+    routes: Vec<Route>,
+}
+
+/// A builder of a [`RouterConnector`](RouterConnector).
+#[derive(Clone)]
+pub struct RouterConnectorBuilder {
+    default: Arc<BoxConnect>,
+    routes: Vec<Route>,
+}
+
+#[derive(Clone)]
+struct Route {
+    connector: Arc<BoxConnect>,
+    pattern: Pattern,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Pattern {
+    Any,
+    Exact(String),
+    Ip(IpAddr),
+    Subdomains(String),
+}
+
+// A `Connect` with its associated types boxed, so it can be a trait object.
+trait BoxConnect: Send + Sync {
+    fn connect(&self, dst: Destination) -> RouterConnecting;
+}
+
+impl<C> BoxConnect for C
+where
+    C: Connect,
+    C::Error: 'static,
+    C::Future: 'static,
+{
+    fn connect(&self, dst: Destination) -> RouterConnecting {
+        let fut = Connect::connect(self, dst)
+            .map(|(io, connected)| (RouterStream(Box::new(io)), connected))
+            .map_err(into_io_error);
+        RouterConnecting {
+            fut: Box::new(fut),
+        }
+    }
+}
+
+impl RouterConnector {
+    /// Start building a `RouterConnector`, with `default` connecting
+    /// hosts no route matches.
+    pub fn builder<C>(default: C) -> RouterConnectorBuilder
+    where
+        C: Connect + 'static,
+        C::Error: 'static,
+        C::Future: 'static,
+    {
+        RouterConnectorBuilder {
+            default: Arc::new(default),
+            routes: Vec::new(),
+        }
+    }
+
+    fn route(&self, host: &str) -> &BoxConnect {
+        self.routes
+            .iter()
+            .find(|route| route.pattern.matches(host))
+            .map(|route| &*route.connector)
+            .unwrap_or(&*self.default)
+    }
+}
+
+impl RouterConnectorBuilder {
+    /// Add a route, connecting hosts matching `pattern` with `connector`.
+    ///
+    /// A pattern is either an exact hostname or IP address, such as
+    /// `api.example.com` or `10.0.0.1`, or a leading wildcard such as
+    /// `*.internal.corp`, matching any subdomain of `internal.corp` but
+    /// not `internal.corp` itself. A pattern of `*` matches every host.
+    /// Hostnames are compared ignoring case and a trailing dot.
+    pub fn route<C>(&mut self, pattern: &str, connector: C) -> &mut Self
+    where
+        C: Connect + 'static,
+        C::Error: 'static,
+        C::Future: 'static,
+    {
+        self.routes.push(Route {
+            connector: Arc::new(connector),
+            pattern: Pattern::parse(pattern),
+        });
+        self
+    }
+
+    /// Build a `RouterConnector` with the routes added so far.
+    pub fn build(&self) -> RouterConnector {
+        RouterConnector {
+            default: self.default.clone(),
+            routes: self.routes.clone(),
+        }
+    }
+}
+
+impl Pattern {
+    fn parse(pattern: &str) -> Pattern {
+        let pattern = pattern.trim();
+        if pattern == "*" {
+            return Pattern::Any;
+        }
+        if let Ok(ip) = unbracket(pattern).parse() {
+            return Pattern::Ip(ip);
+        }
+        let pattern = normalize_host(pattern);
+        if pattern.starts_with("*.") {
+            Pattern::Subdomains(pattern[1..].to_owned())
+        } else {
+            Pattern::Exact(pattern)
+        }
+    }
+
+    // `host` is as given by `Uri::host()`.
+    fn matches(&self, host: &str) -> bool {
+        match *self {
+            Pattern::Any => true,
+            Pattern::Ip(ip) => unbracket(host).parse::<IpAddr>().ok() == Some(ip),
+            Pattern::Exact(ref name) => normalize_host(host) == *name,
+            // The suffix keeps its leading dot, so only subdomains match.
+            Pattern::Subdomains(ref suffix) => normalize_host(host).ends_with(suffix.as_str()),
+        }
+    }
+}
+
+impl fmt::Debug for RouterConnector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RouterConnector")
+            .field("routes", &self.routes.iter().map(|route| &route.pattern).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl fmt::Debug for RouterConnectorBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("RouterConnectorBuilder")
+            .field("routes", &self.routes.iter().map(|route| &route.pattern).collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl Connect for RouterConnector {
+    type Transport = RouterStream;
+    type Error = io::Error;
+    type Future = RouterConnecting;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        trace!("Router::connect; host={}", dst.host());
+        self.route(dst.host()).connect(dst)
+    }
+}
+
+/// A Future representing work to connect with the connector a
+/// `RouterConnector` picked.
+#[must_use = "futures do nothing unless polled"]
+pub struct RouterConnecting {
+    fut: Box<Future<Item=(RouterStream, Connected), Error=io::Error> + Send>,
+}
+
+impl Future for RouterConnecting {
+    type Item = (RouterStream, Connected);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.fut.poll()
+    }
+}
+
+impl fmt::Debug for RouterConnecting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("RouterConnecting")
+    }
+}
+
+trait Io: AsyncRead + AsyncWrite + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Send> Io for T {}
+
+/// The transport of a `RouterConnector`, boxing that of the connector
+/// picked.
+pub struct RouterStream(Box<Io>);
+
+impl Read for RouterStream {
+    #[inline]
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for RouterStream {
+    #[inline]
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    #[inline]
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl AsyncRead for RouterStream {
+    #[inline]
+    unsafe fn prepare_uninitialized_buffer(&self, buf: &mut [u8]) -> bool {
+        self.0.prepare_uninitialized_buffer(buf)
+    }
+}
+
+impl AsyncWrite for RouterStream {
+    #[inline]
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        self.0.shutdown()
+    }
+}
+
+impl fmt::Debug for RouterStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("RouterStream")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net::TcpListener;
+
+    use tokio::runtime::current_thread::Runtime;
+
+    use super::{Connect, Destination, Pattern, RouterConnector};
+    use super::super::{FailConnector, HttpConnector};
+
+    fn dst(s: &str) -> Destination {
+        Destination {
+            uri: s.parse().unwrap(),
+        }
+    }
+
+    fn fail(message: &str) -> FailConnector {
+        FailConnector::new(io::ErrorKind::Other, message)
+    }
+
+    // The message of the connector a host is routed to.
+    fn routed(router: &RouterConnector, uri: &str) -> String {
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(router.connect(dst(uri))).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn test_pattern_matches() {
+        let subdomains = Pattern::parse("*.internal.corp");
+        assert!(subdomains.matches("a.internal.corp"));
+        assert!(subdomains.matches("a.b.Internal.Corp."));
+        assert!(!subdomains.matches("internal.corp"));
+        assert!(!subdomains.matches("notinternal.corp"));
+
+        let exact = Pattern::parse("API.example.com.");
+        assert!(exact.matches("api.example.com"));
+        assert!(!exact.matches("www.api.example.com"));
+
+        assert_eq!(Pattern::parse("10.0.0.1"), Pattern::Ip("10.0.0.1".parse().unwrap()));
+        assert!(Pattern::parse("10.0.0.1").matches("10.0.0.1"));
+        assert!(!Pattern::parse("10.0.0.1").matches("10.0.0.10"));
+        assert!(Pattern::parse("::1").matches("[::1]"));
+        assert!(Pattern::parse("[0:0::1]").matches("[::1]"));
+        assert!(!Pattern::parse("::1").matches("localhost"));
+
+        assert!(Pattern::parse("*").matches("anything.test"));
+    }
+
+    #[test]
+    fn test_router_first_match_wins() {
+        let router = RouterConnector::builder(fail("default"))
+            .route("api.internal.corp", fail("exact"))
+            .route("*.internal.corp", fail("wildcard"))
+            .route("*.api.internal.corp", fail("shadowed"))
+            .route("10.0.0.1", fail("ip"))
+            .build();
+
+        assert_eq!(routed(&router, "https://api.internal.corp"), "exact");
+        assert_eq!(routed(&router, "https://db.internal.corp"), "wildcard");
+        assert_eq!(routed(&router, "https://v1.api.internal.corp"), "wildcard");
+        assert_eq!(routed(&router, "http://10.0.0.1:8080"), "ip");
+        assert_eq!(routed(&router, "http://example.com"), "default");
+    }
+
+    #[test]
+    fn test_router_connects() {
+        let server = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        let router = RouterConnector::builder(fail("default"))
+            .route("127.0.0.1", HttpConnector::new(1))
+            .build();
+
+        let mut rt = Runtime::new().unwrap();
+        let (_io, connected) = rt.block_on(router.connect(dst(&format!("http://{}", addr)))).expect("connect");
+        assert_eq!(connected.remote_addr, Some(addr));
+    }
+}
@@ -0,0 +1,208 @@
+//! Socket options not exposed by `net2`.
+use std::io;
+use std::time::Duration;
+
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub(super) fn bind_device<S: ::std::os::unix::io::AsRawFd>(sock: &S, iface: &str) -> io::Result<()> {
+    use std::ffi::CString;
+    use libc;
+
+    let name = CString::new(iface).map_err(|_| {
+        io::Error::new(io::ErrorKind::InvalidInput, "interface name contains a nul byte")
+    })?;
+    // The kernel would silently truncate a longer name, which could bind
+    // to a different interface than the one asked for.
+    if name.as_bytes_with_nul().len() > libc::IFNAMSIZ {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "interface name is too long"));
+    }
+    let len = name.as_bytes_with_nul().len() as libc::socklen_t;
+    // Safety: `name` is a valid nul-terminated buffer of `len` bytes that
+    // outlives the call.
+    let ret = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_BINDTODEVICE,
+            name.as_ptr() as *const libc::c_void,
+            len,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub(super) fn bind_device<S>(_sock: &S, _iface: &str) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "binding to an interface is only supported on Linux",
+    ))
+}
+
+#[cfg(unix)]
+fn set_int<S: ::std::os::unix::io::AsRawFd>(sock: &S, level: libc::c_int, opt: libc::c_int, val: libc::c_int) -> io::Result<()> {
+    use std::mem;
+    use libc;
+
+    // Safety: `val` is a c_int that outlives the call.
+    let ret = unsafe {
+        libc::setsockopt(
+            sock.as_raw_fd(),
+            level,
+            opt,
+            &val as *const libc::c_int as *const libc::c_void,
+            mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if ret == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Sets `TCP_KEEPINTVL`, the time between keepalive probes.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub(super) fn set_keepalive_interval<S: ::std::os::unix::io::AsRawFd>(sock: &S, dur: Duration) -> io::Result<()> {
+    use std::cmp;
+    use libc;
+
+    // The option is in whole seconds, and 0 is rejected.
+    let secs = cmp::max(cmp::min(dur.as_secs(), libc::c_int::max_value() as u64), 1);
+    set_int(sock, libc::IPPROTO_TCP, libc::TCP_KEEPINTVL, secs as libc::c_int)
+}
+
+/// Sets `TCP_KEEPCNT`, the number of unanswered probes before the
+/// connection is dropped.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub(super) fn set_keepalive_retries<S: ::std::os::unix::io::AsRawFd>(sock: &S, retries: u32) -> io::Result<()> {
+    use std::cmp;
+    use libc;
+
+    let retries = cmp::min(retries, libc::c_int::max_value() as u32);
+    set_int(sock, libc::IPPROTO_TCP, libc::TCP_KEEPCNT, retries as libc::c_int)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub(super) fn set_keepalive_interval<S>(_sock: &S, _dur: Duration) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "setting the keepalive interval is only supported on Linux",
+    ))
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub(super) fn set_keepalive_retries<S>(_sock: &S, _retries: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "setting the keepalive retries is only supported on Linux",
+    ))
+}
+
+/// Sets `TCP_USER_TIMEOUT`, how long sent data may stay unacknowledged
+/// before the connection is dropped.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub(super) fn set_user_timeout<S: ::std::os::unix::io::AsRawFd>(sock: &S, dur: Duration) -> io::Result<()> {
+    use std::cmp;
+    use libc;
+
+    // The option is in milliseconds, where 0 is the system default.
+    let millis = dur.as_secs()
+        .saturating_mul(1000)
+        .saturating_add(dur.subsec_nanos() as u64 / 1_000_000);
+    let millis = cmp::max(cmp::min(millis, libc::c_int::max_value() as u64), 1);
+    set_int(sock, libc::IPPROTO_TCP, libc::TCP_USER_TIMEOUT, millis as libc::c_int)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub(super) fn set_user_timeout<S>(_sock: &S, _dur: Duration) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "setting the TCP user timeout is only supported on Linux",
+    ))
+}
+
+/// Sets `TCP_FASTOPEN_CONNECT`, so the first data written is sent in the
+/// SYN when the kernel has a cookie for the server.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub(super) fn set_fastopen_connect<S: ::std::os::unix::io::AsRawFd>(sock: &S) -> io::Result<()> {
+    set_int(sock, libc::IPPROTO_TCP, libc::TCP_FASTOPEN_CONNECT, 1)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub(super) fn set_fastopen_connect<S>(_sock: &S) -> io::Result<()> {
+    debug!("TCP Fast Open is only supported on Linux, ignoring");
+    Ok(())
+}
+
+/// Sets `SO_REUSEPORT`, so several sockets may bind the same address and
+/// port.
+#[cfg(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd",
+))]
+pub(super) fn set_reuse_port<S: ::std::os::unix::io::AsRawFd>(sock: &S) -> io::Result<()> {
+    set_int(sock, libc::SOL_SOCKET, libc::SO_REUSEPORT, 1)
+}
+
+#[cfg(not(any(
+    target_os = "android",
+    target_os = "dragonfly",
+    target_os = "freebsd",
+    target_os = "ios",
+    target_os = "linux",
+    target_os = "macos",
+    target_os = "netbsd",
+    target_os = "openbsd",
+)))]
+pub(super) fn set_reuse_port<S>(_sock: &S) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "SO_REUSEPORT is only supported on Linux and BSDs",
+    ))
+}
+
+/// Sets `IP_TOS`, or `IPV6_TCLASS` for an IPv6 socket, which marks the
+/// packets sent with a DSCP and ECN value.
+#[cfg(unix)]
+pub(super) fn set_tos<S: ::std::os::unix::io::AsRawFd>(sock: &S, is_ipv6: bool, tos: u32) -> io::Result<()> {
+    use libc;
+
+    if tos > 255 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "IP_TOS must be at most 255"));
+    }
+    if is_ipv6 {
+        set_int(sock, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, tos as libc::c_int)
+    } else {
+        set_int(sock, libc::IPPROTO_IP, libc::IP_TOS, tos as libc::c_int)
+    }
+}
+
+#[cfg(not(unix))]
+pub(super) fn set_tos<S>(_sock: &S, _is_ipv6: bool, _tos: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "setting IP_TOS is only supported on Unix",
+    ))
+}
+
+/// Sets `SO_MARK`, used by policy routing. This needs `CAP_NET_ADMIN`.
+#[cfg(any(target_os = "android", target_os = "linux"))]
+pub(super) fn set_mark<S: ::std::os::unix::io::AsRawFd>(sock: &S, mark: u32) -> io::Result<()> {
+    set_int(sock, libc::SOL_SOCKET, libc::SO_MARK, mark as libc::c_int)
+}
+
+#[cfg(not(any(target_os = "android", target_os = "linux")))]
+pub(super) fn set_mark<S>(_sock: &S, _mark: u32) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "setting SO_MARK is only supported on Linux",
+    ))
+}
@@ -0,0 +1,521 @@
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+
+use futures::{future, Future, Poll};
+use http::uri::{Scheme, Uri};
+use tokio_io::io::{read_exact, write_all};
+use tokio_io::{AsyncRead, AsyncWrite};
+
+use super::{into_io_error, unbracket, Connect, Connected, Destination, HttpConnector};
+use super::dns::{GaiResolver, Name, Resolve};
+
+const VERSION: u8 = 5;
+const METHOD_NONE: u8 = 0x00;
+const METHOD_PASSWORD: u8 = 0x02;
+const METHOD_UNACCEPTABLE: u8 = 0xff;
+const CMD_CONNECT: u8 = 0x01;
+const ATYP_V4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_V6: u8 = 0x04;
+
+/// A connector that tunnels connections through a SOCKS5 proxy.
+///
+/// The proxy is connected to with the inner connector, and then asked to
+/// connect to the destination, as described in [RFC 1928][].
+///
+/// The proxy may be given as a `socks5://` or `socks5h://` URI, defaulting
+/// to port 1080, which is connected to as plain `http` by the inner
+/// connector. As with curl, hostnames are resolved locally for `socks5://`,
+/// and sent to the proxy to resolve for `socks5h://`, or any other URI.
+///
+/// [RFC 1928]: https://tools.ietf.org/html/rfc1928
+pub struct Socks5Connector<C = HttpConnector, R = GaiResolver> {
+    credentials: Option<(String, String)>,
+    inner: C,
+    proxy: Uri,
+    resolve_locally: bool,
+    resolver: Arc<Mutex<LazyResolver<R>>>,
+}
+
+// A resolver, or how to make one the first time it's needed.
+enum LazyResolver<R> {
+    Made(R),
+    Unmade(fn() -> R),
+}
+
+impl<R: Resolve> LazyResolver<R> {
+    fn resolve(&mut self, name: Name) -> R::Future {
+        if let LazyResolver::Unmade(make) = *self {
+            *self = LazyResolver::Made(make());
+        }
+        match *self {
+            LazyResolver::Made(ref resolver) => resolver.resolve(name),
+            LazyResolver::Unmade(_) => unreachable!("the resolver was just made"),
+        }
+    }
+}
+
+impl<C> Socks5Connector<C> {
+    /// Construct a new `Socks5Connector`, connecting to `proxy` using the
+    /// `inner` connector.
+    ///
+    /// A single thread is used to resolve hostnames, started the first
+    /// time one is resolved locally.
+    pub fn new(inner: C, proxy: Uri) -> Socks5Connector<C> {
+        Socks5Connector::with_lazy_resolver(inner, proxy, LazyResolver::Unmade(|| GaiResolver::new(1)))
+    }
+}
+
+impl<C, R> Socks5Connector<C, R> {
+    /// Construct a new `Socks5Connector`, with a resolver to use when
+    /// hostnames are resolved locally.
+    pub fn new_with_resolver(inner: C, proxy: Uri, resolver: R) -> Socks5Connector<C, R> {
+        Socks5Connector::with_lazy_resolver(inner, proxy, LazyResolver::Made(resolver))
+    }
+
+    fn with_lazy_resolver(inner: C, proxy: Uri, resolver: LazyResolver<R>) -> Socks5Connector<C, R> {
+        let resolve_locally = proxy.scheme_part().map(|s| s.as_str()) == Some("socks5");
+        Socks5Connector {
+            credentials: None,
+            inner: inner,
+            proxy: proxy_uri(proxy),
+            resolve_locally: resolve_locally,
+            resolver: Arc::new(Mutex::new(resolver)),
+        }
+    }
+
+    /// Set a username and password to authenticate with, as described in
+    /// [RFC 1929][].
+    ///
+    /// Without credentials, only unauthenticated access is offered to
+    /// the proxy.
+    ///
+    /// [RFC 1929]: https://tools.ietf.org/html/rfc1929
+    #[inline]
+    pub fn set_credentials(&mut self, username: String, password: String) {
+        self.credentials = Some((username, password));
+    }
+
+    /// Set whether hostnames are resolved locally, instead of by the proxy.
+    ///
+    /// Default is `true` for a `socks5://` proxy, and `false` otherwise.
+    #[inline]
+    pub fn set_resolve_locally(&mut self, resolve_locally: bool) {
+        self.resolve_locally = resolve_locally;
+    }
+}
+
+impl<C: Clone, R> Clone for Socks5Connector<C, R> {
+    fn clone(&self) -> Socks5Connector<C, R> {
+        Socks5Connector {
+            credentials: self.credentials.clone(),
+            inner: self.inner.clone(),
+            proxy: self.proxy.clone(),
+            resolve_locally: self.resolve_locally,
+            resolver: self.resolver.clone(),
+        }
+    }
+}
+
+impl<C: fmt::Debug, R> fmt::Debug for Socks5Connector<C, R> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Socks5Connector")
+            .field("inner", &self.inner)
+            .field("proxy", &self.proxy)
+            .field("resolve_locally", &self.resolve_locally)
+            .finish()
+    }
+}
+
+const DEFAULT_PORT: u16 = 1080;
+
+// Inner connectors only know how to reach `http` URIs, so a `socks5` URI
+// is turned into one with the same host, and the default port if missing.
+fn proxy_uri(proxy: Uri) -> Uri {
+    match proxy.scheme_part().map(|s| s.as_str()) {
+        Some("socks5") | Some("socks5h") => (),
+        _ => return proxy,
+    }
+    let host = match proxy.host() {
+        Some(host) => host,
+        None => return proxy,
+    };
+    let uri = format!("http://{}:{}", host, proxy.port().unwrap_or(DEFAULT_PORT));
+    uri.parse().unwrap_or(proxy)
+}
+
+type Handshake<T> = Box<Future<Item=T, Error=io::Error> + Send>;
+
+// Where the proxy is asked to connect to.
+enum Target {
+    Addr(SocketAddr),
+    Domain(String, u16),
+}
+
+impl<C, R> Connect for Socks5Connector<C, R>
+where
+    C: Connect,
+    C::Error: 'static,
+    C::Future: 'static,
+    R: Resolve + Send + Sync + 'static,
+    R::Future: Send + 'static,
+{
+    type Transport = C::Transport;
+    type Error = io::Error;
+    type Future = Socks5Connecting<C::Transport>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let host = unbracket(dst.host()).to_owned();
+        if host.is_empty() {
+            return Socks5Connecting::error(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid URL, missing domain",
+            ));
+        }
+        let port = match dst.port() {
+            Some(port) => port,
+            None => if dst.uri.scheme_part() == Some(&Scheme::HTTPS) { 443 } else { 80 },
+        };
+        trace!("Socks5::connect; host={}, port={}", host, port);
+
+        let target: Handshake<Target> = match host.parse::<IpAddr>() {
+            Ok(ip) => Box::new(future::ok(Target::Addr(SocketAddr::new(ip, port)))),
+            Err(_) if self.resolve_locally => {
+                Box::new(self.resolver.lock().unwrap().resolve(Name::new(host, port)).and_then(|mut addrs| {
+                    addrs.next().map(Target::Addr).ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::NotFound, "hostname resolved to no addresses")
+                    })
+                }))
+            },
+            Err(_) => Box::new(future::ok(Target::Domain(host, port))),
+        };
+
+        let proxy = Destination {
+            uri: self.proxy.clone(),
+        };
+        let credentials = self.credentials.clone();
+        let fut = self.inner.connect(proxy)
+            .map_err(into_io_error)
+            .join(target)
+            .and_then(move |((io, connected), target)| {
+                handshake(io, target, credentials).map(move |io| (io, connected))
+            });
+
+        Socks5Connecting {
+            fut: Box::new(fut),
+        }
+    }
+}
+
+fn handshake<T>(io: T, target: Target, credentials: Option<(String, String)>)
+    -> Handshake<T>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    let greeting = if credentials.is_some() {
+        vec![VERSION, 2, METHOD_NONE, METHOD_PASSWORD]
+    } else {
+        vec![VERSION, 1, METHOD_NONE]
+    };
+    let request = match connect_request(&target) {
+        Ok(request) => request,
+        Err(e) => return Box::new(future::err(e)),
+    };
+
+    let fut = write_all(io, greeting)
+        .and_then(|(io, _)| read_exact(io, [0; 2]))
+        .and_then(move |(io, reply)| -> Handshake<T> {
+            if reply[0] != VERSION {
+                return Box::new(future::err(invalid_data("invalid SOCKS version from proxy")));
+            }
+            match (reply[1], credentials) {
+                (METHOD_NONE, _) => Box::new(future::ok(io)),
+                (METHOD_PASSWORD, Some((username, password))) => Box::new(authenticate(io, &username, &password)),
+                (METHOD_UNACCEPTABLE, _) => Box::new(future::err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "SOCKS5 proxy accepted none of the authentication methods",
+                ))),
+                _ => Box::new(future::err(invalid_data("SOCKS5 proxy chose an unoffered authentication method"))),
+            }
+        })
+        .and_then(move |io| write_all(io, request))
+        .and_then(|(io, _)| read_exact(io, [0; 4]))
+        .and_then(|(io, reply)| {
+            if reply[0] != VERSION {
+                return Err(invalid_data("invalid SOCKS version from proxy"));
+            }
+            if reply[1] != 0 {
+                return Err(reply_error(reply[1]));
+            }
+            // The bound address isn't needed, but has to be read past.
+            let len = match reply[3] {
+                ATYP_V4 => 4 + 2,
+                ATYP_V6 => 16 + 2,
+                ATYP_DOMAIN => return Ok((io, None)),
+                _ => return Err(invalid_data("invalid address type from SOCKS5 proxy")),
+            };
+            Ok((io, Some(len)))
+        })
+        .and_then(|(io, len)| -> Handshake<T> {
+            match len {
+                Some(len) => skip(io, len),
+                None => Box::new(read_exact(io, [0; 1]).and_then(|(io, len)| skip(io, len[0] as usize + 2))),
+            }
+        });
+    Box::new(fut)
+}
+
+fn authenticate<T>(io: T, username: &str, password: &str) -> Handshake<T>
+where
+    T: AsyncRead + AsyncWrite + Send + 'static,
+{
+    if username.len() > 255 || password.len() > 255 {
+        return Box::new(future::err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "SOCKS5 username and password must be at most 255 bytes",
+        )));
+    }
+    let mut request = vec![1, username.len() as u8];
+    request.extend_from_slice(username.as_bytes());
+    request.push(password.len() as u8);
+    request.extend_from_slice(password.as_bytes());
+
+    Box::new(write_all(io, request)
+        .and_then(|(io, _)| read_exact(io, [0; 2]))
+        .and_then(|(io, reply)| {
+            // The version of the username and password negotiation.
+            if reply[0] != 1 {
+                return Err(invalid_data("invalid SOCKS5 authentication version from proxy"));
+            }
+            if reply[1] != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "SOCKS5 proxy rejected the username and password",
+                ));
+            }
+            Ok(io)
+        }))
+}
+
+fn connect_request(target: &Target) -> io::Result<Vec<u8>> {
+    let mut request = vec![VERSION, CMD_CONNECT, 0];
+    let port = match *target {
+        Target::Addr(SocketAddr::V4(ref addr)) => {
+            request.push(ATYP_V4);
+            request.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        },
+        Target::Addr(SocketAddr::V6(ref addr)) => {
+            request.push(ATYP_V6);
+            request.extend_from_slice(&addr.ip().octets());
+            addr.port()
+        },
+        Target::Domain(ref host, port) => {
+            if host.len() > 255 {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "hostname is too long for SOCKS5"));
+            }
+            request.push(ATYP_DOMAIN);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+            port
+        },
+    };
+    request.push((port >> 8) as u8);
+    request.push(port as u8);
+    Ok(request)
+}
+
+fn skip<T: AsyncRead + Send + 'static>(io: T, len: usize) -> Handshake<T> {
+    Box::new(read_exact(io, vec![0; len]).map(|(io, _)| io))
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn reply_error(code: u8) -> io::Error {
+    let (kind, msg) = match code {
+        0x01 => (io::ErrorKind::Other, "general SOCKS server failure"),
+        0x02 => (io::ErrorKind::PermissionDenied, "connection not allowed by ruleset"),
+        0x03 => (io::ErrorKind::Other, "network unreachable"),
+        0x04 => (io::ErrorKind::Other, "host unreachable"),
+        0x05 => (io::ErrorKind::ConnectionRefused, "connection refused"),
+        0x06 => (io::ErrorKind::TimedOut, "TTL expired"),
+        0x07 => (io::ErrorKind::Other, "command not supported"),
+        0x08 => (io::ErrorKind::Other, "address type not supported"),
+        _ => (io::ErrorKind::Other, "unknown error"),
+    };
+    io::Error::new(kind, format!("SOCKS5 proxy: {} ({:#04x})", msg, code))
+}
+
+/// A Future representing work to connect to a destination through a
+/// SOCKS5 proxy.
+#[must_use = "futures do nothing unless polled"]
+pub struct Socks5Connecting<T> {
+    fut: Box<Future<Item=(T, Connected), Error=io::Error> + Send>,
+}
+
+impl<T: Send + 'static> Socks5Connecting<T> {
+    fn error(err: io::Error) -> Socks5Connecting<T> {
+        Socks5Connecting {
+            fut: Box::new(future::err(err)),
+        }
+    }
+}
+
+impl<T> Future for Socks5Connecting<T> {
+    type Item = (T, Connected);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        self.fut.poll()
+    }
+}
+
+impl<T> fmt::Debug for Socks5Connecting<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("Socks5Connecting")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::net::{SocketAddr, TcpListener};
+    use std::thread;
+
+    use futures::future::{self, FutureResult};
+    use tokio::runtime::current_thread::Runtime;
+    use tokio_io::io::read_exact;
+
+    use super::{proxy_uri, Connect, Destination, HttpConnector, LazyResolver, Socks5Connector};
+    use super::super::dns::{IpAddrs, Name, Resolve};
+
+    // Expects each request in turn, writing back its reply.
+    fn socks_server(script: Vec<(&'static [u8], &'static [u8])>) -> (Socks5Connector, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy = format!("socks5h://{}", listener.local_addr().unwrap()).parse().unwrap();
+        let server = thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            for (request, reply) in script {
+                let mut buf = vec![0; request.len()];
+                sock.read_exact(&mut buf).unwrap();
+                assert_eq!(buf, request);
+                sock.write_all(reply).unwrap();
+            }
+        });
+        (Socks5Connector::new(HttpConnector::new(1), proxy), server)
+    }
+
+    fn dst(s: &str) -> Destination {
+        Destination {
+            uri: s.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_proxy_uri() {
+        let uri = |s: &str| proxy_uri(s.parse().unwrap()).to_string();
+        assert_eq!(uri("socks5://proxy.test"), "http://proxy.test:1080/");
+        assert_eq!(uri("socks5h://[::1]:9050"), "http://[::1]:9050/");
+        assert_eq!(uri("http://proxy.test:3128"), "http://proxy.test:3128/");
+    }
+
+    #[test]
+    fn test_scheme_sets_resolve_locally() {
+        let connector = |s: &str| Socks5Connector::new((), s.parse().unwrap());
+        assert!(connector("socks5://proxy.test").resolve_locally);
+        assert!(!connector("socks5h://proxy.test").resolve_locally);
+        assert!(!connector("http://proxy.test:1080").resolve_locally);
+    }
+
+    #[test]
+    fn test_connect_by_hostname() {
+        let (connector, server) = socks_server(vec![
+            (&[5, 1, 0], &[5, 0]),
+            (b"\x05\x01\x00\x03\x0cexample.test\x00\x50", b"\x05\x00\x00\x01\x7f\x00\x00\x01\x00\x50ok"),
+        ]);
+
+        let mut rt = Runtime::new().unwrap();
+        let (io, _) = rt.block_on(connector.connect(dst("http://example.test/"))).expect("connect");
+        let (_, buf) = rt.block_on(read_exact(io, [0; 2])).unwrap();
+        assert_eq!(&buf, b"ok");
+        server.join().unwrap();
+
+        // Without resolving locally, no resolver thread is started.
+        match *connector.resolver.lock().unwrap() {
+            LazyResolver::Unmade(_) => (),
+            LazyResolver::Made(_) => panic!("the resolver was made"),
+        };
+    }
+
+    #[test]
+    fn test_connect_by_address() {
+        let (connector, server) = socks_server(vec![
+            (&[5, 1, 0], &[5, 0]),
+            (&[5, 1, 0, 1, 10, 1, 2, 3, 0x1f, 0x90], b"\x05\x00\x00\x03\x04host\x00\x50"),
+        ]);
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(connector.connect(dst("http://10.1.2.3:8080/"))).expect("connect");
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_password_auth_and_refused() {
+        let (mut connector, server) = socks_server(vec![
+            (&[5, 2, 0, 2], &[5, 2]),
+            (b"\x01\x04user\x04pass", &[1, 0]),
+            (b"\x05\x01\x00\x03\x0cexample.test\x01\xbb", &[5, 5, 0, 1, 0, 0, 0, 0, 0, 0]),
+        ]);
+        connector.set_credentials("user".to_owned(), "pass".to_owned());
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(dst("https://example.test/"))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+        assert!(err.to_string().contains("connection refused"), "{}", err);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn test_password_auth_checks_version() {
+        let (mut connector, server) = socks_server(vec![
+            (&[5, 2, 0, 2], &[5, 2]),
+            (b"\x01\x04user\x04pass", &[5, 0]),
+        ]);
+        connector.set_credentials("user".to_owned(), "pass".to_owned());
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(dst("http://example.test/"))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        server.join().unwrap();
+    }
+
+    struct StaticResolver(SocketAddr);
+
+    impl Resolve for StaticResolver {
+        type Future = FutureResult<IpAddrs, io::Error>;
+
+        fn resolve(&self, name: Name) -> Self::Future {
+            assert_eq!(name.as_str(), "example.test");
+            future::ok(IpAddrs::new(vec![SocketAddr::new(self.0.ip(), name.port())]))
+        }
+    }
+
+    #[test]
+    fn test_resolve_locally() {
+        let (connector, server) = socks_server(vec![
+            (&[5, 1, 0], &[5, 0]),
+            (&[5, 1, 0, 1, 192, 0, 2, 7, 0, 80], &[5, 0, 0, 1, 0, 0, 0, 0, 0, 0]),
+        ]);
+        let resolver = StaticResolver(([192, 0, 2, 7], 0).into());
+        let mut connector = Socks5Connector::new_with_resolver(connector.inner, connector.proxy, resolver);
+        connector.set_resolve_locally(true);
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(connector.connect(dst("http://example.test/"))).expect("connect");
+        server.join().unwrap();
+    }
+}
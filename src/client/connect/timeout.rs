@@ -0,0 +1,164 @@
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use tokio_timer::Delay;
+
+use super::{into_io_error, Connect, Connected, Destination};
+
+/// A connector that bounds how long another connector may take.
+///
+/// This applies a single deadline to everything the inner connector does,
+/// such as resolving, connecting and a TLS handshake.
+#[derive(Clone, Debug)]
+pub struct TimeoutConnector<C> {
+    connect_timeout: Option<Duration>,
+    inner: C,
+}
+
+impl<C> TimeoutConnector<C> {
+    /// Construct a new `TimeoutConnector` wrapping `inner`.
+    pub fn new(inner: C) -> TimeoutConnector<C> {
+        TimeoutConnector {
+            connect_timeout: None,
+            inner: inner,
+        }
+    }
+
+    /// Set the timeout for the inner connector to connect.
+    ///
+    /// When it takes longer, the inner connect is dropped, and the connect
+    /// fails with `io::ErrorKind::TimedOut`.
+    ///
+    /// Default is `None`.
+    #[inline]
+    pub fn set_connect_timeout(&mut self, dur: Option<Duration>) {
+        self.connect_timeout = dur;
+    }
+
+    /// Get a reference to the inner connector.
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    /// Get a mutable reference to the inner connector.
+    pub fn inner_mut(&mut self) -> &mut C {
+        &mut self.inner
+    }
+}
+
+impl<C: Connect> Connect for TimeoutConnector<C> {
+    type Transport = C::Transport;
+    type Error = io::Error;
+    type Future = TimeoutConnecting<C>;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        TimeoutConnecting {
+            future: self.inner.connect(dst),
+            timeout: self.connect_timeout.map(|dur| Delay::new(Instant::now() + dur)),
+        }
+    }
+}
+
+/// A Future representing work to connect, bounded by a timeout.
+#[must_use = "futures do nothing unless polled"]
+pub struct TimeoutConnecting<C: Connect> {
+    future: C::Future,
+    timeout: Option<Delay>,
+}
+
+impl<C: Connect> Future for TimeoutConnecting<C> {
+    type Item = (C::Transport, Connected);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        if let Async::Ready(ok) = self.future.poll().map_err(into_io_error)? {
+            return Ok(Async::Ready(ok));
+        }
+
+        if let Some(ref mut timeout) = self.timeout {
+            match timeout.poll() {
+                Ok(Async::Ready(())) => {
+                    return Err(io::Error::new(io::ErrorKind::TimedOut, "connect timed out"));
+                },
+                Ok(Async::NotReady) => (),
+                Err(e) => return Err(io::Error::new(io::ErrorKind::Other, e)),
+            }
+        }
+        Ok(Async::NotReady)
+    }
+}
+
+impl<C: Connect> fmt::Debug for TimeoutConnecting<C> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("TimeoutConnecting")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use futures::future;
+    use tokio::runtime::current_thread::Runtime;
+
+    use mock::Duplex;
+    use super::{Connect, Connected, Destination, TimeoutConnector};
+    use super::super::HttpConnector;
+    use super::super::dns::{IpAddrs, Name, Resolve};
+    use super::super::tests::discard_addr;
+
+    struct NeverConnector;
+
+    impl Connect for NeverConnector {
+        type Transport = Duplex;
+        type Error = io::Error;
+        type Future = future::Empty<(Duplex, Connected), io::Error>;
+
+        fn connect(&self, _dst: Destination) -> Self::Future {
+            future::empty()
+        }
+    }
+
+    fn dst(s: &str) -> Destination {
+        Destination {
+            uri: s.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_timeout_never_connecting() {
+        let mut connector = TimeoutConnector::new(NeverConnector);
+        connector.set_connect_timeout(Some(Duration::from_millis(50)));
+
+        let mut rt = Runtime::new().unwrap();
+        let err = match rt.block_on(connector.connect(dst("http://mock.local"))) {
+            Ok(_) => panic!("never connector connected"),
+            Err(e) => e,
+        };
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+
+    struct BlackholeResolver;
+
+    impl Resolve for BlackholeResolver {
+        type Future = future::FutureResult<IpAddrs, io::Error>;
+
+        fn resolve(&self, name: Name) -> Self::Future {
+            future::ok(IpAddrs::new(vec![discard_addr(1, name.port())]))
+        }
+    }
+
+    #[test]
+    fn test_timeout_http_connector() {
+        let inner = HttpConnector::new_with_resolver(BlackholeResolver, None);
+        let mut connector = TimeoutConnector::new(inner);
+        connector.set_connect_timeout(Some(Duration::from_millis(50)));
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(connector.connect(dst("http://blackhole.test"))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    }
+}
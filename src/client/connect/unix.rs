@@ -0,0 +1,249 @@
+use std::error::Error as StdError;
+use std::ffi::OsStr;
+use std::fmt;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::path::PathBuf;
+
+use futures::{Async, Future, Poll};
+use tokio_uds::{ConnectFuture, UnixStream};
+
+use super::{Connect, Connected, Destination};
+
+/// A connector for Unix domain sockets.
+///
+/// The socket path is taken from the host of a `unix` (or `http+unix`) URI. Since a host
+/// can't contain slashes or percent-encoding, the path bytes must be
+/// hex-encoded, such as
+/// `unix://2f7661722f72756e2f646f636b65722e736f636b/containers/json`
+/// for `/var/run/docker.sock`.
+///
+/// Options that only apply to TCP, like keep-alive, have no equivalent.
+#[derive(Clone, Debug, Default)]
+pub struct UnixConnector {
+    _priv: (),
+}
+
+impl UnixConnector {
+    /// Construct a new `UnixConnector`.
+    pub fn new() -> UnixConnector {
+        UnixConnector {
+            _priv: (),
+        }
+    }
+}
+
+impl Connect for UnixConnector {
+    type Transport = UnixStream;
+    type Error = io::Error;
+    type Future = UnixConnecting;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        trace!("Unix::connect; scheme={}, host={}", dst.scheme(), dst.host());
+
+        if dst.scheme() != "unix" && dst.scheme() != "http+unix" {
+            return invalid_url(InvalidUrl::NotUnix);
+        }
+
+        let path = match dst.uri.host() {
+            Some(host) if !host.is_empty() => match hex_decode(host) {
+                Some(path) => path,
+                None => return invalid_url(InvalidUrl::BadPath),
+            },
+            _ => return invalid_url(InvalidUrl::MissingPath),
+        };
+
+        debug!("connecting to {:?}", path);
+        UnixConnecting {
+            state: State::Connecting(UnixStream::connect(path)),
+        }
+    }
+}
+
+fn invalid_url(err: InvalidUrl) -> UnixConnecting {
+    UnixConnecting {
+        state: State::Error(Some(io::Error::new(io::ErrorKind::InvalidInput, err))),
+    }
+}
+
+// Decodes pairs of hex digits into the raw bytes of a path.
+fn hex_decode(s: &str) -> Option<PathBuf> {
+    fn hex(b: u8) -> Option<u8> {
+        (b as char).to_digit(16).map(|d| d as u8)
+    }
+
+    let bytes = s.as_bytes();
+    if bytes.len() % 2 != 0 {
+        return None;
+    }
+    let mut path = Vec::with_capacity(bytes.len() / 2);
+    for pair in bytes.chunks(2) {
+        match (hex(pair[0]), hex(pair[1])) {
+            (Some(high), Some(low)) => path.push(high << 4 | low),
+            _ => return None,
+        }
+    }
+    Some(PathBuf::from(OsStr::from_bytes(&path)))
+}
+
+#[derive(Debug, Clone, Copy)]
+enum InvalidUrl {
+    NotUnix,
+    MissingPath,
+    BadPath,
+}
+
+impl InvalidUrl {
+    fn as_str(&self) -> &'static str {
+        match *self {
+            InvalidUrl::NotUnix => "invalid URL, scheme must be unix or http+unix",
+            InvalidUrl::MissingPath => "invalid URL, missing socket path",
+            InvalidUrl::BadPath => "invalid URL, socket path is not hex-encoded",
+        }
+    }
+}
+
+impl fmt::Display for InvalidUrl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl StdError for InvalidUrl {
+    fn description(&self) -> &str {
+        self.as_str()
+    }
+}
+
+/// A Future representing work to connect to a Unix domain socket.
+#[must_use = "futures do nothing unless polled"]
+pub struct UnixConnecting {
+    state: State,
+}
+
+enum State {
+    Connecting(ConnectFuture),
+    Error(Option<io::Error>),
+}
+
+impl Future for UnixConnecting {
+    type Item = (UnixStream, Connected);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        match self.state {
+            State::Connecting(ref mut fut) => {
+                let sock = try_ready!(fut.poll());
+                Ok(Async::Ready((sock, Connected::new())))
+            },
+            State::Error(ref mut e) => Err(e.take().expect("polled more than once")),
+        }
+    }
+}
+
+impl fmt::Debug for UnixConnecting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("UnixConnecting")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::io::{self, Read, Write};
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::thread;
+
+    use futures::{Future, Stream};
+    use libc;
+    use tokio::runtime::current_thread::Runtime;
+    use tokio_io::io::{read_exact, write_all};
+
+    use client::Client;
+    use super::{hex_decode, Connect, Destination, UnixConnector};
+
+    fn socket_path(name: &str) -> PathBuf {
+        let pid = unsafe { libc::getpid() };
+        let path = env::temp_dir().join(format!("hyper-{}-{}.sock", name, pid));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn encode(path: &PathBuf) -> String {
+        path.to_str().unwrap().bytes().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    #[test]
+    fn test_hex_decode() {
+        assert_eq!(hex_decode("2f7661722F612e736f636b"), Some(PathBuf::from("/var/a.sock")));
+        assert_eq!(hex_decode("2f7"), None);
+        assert_eq!(hex_decode("zz"), None);
+    }
+
+    #[test]
+    fn test_errors_missing_path() {
+        let dst = Destination {
+            uri: "/containers/json".parse().unwrap(),
+        };
+        let err = UnixConnector::new().connect(dst).wait().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let dst = Destination {
+            uri: "unix://zz/containers/json".parse().unwrap(),
+        };
+        let err = UnixConnector::new().connect(dst).wait().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_connect_round_trip() {
+        let path = socket_path("connect");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut buf = [0; 1];
+            sock.read_exact(&mut buf).unwrap();
+            sock.write_all(&buf).unwrap();
+        });
+
+        let dst = Destination {
+            uri: format!("http+unix://{}/", encode(&path)).parse().unwrap(),
+        };
+        let mut rt = Runtime::new().unwrap();
+        let (io, _) = rt.block_on(UnixConnector::new().connect(dst)).expect("connect");
+        let (io, _) = rt.block_on(write_all(io, [42])).unwrap();
+        let (_, buf) = rt.block_on(read_exact(io, [0; 1])).unwrap();
+        assert_eq!(buf, [42]);
+
+        server.join().unwrap();
+        let _ = fs::remove_file(&path);
+    }
+
+    #[test]
+    fn test_client_round_trip() {
+        let path = socket_path("round-trip");
+        let listener = UnixListener::bind(&path).unwrap();
+        let server = thread::spawn(move || {
+            let (mut sock, _) = listener.accept().unwrap();
+            let mut buf = [0; 4096];
+            let n = sock.read(&mut buf).unwrap();
+            assert!(buf[..n].starts_with(b"GET /containers/json HTTP/1.1\r\n"));
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello").unwrap();
+        });
+
+        let client = Client::builder().build::<_, ::Body>(UnixConnector::new());
+        let uri = format!("unix://{}/containers/json", encode(&path)).parse().unwrap();
+
+        let mut rt = Runtime::new().unwrap();
+        let body = rt.block_on(client.get(uri).and_then(|res| {
+            assert_eq!(res.status(), 200);
+            res.into_body().concat2()
+        })).expect("request");
+        assert_eq!(&body[..], b"hello");
+
+        server.join().unwrap();
+        let _ = fs::remove_file(&path);
+    }
+}
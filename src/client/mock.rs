@@ -0,0 +1,415 @@
+//! A mock connector, for testing code that uses a `Client`.
+//!
+//! This module is only available with the `testing` feature.
+//!
+//! A [`MockConnector`](MockConnector) doesn't open any sockets. Instead,
+//! each connect is answered with a [`MockStream`](MockStream) registered
+//! ahead of time for that URI, which replies with canned bytes once the
+//! client has written its request.
+//!
+//! ```
+//! # extern crate hyper;
+//! # extern crate tokio;
+//! # fn main() {
+//! use hyper::Client;
+//! use hyper::client::mock::MockConnector;
+//! use tokio::runtime::current_thread::Runtime;
+//!
+//! let connector = MockConnector::new();
+//! let sock = connector.mock(
+//!     "http://mock.local",
+//!     "HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n",
+//! );
+//! let client = Client::builder().build::<_, hyper::Body>(connector.clone());
+//!
+//! let mut rt = Runtime::new().unwrap();
+//! let res = rt.block_on(client.get("http://mock.local/a".parse().unwrap())).unwrap();
+//! assert_eq!(res.status(), 200);
+//! assert!(sock.written().starts_with(b"GET /a HTTP/1.1\r\n"));
+//! assert_eq!(connector.connects(), vec!["http://mock.local"]);
+//! # }
+//! ```
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use futures::{Async, Future, Poll};
+use futures::task::{self, Task};
+use tokio_io::{AsyncRead, AsyncWrite};
+use tokio_timer::Delay;
+
+use super::connect::{Connect, Connected, Destination};
+
+/// A connector that connects to registered in-memory streams.
+///
+/// Streams are registered per URI, in the form `scheme://host[:port]`, and
+/// each connect to that URI takes the next one registered. A connect to a
+/// URI with no streams left fails with `io::ErrorKind::ConnectionRefused`.
+///
+/// Cloning a `MockConnector` shares its streams and its record of connects,
+/// so a test can keep a clone after giving one to a `Client`.
+#[derive(Clone)]
+pub struct MockConnector {
+    inner: Arc<Mutex<Inner>>,
+}
+
+struct Inner {
+    connects: Vec<String>,
+    fail_nth: Option<(usize, io::ErrorKind)>,
+    mocks: HashMap<String, VecDeque<Mock>>,
+}
+
+struct Mock {
+    stream: Option<MockStream>,
+    wait: Option<Duration>,
+}
+
+impl MockConnector {
+    /// Construct a new `MockConnector`, with no streams registered.
+    pub fn new() -> MockConnector {
+        MockConnector {
+            inner: Arc::new(Mutex::new(Inner {
+                connects: Vec::new(),
+                fail_nth: None,
+                mocks: HashMap::new(),
+            })),
+        }
+    }
+
+    /// Register a stream for a connect to `uri`, replying with `response`.
+    ///
+    /// The returned `MockHandle` can inspect what the client wrote, and
+    /// queue more bytes to reply with.
+    pub fn mock<B: Into<Vec<u8>>>(&self, uri: &str, response: B) -> MockHandle {
+        self.register(uri, None, response.into())
+    }
+
+    /// Register a stream for a connect to `uri` that only connects after
+    /// `delay`.
+    ///
+    /// The delay waits on the runtime's timer.
+    pub fn mock_delayed<B: Into<Vec<u8>>>(&self, uri: &str, delay: Duration, response: B) -> MockHandle {
+        self.register(uri, Some(delay), response.into())
+    }
+
+    /// Register a connect to `uri` that never finishes.
+    ///
+    /// This is useful to test connect timeouts.
+    pub fn mock_never(&self, uri: &str) {
+        self.inner.lock().unwrap().mocks
+            .entry(uri.to_owned())
+            .or_insert_with(VecDeque::new)
+            .push_back(Mock {
+                stream: None,
+                wait: None,
+            });
+    }
+
+    /// Set that the `n`th connect, counting from 1, fails with `kind`.
+    ///
+    /// Connects are counted across all URIs, and the failed connect doesn't
+    /// take a registered stream.
+    pub fn fail_nth(&self, n: usize, kind: io::ErrorKind) {
+        self.inner.lock().unwrap().fail_nth = Some((n, kind));
+    }
+
+    /// Get the URIs that were connected to so far, in order.
+    ///
+    /// This includes failed connects.
+    pub fn connects(&self) -> Vec<String> {
+        self.inner.lock().unwrap().connects.clone()
+    }
+
+    fn register(&self, uri: &str, wait: Option<Duration>, response: Vec<u8>) -> MockHandle {
+        let stream = MockStream::new(response);
+        let handle = MockHandle {
+            inner: stream.inner.clone(),
+        };
+        self.inner.lock().unwrap().mocks
+            .entry(uri.to_owned())
+            .or_insert_with(VecDeque::new)
+            .push_back(Mock {
+                stream: Some(stream),
+                wait: wait,
+            });
+        handle
+    }
+}
+
+impl fmt::Debug for MockConnector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let inner = self.inner.lock().unwrap();
+        f.debug_struct("MockConnector")
+            .field("connects", &inner.connects)
+            .field("fail_nth", &inner.fail_nth)
+            .finish()
+    }
+}
+
+impl Connect for MockConnector {
+    type Transport = MockStream;
+    type Error = io::Error;
+    type Future = MockConnecting;
+
+    fn connect(&self, dst: Destination) -> Self::Future {
+        let key = match dst.port() {
+            Some(port) => format!("{}://{}:{}", dst.scheme(), dst.host(), port),
+            None => format!("{}://{}", dst.scheme(), dst.host()),
+        };
+        trace!("mock connect: {}", key);
+
+        let mut inner = self.inner.lock().unwrap();
+        inner.connects.push(key.clone());
+        let nth = inner.connects.len();
+
+        let state = match inner.fail_nth {
+            Some((n, kind)) if n == nth => {
+                State::Ready(Some(Err(io::Error::new(kind, "mock connect failed"))))
+            },
+            _ => match inner.mocks.get_mut(&key).and_then(|mocks| mocks.pop_front()) {
+                Some(Mock { stream: None, .. }) => State::Never,
+                Some(Mock { stream: Some(stream), wait: Some(delay) }) => {
+                    State::Delayed(Delay::new(Instant::now() + delay), Some(stream))
+                },
+                Some(Mock { stream: Some(stream), wait: None }) => State::Ready(Some(Ok(stream))),
+                None => State::Ready(Some(Err(io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    format!("no mock registered for {}", key),
+                )))),
+            },
+        };
+        MockConnecting {
+            state: state,
+        }
+    }
+}
+
+/// A Future representing a connect to a `MockConnector`.
+#[must_use = "futures do nothing unless polled"]
+pub struct MockConnecting {
+    state: State,
+}
+
+enum State {
+    Ready(Option<io::Result<MockStream>>),
+    Delayed(Delay, Option<MockStream>),
+    Never,
+}
+
+impl Future for MockConnecting {
+    type Item = (MockStream, Connected);
+    type Error = io::Error;
+
+    fn poll(&mut self) -> Poll<Self::Item, Self::Error> {
+        let stream = match self.state {
+            State::Ready(ref mut res) => res.take().expect("polled after complete")?,
+            State::Delayed(ref mut delay, ref mut stream) => {
+                try_ready!(delay.poll().map_err(|e| io::Error::new(io::ErrorKind::Other, e)));
+                stream.take().expect("polled after complete")
+            },
+            State::Never => return Ok(Async::NotReady),
+        };
+        Ok(Async::Ready((stream, Connected::new())))
+    }
+}
+
+impl fmt::Debug for MockConnecting {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("MockConnecting")
+    }
+}
+
+/// An in-memory stream returned by a `MockConnector`.
+///
+/// Each reply, starting with the canned response, only becomes readable
+/// when the client writes after the previous reply was read, so that a
+/// reply comes after its request. Once every reply is read, reads wait,
+/// like an idle connection.
+pub struct MockStream {
+    inner: Arc<Mutex<StreamInner>>,
+}
+
+struct StreamInner {
+    read_task: Option<Task>,
+    readable: VecDeque<u8>,
+    replies: VecDeque<Vec<u8>>,
+    written: Vec<u8>,
+}
+
+impl MockStream {
+    fn new(response: Vec<u8>) -> MockStream {
+        MockStream {
+            inner: Arc::new(Mutex::new(StreamInner {
+                read_task: None,
+                readable: VecDeque::new(),
+                replies: vec![response].into(),
+                written: Vec::new(),
+            })),
+        }
+    }
+}
+
+impl fmt::Debug for MockStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("MockStream")
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.readable.is_empty() {
+            inner.read_task = Some(task::current());
+            return Err(io::ErrorKind::WouldBlock.into());
+        }
+        let n = ::std::cmp::min(buf.len(), inner.readable.len());
+        for (dst, src) in buf.iter_mut().zip(inner.readable.drain(..n)) {
+            *dst = src;
+        }
+        Ok(n)
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.written.extend_from_slice(buf);
+        if inner.readable.is_empty() {
+            if let Some(reply) = inner.replies.pop_front() {
+                inner.readable.extend(reply);
+                if let Some(task) = inner.read_task.take() {
+                    task.notify();
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl AsyncRead for MockStream {}
+
+impl AsyncWrite for MockStream {
+    fn shutdown(&mut self) -> Poll<(), io::Error> {
+        Ok(Async::Ready(()))
+    }
+}
+
+/// A handle to a registered `MockStream`.
+#[derive(Clone)]
+pub struct MockHandle {
+    inner: Arc<Mutex<StreamInner>>,
+}
+
+impl MockHandle {
+    /// Get everything the client has written to the stream so far.
+    pub fn written(&self) -> Vec<u8> {
+        self.inner.lock().unwrap().written.clone()
+    }
+
+    /// Queue another reply, for the next request on this connection.
+    pub fn reply<B: Into<Vec<u8>>>(&self, bytes: B) {
+        self.inner.lock().unwrap().replies.push_back(bytes.into());
+    }
+}
+
+impl fmt::Debug for MockHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad("MockHandle")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::time::Duration;
+
+    use tokio::runtime::current_thread::Runtime;
+
+    use super::{Connect, Destination, MockConnector};
+    use super::super::connect::TimeoutConnector;
+
+    fn dst(s: &str) -> Destination {
+        Destination {
+            uri: s.parse().unwrap(),
+        }
+    }
+
+    #[test]
+    fn test_mock_records_connects() {
+        let connector = MockConnector::new();
+        connector.mock("http://mock.local", "");
+        connector.mock("https://mock.local:8443", "");
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(connector.connect(dst("http://mock.local/a"))).expect("first");
+        rt.block_on(connector.connect(dst("https://mock.local:8443/b"))).expect("second");
+        let err = rt.block_on(connector.connect(dst("http://mock.local/c"))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+
+        assert_eq!(connector.connects(), vec![
+            "http://mock.local",
+            "https://mock.local:8443",
+            "http://mock.local",
+        ]);
+    }
+
+    #[test]
+    fn test_mock_fail_nth() {
+        let connector = MockConnector::new();
+        connector.mock("http://mock.local", "");
+        connector.mock("http://mock.local", "");
+        connector.fail_nth(2, io::ErrorKind::ConnectionReset);
+
+        let mut rt = Runtime::new().unwrap();
+        rt.block_on(connector.connect(dst("http://mock.local"))).expect("first");
+        let err = rt.block_on(connector.connect(dst("http://mock.local"))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionReset);
+        rt.block_on(connector.connect(dst("http://mock.local"))).expect("third");
+    }
+
+    #[test]
+    fn test_mock_never_and_delayed() {
+        let connector = MockConnector::new();
+        connector.mock_never("http://mock.local");
+        connector.mock_delayed("http://mock.local", Duration::from_millis(10), "");
+        let mut timeout = TimeoutConnector::new(connector);
+        timeout.set_connect_timeout(Some(Duration::from_millis(50)));
+
+        let mut rt = Runtime::new().unwrap();
+        let err = rt.block_on(timeout.connect(dst("http://mock.local"))).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        rt.block_on(timeout.connect(dst("http://mock.local"))).expect("delayed");
+    }
+
+    #[test]
+    fn test_mock_client_reused_connection() {
+        use futures::Stream;
+        use {Body, Client};
+
+        let connector = MockConnector::new();
+        let sock = connector.mock("http://mock.local", "HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nhi");
+        let client = Client::builder().build::<_, Body>(connector.clone());
+
+        let mut rt = Runtime::new().unwrap();
+        let res = rt.block_on(client.get("http://mock.local/a".parse().unwrap())).expect("first");
+        assert_eq!(res.status(), 200);
+        let body = rt.block_on(res.into_body().concat2()).expect("body");
+        assert_eq!(&body[..], b"hi");
+
+        sock.reply("HTTP/1.1 204 No Content\r\n\r\n");
+        let res = rt.block_on(client.get("http://mock.local/b".parse().unwrap())).expect("second");
+        assert_eq!(res.status(), 204);
+
+        let written = String::from_utf8(sock.written()).unwrap();
+        assert!(written.starts_with("GET /a HTTP/1.1\r\n"), "{:?}", written);
+        assert!(written.contains("GET /b HTTP/1.1\r\n"), "{:?}", written);
+        assert_eq!(connector.connects(), vec!["http://mock.local"]);
+    }
+}
@@ -86,15 +86,18 @@ use http::uri::Scheme;
 
 use body::{Body, Payload};
 use common::Exec;
-use self::connect::{Connect, Destination};
+use self::connect::{Connect, Destination, HttpInfo};
 use self::pool::{Pool, Poolable, Reservation};
 
-#[cfg(feature = "runtime")] pub use self::connect::HttpConnector;
+#[cfg(feature = "runtime")] pub use self::connect::{HttpConnector, RetryConnector, TimeoutConnector};
+#[cfg(feature = "runtime")] pub use self::connect::dns::GaiResolver;
+#[cfg(all(unix, feature = "runtime"))] pub use self::connect::UnixConnector;
 
 pub mod conn;
 pub mod connect;
 pub(crate) mod dispatch;
-#[cfg(feature = "runtime")] mod dns;
+#[cfg(feature = "testing")]
+pub mod mock;
 mod pool;
 #[cfg(test)]
 mod tests;
@@ -280,6 +283,7 @@ where C: Connect + Sync + 'static,
                                 })
                                 .map(move |tx| {
                                     pool.pooled(connecting, PoolClient {
+                                        info: connected.http_info(),
                                         is_proxied: connected.is_proxied,
                                         tx: match ver {
                                             Ver::Http1 => PoolTx::Http1(tx),
@@ -320,7 +324,14 @@ where C: Connect + Sync + 'static,
             if ver == Ver::Http1 {
                 set_relative_uri(req.uri_mut(), pooled.is_proxied);
             }
-            let fut = pooled.send_request_retryable(req);
+            let info = pooled.info;
+            let fut = pooled.send_request_retryable(req)
+                .map(move |mut res| {
+                    if let Some(info) = info {
+                        res.extensions_mut().insert(info);
+                    }
+                    res
+                });
 
             // As of futures@0.1.21, there is a race condition in the mpsc
             // channel, such that sending when the receiver is closing can
@@ -499,6 +510,7 @@ where
 }
 
 struct PoolClient<B> {
+    info: Option<HttpInfo>,
     is_proxied: bool,
     tx: PoolTx<B>,
 }
@@ -559,16 +571,19 @@ where
         match self.tx {
             PoolTx::Http1(tx) => {
                 Reservation::Unique(PoolClient {
+                    info: self.info,
                     is_proxied: self.is_proxied,
                     tx: PoolTx::Http1(tx),
                 })
             },
             PoolTx::Http2(tx) => {
                 let b = PoolClient {
+                    info: self.info,
                     is_proxied: self.is_proxied,
                     tx: PoolTx::Http2(tx.clone()),
                 };
                 let a = PoolClient {
+                    info: self.info,
                     is_proxied: self.is_proxied,
                     tx: PoolTx::Http2(tx),
                 };
@@ -117,3 +117,64 @@ fn conn_reset_after_write() {
         other => panic!("expected Incomplete, found {:?}", other)
     }
 }
+
+#[test]
+fn response_has_http_info() {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use tokio::runtime::current_thread::Runtime;
+    use super::connect::HttpInfo;
+
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || {
+        let (mut sock, _) = server.accept().unwrap();
+        sock.read(&mut [0u8; 512]).unwrap();
+        sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+    });
+
+    let client = Client::new();
+    let uri = format!("http://{}/a", addr).parse().unwrap();
+
+    let mut rt = Runtime::new().unwrap();
+    let res = rt.block_on(client.get(uri)).expect("res");
+    let info = res.extensions().get::<HttpInfo>().expect("HttpInfo");
+    assert_eq!(info.remote_addr(), addr);
+    assert!(!info.is_resolved());
+}
+
+#[test]
+fn reused_connection_has_http_info() {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use futures::Stream;
+    use tokio::runtime::current_thread::Runtime;
+    use super::connect::HttpInfo;
+
+    let server = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = server.local_addr().unwrap();
+    thread::spawn(move || {
+        // Only one connection is accepted, so both requests must use it.
+        let (mut sock, _) = server.accept().unwrap();
+        for _ in 0..2 {
+            sock.read(&mut [0u8; 512]).unwrap();
+            sock.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n").unwrap();
+        }
+    });
+
+    // A hostname, so the address comes from resolving it, rather than
+    // the `localhost` shortcut.
+    let mut connector = super::connect::HttpConnector::new(1);
+    connector.set_resolve_localhost(true);
+    let client = Client::builder().build::<_, ::Body>(connector);
+    let uri = || format!("http://localhost:{}/a", addr.port()).parse().unwrap();
+
+    let mut rt = Runtime::new().unwrap();
+    for _ in 0..2 {
+        let res = rt.block_on(client.get(uri())).expect("res");
+        let info = res.extensions().get::<HttpInfo>().expect("HttpInfo");
+        assert_eq!(info.remote_addr(), addr);
+        assert!(info.is_resolved());
+        rt.block_on(res.into_body().concat2()).expect("body");
+    }
+}
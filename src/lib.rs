@@ -23,6 +23,7 @@ extern crate h2;
 extern crate http;
 extern crate httparse;
 extern crate iovec;
+#[cfg(feature = "runtime")] extern crate libc;
 #[macro_use] extern crate log;
 #[cfg(feature = "runtime")] extern crate net2;
 extern crate time;
@@ -32,6 +33,7 @@ extern crate time;
 #[cfg(feature = "runtime")] extern crate tokio_reactor;
 #[cfg(feature = "runtime")] extern crate tokio_tcp;
 #[cfg(feature = "runtime")] extern crate tokio_timer;
+#[cfg(all(unix, feature = "runtime"))] extern crate tokio_uds;
 extern crate want;
 
 #[cfg(all(test, feature = "nightly"))]